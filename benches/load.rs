@@ -0,0 +1,43 @@
+// load times of a large input read in the usual way and from a memory map, run with
+// cargo bench --features mmap --bench load, without the feature only the usual way is timed
+// the images are made in the temporary folder and removed afterwards
+
+use std::{env, fs, process, time::{Duration, Instant}};
+
+use color_reflect::mapped;
+use image::{ImageFormat, Rgb, RgbImage};
+
+const WIDTH: u32 = 6000;
+const HEIGHT: u32 = 4000;
+// each way is timed this many times and the fastest kept, the first read warms the file cache
+const RUNS: usize = 5;
+
+fn main() {
+    let img = RgbImage::from_fn(WIDTH, HEIGHT, |x, y| Rgb([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8]));
+    let dir = env::temp_dir().join(format!("color_reflect_bench_{}", process::id()));
+    fs::create_dir_all(&dir).expect("Failed to make the temporary folder");
+    for format in [ImageFormat::Bmp, ImageFormat::Png] {
+        let path = dir.join(format!("large.{}", format.extensions_str()[0]));
+        img.save_with_format(&path, format).expect("Failed to save the test image");
+        let path = path.to_string_lossy().into_owned();
+        let read = fastest(|| drop(image::open(&path).unwrap()));
+        print!("{}x{} {}: read {}ms", WIDTH, HEIGHT, format.extensions_str()[0], read.as_millis());
+        if mapped::load(&path).is_some() {
+            let mapped = fastest(|| drop(mapped::load(&path).unwrap().unwrap()));
+            print!(", mapped {}ms", mapped.as_millis());
+        }
+        println!();
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+fn fastest(load: impl Fn()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let timer = Instant::now();
+            load();
+            timer.elapsed()
+        })
+        .min()
+        .unwrap()
+}
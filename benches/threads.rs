@@ -0,0 +1,34 @@
+// the measurement behind PIXELS_PER_THREAD in transform.rs, the cost of starting a thread against
+// that of reflecting a pixel, run with cargo bench --bench threads
+
+use std::{thread, time::{Duration, Instant}};
+
+use color_reflect::transform::{reflect_image, Axis, Operation, Transform};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+// each is timed this many times and the fastest kept
+const RUNS: usize = 20;
+
+fn main() {
+    let start = fastest(|| thread::scope(|scope| drop(scope.spawn(|| {}))));
+    println!("starting and joining a thread: {:?}", start);
+    let transform = Transform::new(Operation::Reflect(Axis::Fixed(40.)));
+    for pixels in [10_000, 50_000, 100_000, 200_000, 400_000] {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(pixels / 100, 100, |x, y| Rgba([(x * 7 % 256) as u8, (y * 13 % 256) as u8, ((x + y) % 256) as u8, 255])));
+        let with_threads = |threads| Transform { threads: Some(threads), ..transform };
+        let one = fastest(|| drop(reflect_image(&img, with_threads(1))));
+        let two = fastest(|| drop(reflect_image(&img, with_threads(2))));
+        println!("{} pixels: 1 thread {:?}, 2 threads {:?}, {:?} a pixel", pixels, one, two, one / pixels);
+    }
+}
+
+fn fastest(work: impl Fn()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let timer = Instant::now();
+            work();
+            timer.elapsed()
+        })
+        .min()
+        .unwrap()
+}
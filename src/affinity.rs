@@ -0,0 +1,64 @@
+// pinning worker threads to cores, with the affinity feature, only a hint, elsewhere and where
+// it isn't supported workers just float
+
+// binds the calling thread to the index-th core it is allowed on, wrapping around, true if that worked
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+pub fn pin_to_core(index: usize) -> bool {
+    let Some(core) = core_for(index) else { return false };
+    // safe, sched_setaffinity only reads the cpu_set_t we pass
+    unsafe {
+        let mut pinned: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut pinned);
+        libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &pinned) == 0
+    }
+}
+
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+pub fn pin_to_core(_index: usize) -> bool {
+    false
+}
+
+// the cores the calling thread may run on, in order
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+fn allowed_cores() -> Vec<usize> {
+    // safe, sched_getaffinity only writes the cpu_set_t we pass
+    unsafe {
+        let mut allowed: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut allowed) != 0 {
+            return vec![];
+        }
+        (0..libc::CPU_SETSIZE as usize).filter(|&cpu| libc::CPU_ISSET(cpu, &allowed)).collect()
+    }
+}
+
+// the core the index-th worker is bound to, so workers up to the number of cores get one each
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+fn core_for(index: usize) -> Option<usize> {
+    let cores = allowed_cores();
+    (!cores.is_empty()).then(|| cores[index % cores.len()])
+}
+
+#[cfg(all(test, feature = "affinity", target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, thread};
+
+    #[test]
+    fn workers_get_distinct_cores() {
+        let cores = allowed_cores();
+        assert!(!cores.is_empty());
+        let requested: Vec<usize> = (0..cores.len()).map(|index| core_for(index).unwrap()).collect();
+        assert_eq!(requested.iter().collect::<HashSet<_>>().len(), cores.len());
+        // more workers than cores wrap around to the first ones again
+        assert_eq!(core_for(cores.len()), Some(cores[0]));
+        // each worker ends up allowed on its own core only
+        thread::scope(|scope| {
+            for (index, &core) in requested.iter().enumerate() {
+                scope.spawn(move || {
+                    assert!(pin_to_core(index));
+                    assert_eq!(allowed_cores(), vec![core]);
+                });
+            }
+        });
+    }
+}
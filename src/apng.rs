@@ -0,0 +1,71 @@
+use std::{fs::File, io::BufReader};
+
+use image::{codecs::png::PngDecoder, AnimationDecoder, DynamicImage, RgbaImage};
+
+// the frames of an animated png, each one already drawn onto the full canvas
+pub struct Animation {
+    // the still image viewers without apng support show, when it isn't also the first frame
+    pub default_image: Option<RgbaImage>,
+    // how long each frame is shown, in seconds as numerator and denominator like the file stores it
+    pub frames: Vec<(RgbaImage, (u16, u16))>,
+    // how many times it plays, 0 loops forever
+    pub plays: u32,
+}
+
+impl Animation {
+    // the same animation with every frame (and the default image) replaced by what change makes of it
+    pub fn map(&self, mut change: impl FnMut(&RgbaImage) -> RgbaImage) -> Animation {
+        Animation {
+            default_image: self.default_image.as_ref().map(&mut change),
+            frames: self.frames.iter().map(|(frame, delay)| (change(frame), *delay)).collect(),
+            plays: self.plays,
+        }
+    }
+}
+
+// None for anything that isn't an animated png, errors for animations that can't be read,
+// such as 16 bit ones, which the image crate doesn't draw frames of
+pub fn read(path: &str) -> Option<Result<Animation, String>> {
+    // the png crate tells how the animation is set up, the image crate draws the frames
+    let reader = png::Decoder::new(BufReader::new(File::open(path).ok()?)).read_info().ok()?;
+    let control = reader.info().animation_control?;
+    // without a frame control before the image data, the default image isn't part of the animation
+    let separate_default = reader.info().frame_control.is_none();
+    drop(reader);
+    Some(read_frames(path, separate_default, control.num_plays))
+}
+
+fn read_frames(path: &str, separate_default: bool, plays: u32) -> Result<Animation, String> {
+    let open = || -> Result<PngDecoder<BufReader<File>>, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        PngDecoder::new(BufReader::new(file)).map_err(|e| e.to_string())
+    };
+    let default_image = match separate_default {
+        true => Some(DynamicImage::from_decoder(open()?).map_err(|e| e.to_string())?.to_rgba8()),
+        false => None,
+    };
+    let mut frames = vec![];
+    for frame in open()?.apng().map_err(|e| e.to_string())?.into_frames() {
+        let frame = frame.map_err(|e| e.to_string())?;
+        let delay = seconds(frame.delay().numer_denom_ms());
+        frames.push((frame.into_buffer(), delay));
+    }
+    Ok(Animation { default_image, frames, plays })
+}
+
+// a delay in milliseconds back to seconds as two 16 bit numbers, the image crate keeps it as a reduced
+// fraction of milliseconds so this gives the file's own numbers (or the same fraction reduced)
+fn seconds((numerator, denominator): (u32, u32)) -> (u16, u16) {
+    let (numerator, denominator) = (numerator as u64, denominator as u64 * 1000);
+    let divisor = gcd(numerator, denominator).max(1);
+    let (numerator, denominator) = (numerator / divisor, denominator / divisor);
+    match (u16::try_from(numerator), u16::try_from(denominator)) {
+        (Ok(numerator), Ok(denominator)) => (numerator, denominator),
+        // can't happen for delays read from a png, rounded to milliseconds just in case
+        _ => ((numerator * 1000 / denominator).min(u16::MAX as u64) as u16, 1000),
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
@@ -0,0 +1,204 @@
+// reading and writing zip archives of images, with the zip feature, elsewhere is_archive is always
+// false and .zip paths are taken as images like any other
+// only what images need is covered, stored and deflated entries without zip64 or encryption
+
+use std::{fs, io::{Read, Write}, path::Path, time::{SystemTime, UNIX_EPOCH}};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression, Crc};
+
+use crate::template::calendar_date;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_DIRECTORY: u32 = 0x06054b50;
+// the end of directory record is 22 bytes and can be followed by a comment of up to 65535
+const MOST_END_BYTES: usize = 22 + 65535;
+// names are utf-8, which is what every name written here is
+const UTF8_NAMES: u16 = 1 << 11;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+#[cfg(feature = "zip")]
+pub fn is_archive(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+#[cfg(not(feature = "zip"))]
+pub fn is_archive(_path: &str) -> bool {
+    false
+}
+
+// a zip file read into memory, with where each file in it is
+pub struct Archive {
+    bytes: Vec<u8>,
+    pub entries: Vec<Entry>,
+}
+
+pub struct Entry {
+    // the path inside the archive, with / between folders
+    pub name: String,
+    method: u16,
+    encrypted: bool,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+    header_offset: usize,
+}
+
+impl Archive {
+    // the files listed in the central directory, folders are left out
+    pub fn open(path: &str) -> Result<Archive, String> {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        let invalid = || format!("{} isn't a zip archive or is damaged", path);
+        let search_from = bytes.len().saturating_sub(MOST_END_BYTES);
+        let end = (search_from..bytes.len().saturating_sub(21)).rev().find(|&i| read_u32(&bytes, i) == Some(END_OF_DIRECTORY)).ok_or_else(invalid)?;
+        let count = read_u16(&bytes, end + 10).ok_or_else(invalid)? as usize;
+        let directory_offset = read_u32(&bytes, end + 16).ok_or_else(invalid)?;
+        if count == 0xFFFF || directory_offset == 0xFFFFFFFF {
+            return Err(format!("{} is a zip64 archive, which isn't supported", path));
+        }
+
+        let mut entries = vec![];
+        let mut offset = directory_offset as usize;
+        for _ in 0..count {
+            if read_u32(&bytes, offset) != Some(CENTRAL_HEADER) {
+                return Err(invalid());
+            }
+            let field16 = |at| read_u16(&bytes, offset + at).ok_or_else(invalid);
+            let field32 = |at| read_u32(&bytes, offset + at).ok_or_else(invalid);
+            let name_length = field16(28)? as usize;
+            let name = bytes.get(offset + 46..offset + 46 + name_length).ok_or_else(invalid)?;
+            let entry = Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: field16(10)?,
+                encrypted: field16(8)? & 1 != 0,
+                crc: field32(16)?,
+                compressed_size: field32(20)? as usize,
+                size: field32(24)? as usize,
+                header_offset: field32(42)? as usize,
+            };
+            offset += 46 + name_length + field16(30)? as usize + field16(32)? as usize;
+            if !entry.name.ends_with('/') {
+                entries.push(entry);
+            }
+        }
+        Ok(Archive { bytes, entries })
+    }
+
+    // the contents of one of the files, checked against the crc the archive has for it
+    pub fn read(&self, entry: &Entry) -> Result<Vec<u8>, String> {
+        let invalid = || format!("{} is damaged in the archive", entry.name);
+        if entry.encrypted {
+            return Err(format!("{} is encrypted", entry.name));
+        }
+        let offset = entry.header_offset;
+        if read_u32(&self.bytes, offset) != Some(LOCAL_HEADER) {
+            return Err(invalid());
+        }
+        // the local header's name and extra field can differ in length from the central directory's
+        let start = offset + 30 + read_u16(&self.bytes, offset + 26).ok_or_else(invalid)? as usize + read_u16(&self.bytes, offset + 28).ok_or_else(invalid)? as usize;
+        let data = self.bytes.get(start..start + entry.compressed_size).ok_or_else(invalid)?;
+        let contents = match entry.method {
+            STORED => data.to_vec(),
+            DEFLATED => {
+                let mut contents = Vec::with_capacity(entry.size);
+                DeflateDecoder::new(data).read_to_end(&mut contents).map_err(|_| invalid())?;
+                contents
+            }
+            method => return Err(format!("{} is compressed with method {}, only stored and deflated files can be read", entry.name, method)),
+        };
+        let mut crc = Crc::new();
+        crc.update(&contents);
+        if contents.len() != entry.size || crc.sum() != entry.crc {
+            return Err(invalid());
+        }
+        Ok(contents)
+    }
+}
+
+// a new archive at path holding each file under its name, deflated unless that doesn't make it smaller
+// (already compressed images mostly don't), dated with the file's modification time
+pub fn write(path: &str, files: &[(String, &Path)]) -> Result<(), String> {
+    let too_big = || format!("{} would be too big for a zip archive without zip64", path);
+    if files.len() >= 0xFFFF {
+        return Err(too_big());
+    }
+    let mut archive = vec![];
+    let mut directory = vec![];
+    for (name, file) in files {
+        let contents = fs::read(file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let mut crc = Crc::new();
+        crc.update(&contents);
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(&contents).map_err(|e| e.to_string())?;
+        let deflated = encoder.finish().map_err(|e| e.to_string())?;
+        let (method, data) = if deflated.len() < contents.len() { (DEFLATED, &deflated) } else { (STORED, &contents) };
+        let modified = fs::metadata(file).and_then(|metadata| metadata.modified()).unwrap_or(UNIX_EPOCH);
+        let (time, date) = dos_time(modified);
+        let (Ok(size), Ok(offset)) = (u32::try_from(contents.len()), u32::try_from(archive.len())) else {
+            return Err(too_big());
+        };
+
+        // the fields the local header and the central directory share, from the version needed on
+        let mut shared = vec![];
+        for field in [20, UTF8_NAMES, method, time, date] {
+            shared.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [crc.sum(), data.len() as u32, size] {
+            shared.extend_from_slice(&field.to_le_bytes());
+        }
+        shared.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        // no extra field
+        shared.extend_from_slice(&0u16.to_le_bytes());
+
+        archive.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        archive.extend_from_slice(&shared);
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(data);
+
+        directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        // made by version 2.0
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&shared);
+        // no comment, the first disk, no attributes
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let (Ok(directory_size), Ok(directory_offset)) = (u32::try_from(directory.len()), u32::try_from(archive.len())) else {
+        return Err(too_big());
+    };
+    archive.extend_from_slice(&directory);
+    archive.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+    // this disk and the one the directory starts on
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&directory_size.to_le_bytes());
+    archive.extend_from_slice(&directory_offset.to_le_bytes());
+    // no comment
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    fs::write(path, archive).map_err(|e| format!("Failed to save {}: {}", path, e))
+}
+
+// ms-dos time and date as zip stores them, in utc to two seconds, times before 1980 can't be stored
+fn dos_time(time: SystemTime) -> (u16, u16) {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0) as i64;
+    let (year, month, day) = calendar_date(seconds.div_euclid(86400));
+    if year < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+    let of_day = seconds.rem_euclid(86400);
+    let time = (of_day / 3600) << 11 | (of_day / 60 % 60) << 5 | (of_day % 60 / 2);
+    let date = (year.min(2107) - 1980) << 9 | month << 5 | day;
+    (time as u16, date as u16)
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
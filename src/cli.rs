@@ -0,0 +1,1213 @@
+use std::{env, fs, iter::Peekable, path::Path};
+
+use image::{ImageFormat, Rgb};
+
+use color_reflect::color::{oklab_chroma_hue, parse_hex, rgb_to_hsv, rgb_to_oklab, HueRange, DEFAULT_TOLERANCE};
+use color_reflect::curve::{self, Tables};
+use color_reflect::metrics::Metric;
+use color_reflect::vision::Deficiency;
+use color_reflect::{archive, output, palette};
+use color_reflect::shader::ShaderLanguage;
+use color_reflect::space::{ColorSpace, HueSpace};
+use color_reflect::template::Template;
+use color_reflect::tonemap::Tonemap;
+use color_reflect::transform::{Axis, Chain, Direction, Overflow, Stage, MAX_STAGES};
+
+// options every subcommand accepts
+pub struct CommonArgs {
+    pub input: String,
+    pub output: String,
+    // names each output instead of output, filled in per file
+    pub output_template: Option<Template>,
+    pub output_format: Option<ImageFormat>,
+    // folder every output is saved in, output and the template are already inside it
+    pub output_dir: Option<String>,
+    pub dpi: Option<f32>,
+    pub preserve_mtime: bool,
+    // file the sha-256 of every saved output is written to
+    pub checksum_log: Option<String>,
+    pub auto_levels: bool,
+    pub input_space: ColorSpace,
+    // curve float inputs are brought down to 8 bits with, clamped when None
+    pub tonemap: Option<Tonemap>,
+    pub hue_space: HueSpace,
+    pub invert_value: bool,
+    pub equalize_hue: bool,
+    pub repeat: u32,
+    pub palette_size: Option<usize>,
+    // also save the result as seen with this kind of color blindness
+    pub simulate: Option<Deficiency>,
+    // channel order like [2, 1, 0] for bgr, None keeps rgb
+    pub swap: Option<[usize; 3]>,
+    pub curve: Option<Tables>,
+    pub tint: Option<(Rgb<u8>, f32)>,
+    // most degrees a hue is moved at random, and the seed
+    pub jitter: Option<(f32, u64)>,
+    // most degrees the reflect axis is turned along each row by --glitch, and the seed
+    pub glitch: Option<(f32, u64)>,
+    pub max_shift: Option<f32>,
+    // how close to gray a pixel, and how close to the axis a hue, has to be to be left alone
+    pub tolerance: f32,
+    // oklab lightness (0-100) a pixel has to be above to be changed
+    pub highlight_only: Option<f32>,
+    pub vignette: f32,
+    pub alpha_scale: f32,
+    // width, color and whether it is drawn inside the image instead of around it
+    pub border: Option<(u32, Rgb<u8>, bool)>,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    // degrees to turn the result clockwise, 0, 90, 180 or 270
+    pub rotate: u32,
+    // rows and columns of copies of the result the output is made of
+    pub tile: Option<(u32, u32)>,
+    // color transparent pixels are flattened onto, formats without alpha use white when it is None
+    pub background: Option<Rgb<u8>>,
+    pub threads_affinity: bool,
+    pub keep_metadata: bool,
+    // bits per channel of the saved file, 8 or 16
+    pub bit_depth: u8,
+    // most colors of a palette based png output
+    pub indexed: Option<usize>,
+    // adam7 interlaced png output
+    pub interlace: bool,
+    // png compression, None keeps the png crate's fast setting
+    pub compress_level: Option<png::Compression>,
+    // save the pixels as plain rgba bytes instead of an image file
+    pub raw: bool,
+    pub preview_term: bool,
+    // show the full error and what caused it when an image can't be opened or saved
+    pub verbose_errors: bool,
+    // fail instead of warning when something asked for can't be kept, and on inputs without alpha
+    pub strict: bool,
+    // --allow-rgb, inputs without alpha are fine under --strict
+    pub allow_rgb: bool,
+    // x, y, width and height of the part of the input to keep
+    pub crop: Option<(u32, u32, u32, u32)>,
+    pub max_dimension: Option<u32>,
+    // percent step and path for partial previews
+    pub preview_every: Option<(f32, String)>,
+    // what to measure between the input and the result, in the order they are printed
+    pub metrics: Vec<Metric>,
+    pub stats: bool,
+    // where to save the image of which pixels the transform changed
+    pub change_mask: Option<String>,
+    // pixel whose colors are printed on the way through the transform
+    pub sample_point: Option<(u32, u32)>,
+    // the range --sample-point prints hues in
+    pub hue_range: HueRange,
+    // columns of the grid of every result from a folder input
+    pub montage: Option<u32>,
+    // how many images of a folder input are worked on at once
+    pub concurrency: usize,
+    // None picks one from the size of the images
+    pub schedule: Option<Schedule>,
+    // skip images of a folder input whose outputs are newer than them
+    pub resume: bool,
+    // run every output again with different thread counts and fail if they don't match
+    pub verify_determinism: bool,
+    // print a json line of timings for every output
+    pub benchmark: bool,
+}
+
+// how the images of a folder input share the cores
+#[derive(Clone, Copy, PartialEq)]
+pub enum Schedule {
+    // every image on one core, as many images at once as --concurrency allows
+    AcrossFiles,
+    // every image spread over all the cores
+    WithinFiles,
+}
+
+// where the reflect subcommand gets its angles from
+pub enum AngleSource {
+    Single(f32),
+    // one output per angle listed in the file
+    File(String),
+    // one output with the angle changing across the image
+    Varying(Axis),
+    // like Varying, with the angles read from the gray values of an image
+    Map(String),
+    // --ops, several steps in place of the single reflection
+    Chain(Chain),
+    // --hue-from, hues taken from the same pixels of another image
+    HueFrom(String),
+    // picked per image, giving the largest mean change or the one closest to target
+    Auto { target: Option<f32> },
+    // an angle worth trying worked out from the hues of each image, printed without saving anything
+    Suggest,
+    // picked per image to take its mean hue to the mean hue of the image at the path
+    Reference(String),
+    // angles typed in one at a time
+    Repl,
+    // every step degrees from 0 to 180, saved together as one grid
+    ContactSheet { step: f32, columns: u32, labels: bool },
+}
+
+pub enum Command {
+    // with a number of folds the reflection is a kaleidoscope
+    Reflect { common: CommonArgs, angles: AngleSource, kaleidoscope: Option<u32> },
+    Rotate { common: CommonArgs, degrees: f32 },
+    Invert { common: CommonArgs },
+    ChromaInvert { common: CommonArgs },
+    // reflect a single color, no image involved
+    Color { color: Rgb<u8>, angle: f32, hue_range: HueRange },
+    // reflect every color of a palette file
+    Palette { input: String, angle: f32, output: Option<String>, swatches: Option<String> },
+    // print shader code for the reflection instead of reading an image
+    Shader { language: ShaderLanguage, angle: f32 },
+    // help for one subcommand, or the overview
+    Help(Option<String>),
+}
+
+const COMMANDS: [&str; 6] = ["reflect", "rotate", "invert", "chroma-invert", "color", "palette"];
+
+const USAGE: &str = "\
+Usage: color_reflect <command> <image> [arguments] [options]
+
+Commands:
+  reflect <image> <angle>    reflect hues along the line at angle degrees from red
+  rotate <image> <degrees>   shift every hue by degrees
+  invert <image>             rgb negative
+  chroma-invert <image>      the opposite oklab color at the same lightness
+  color <hex> <angle>        print what a single color like #3498db reflects to
+  palette <file> <angle>     reflect every color in a list of hex colors
+
+<image> can also be a folder, every image in it is processed and saved with its name
+added to the output path, other files are skipped. Built with the zip feature, it can
+be a .zip of images too, and -o a .zip to save the results into.
+
+Without an angle, reflect uses the HUE_REFLECT_ANGLE environment variable if set.
+
+Run color_reflect help <command> for the options of each command.
+The old form, color_reflect <image> <angle>, still works as reflect but is deprecated.
+";
+
+const COMMON_OPTIONS: &str = "\
+Options:
+  -o, --output <path>    where to save the result (default output.png)
+  --output-dir <path>    save every output in this folder, creating it if needed, -o and
+                         --output-template paths are taken relative to it
+  --output-format <name> save as png, jpg, webp, tiff, ... whatever the extension says,
+                         --format is the same
+  --output-template <pattern>
+                         name outputs from a pattern like {stem}_{angle}_{mode}.{ext},
+                         with {stem} and {ext} of the input, {angle}, {mode}, {index}
+                         (counting outputs from 0) and {date}, replaces -o
+  --preserve-mtime       give the output the same modification time as the input,
+                         --preserve-timestamps is the same
+  --checksum-log <path>  write the sha-256 of every saved output to path, as sha256sum does
+  --dpi <n>              resolution to store in the output (default: the input's)
+  --keep-metadata        copy text and xmp metadata from the input into png and jpeg outputs
+  --strip-metadata       leave all metadata except the resolution out (the default)
+  --bit-depth <8|16>     bits per channel of the saved file (default 8), 16 needs png or tiff,
+                         the work is always done in 8 bits, 16 bit inputs lose the extra
+                         precision and 16 only spreads the 8 bit result over the wider range
+  --indexed <n>          save a png with a palette of at most n colors (2-256)
+  --interlace            save an adam7 interlaced png, which browsers show bit by bit as it loads
+  --compress-level <fast|default|best>
+                         how hard png output is compressed (default fast), smaller files take longer
+  --raw                  save plain rgba bytes to a .raw output (default output.raw),
+                         with the width and height in a .json file next to it
+  --repeat <n>           apply the operation n times, for rotate this adds up the degrees,
+                         for reflect and invert an even count gives back the input
+  --auto-levels          stretch brightness to the full range first
+  --input-space <srgb|p3|linear>
+                         color space of the input (default srgb), the output is saved in
+                         the same space
+  --tonemap <reinhard|aces>
+                         roll the highlights of hdr and exr inputs off instead of clipping
+                         everything over full brightness
+  --space <hsv|oklab>    what hues are measured in for reflect and rotate (default hsv),
+                         oklab hues look more even, the angle is then an oklch hue
+  --invert-value         flip brightness (hsv value) first, keeping hue and saturation
+  --equalize-hue         spread the hues of each image evenly round the wheel first
+  --crop <x,y,w,h>       keep only the w by h pixel rectangle with its top left corner at x,y
+  --max-dimension <n>    shrink the image first so neither side is longer than n pixels
+  --palette [n]          print the n (default 8) dominant colors of the result and
+                         save them as swatches next to the output
+  --simulate <protanopia|deuteranopia|tritanopia>
+                         also save the result as it looks with that color blindness,
+                         as the output path with the name added
+  --swap <order>         put the result's channels in another order, like bgr
+  --curve <path>         apply tone curves from a file of input output points to the
+                         result, lines starting with r, g or b curve only that channel
+  --tint <hex> <opacity> blend a color like #ff8800 over the result, opacity from 0 to 1
+  --jitter <degrees>     after reflect or rotate, move each hue by a random amount of up
+                         to degrees either way, the same for every run with the same seed
+  --seed <n>             seed for --jitter (default 0)
+  --max-shift <degrees>  keep every hue within degrees of where it started, moving the
+                         ones that would go further back to that limit
+  --tolerance <eps>      how near counts as exact, a fraction (0 to 1) of the full range: pixels
+                         whose channels are at most eps apart are gray and hues at most eps of
+                         a turn (eps * 360 degrees) off the axis are on it, the default takes
+                         only exact grays and hues a thousandth of a degree off
+  --highlight-only <lightness>
+                         only change pixels brighter than lightness (oklab, 0 to 100),
+                         leaving the shadows as they are
+  --vignette <strength>  darken the result towards the corners, from 0 (not at all)
+                         to 1 (black corners)
+  --alpha-scale <factor> multiply the opacity of every pixel by factor, leaving rgb as it is
+  --flip-horizontal      mirror the result left to right
+  --flip-vertical        mirror the result top to bottom
+  --rotate <90|180|270>  turn the result clockwise, after any flips
+  --tile <rows,cols>     save the result repeated rows times down and cols times across
+  --border <width,hex>   add a frame of width pixels in a color like #ffffff around the result
+  --border-inset         draw the frame over the edges of the result instead, keeping its size
+  --background <hex>     draw the result over a color like #000000, making it opaque,
+                         jpeg outputs are always drawn over one (default white)
+  --preview-term         also draw the result in the terminal
+  --metrics              print psnr and ssim between the input and the result
+  --metric <psnr|ssim>   print just one of them, can be given twice
+  --stats                print mean, min and max of red, green and blue, and the mean hue, before and after
+  --change-mask <path>   also save an image that is white where the transform changed a
+                         pixel's rgb and black where it didn't
+  --sample-point <x,y>   print the rgb and hsv of the pixel at x,y before and after, to stderr
+  --signed-hue           print those hues from -180 to 180 instead of 0 to 360
+  --verbose-errors, -vv  when an image can't be opened or saved, print everything the image
+                         library reports about why, for bug reports
+  --strict               fail instead of warning when something can't be kept (metadata,
+                         dpi, transparency...), and on inputs without an alpha channel
+  --allow-rgb            accept inputs without an alpha channel under --strict
+  --preview-every <pct> <path>
+                         save the unfinished result each time another pct percent of
+                         rows is done, as path with the percentage added
+  --montage <columns>    with a folder as input, also save every result in one grid with
+                         that many columns, as the output path with _montage added
+  --threads-affinity     bind each worker thread to its own core (a hint, linux only,
+                         built with the affinity feature)
+  --concurrency <n>      with a folder as input, work on at most n images at once
+                         (default: the number of cores)
+  --across-files         with a folder as input, do each image on a single core, faster
+                         for many small images
+  --within-files         with a folder as input, spread each image over every core, faster
+                         for a few large ones (default: picked from the image sizes)
+  --resume               with a folder as input, skip images whose outputs already exist
+                         and are newer than them, to carry on after an interrupted run
+  --force                process every image even with --resume
+  --verify-determinism   redo every result with 1, 2 and many threads and fail at the first
+                         pixel that comes out different, for checking the threading
+  --benchmark            print a line of json with the size, load and processing times and
+                         (best effort, left out where unavailable) peak memory of every output
+";
+
+const REFLECT_USAGE: &str = "\
+Usage: color_reflect reflect <image> <angle> [options]
+
+Reflects every hue along the line at angle degrees from red.
+
+Angle options, each replaces the angle argument:
+  --axis-color <hex>     reflect along the hue of a color like #3498db
+  --angles-file <path>   read angles from a file, one per line, saving one output per angle
+  --angle-map <path>     take each pixel's angle from the gray value of the same pixel of
+                         an image the size of the input, 0 is 0 degrees and 255 is 180
+  --angle-gradient <start,end>
+                         go from start at the left edge to end at the right edge
+  --angle-gradient-direction <horizontal|vertical>
+                         run the gradient left to right (default) or top to bottom
+  --gradient-angle <top> <bottom>
+                         go from top at the top row to bottom at the bottom row
+  --radial-angle <center_angle,edge_angle>
+                         go from center_angle in the middle of the image to edge_angle
+                         at the corners
+  --center <x,y>         pixel to use as the middle for --radial-angle
+  --axis-by-saturation <low> <high> <threshold>
+                         reflect along low for pixels with less saturation (0-100) than
+                         threshold and along high for the rest
+  --ops <stages>         run several steps per pixel in order, like
+                         reflect:90,rotate:30,saturation:1.2,brightness:0.9
+  --pipeline <stages>    the same as --ops
+  --hue-from <path>      give each pixel the hue of the same pixel of an image the size of
+                         the input instead, keeping its own saturation and value
+  --overflow <clamp|wrap>
+                         with --ops, stop saturation at 100 (the default) or wrap it
+                         back around from 0
+  --auto-angle           try angles on a small copy of each image and use the one that
+                         changes its colors the most, printing it
+  --target-change <n>    with --auto-angle, pick the angle whose mean change per channel
+                         (0-255) is closest to n instead
+  --suggest-angle        print angles worth trying, from how the hues of each image are
+                         spread, without processing or saving anything
+  --reference <path>     use the angle that takes the mean hue of each image to the mean
+                         hue of another image, printing it, a rough color match
+  --repl                 load the image once and read angles from the keyboard, saving
+                         the output after each one, type help once running for commands
+  --contact-sheet <step,columns>
+                         reflect every step degrees from 0 to 180 and save the results
+                         as one image, in a grid with that many columns
+  --sheet-labels         write each cell's angle in its top left corner
+
+Other options:
+  --kaleidoscope <n>     mirror each hue across the closest of n lines spread evenly around
+                         the wheel from the angle, giving the hues n-fold symmetry
+  --glitch <seed>        turn the axis by a random amount along each row for a scanline
+                         glitch, the same for every run with the same seed
+  --glitch-amount <degrees>
+                         most degrees --glitch turns a row's axis either way (default 10)
+  --emit-shader <glsl|wgsl>
+                         print a shader function doing the same reflection on the gpu
+                         instead, takes just the angle, no image
+";
+
+const ROTATE_USAGE: &str = "\
+Usage: color_reflect rotate <image> <degrees> [options]
+
+Shifts every hue by the given number of degrees.
+";
+
+const INVERT_USAGE: &str = "\
+Usage: color_reflect invert <image> [options]
+
+Replaces every color with its rgb negative.
+";
+
+const CHROMA_INVERT_USAGE: &str = "\
+Usage: color_reflect chroma-invert <image> [options]
+
+Negates the a and b of every color in oklab, giving its complement at the same
+lightness, grays stay as they are.
+";
+
+const COLOR_USAGE: &str = "\
+Usage: color_reflect color <hex> <angle> [options]
+
+Reflects a single color like #3498db along the line at angle degrees from red and
+prints the result as hex, rgb and hsv.
+
+Options:
+  --signed-hue           print hues from -180 to 180 instead of 0 to 360
+";
+
+const PALETTE_USAGE: &str = "\
+Usage: color_reflect palette <file> <angle> [options]
+
+Reflects every color of a palette file, one hex color like #3498db per line,
+and prints the reflected palette in the same format.
+
+Options:
+  -o, --output <path>    write the reflected palette to a file instead
+  --swatches <path>      also save the reflected colors as an image of swatches
+";
+
+pub fn usage(command: Option<&str>) -> String {
+    let specific = match command {
+        Some("reflect") => REFLECT_USAGE,
+        Some("rotate") => ROTATE_USAGE,
+        Some("invert") => INVERT_USAGE,
+        Some("chroma-invert") => CHROMA_INVERT_USAGE,
+        Some("color") => return COLOR_USAGE.to_string(),
+        Some("palette") => return PALETTE_USAGE.to_string(),
+        _ => return USAGE.to_string(),
+    };
+    format!("{}\n{}", specific, COMMON_OPTIONS)
+}
+
+// values for the reflect only options, checked once everything is parsed
+#[derive(Default)]
+struct ReflectArgs {
+    angles_file: Option<String>,
+    angle_map: Option<String>,
+    angle_gradient: Option<(f32, f32)>,
+    gradient_angle: Option<(f32, f32)>,
+    gradient_direction: Option<Direction>,
+    radial_angle: Option<(f32, f32)>,
+    center: Option<(f32, f32)>,
+    repl: bool,
+    contact_sheet: Option<(f32, f32)>,
+    sheet_labels: bool,
+    emit_shader: Option<ShaderLanguage>,
+    axis_color: Option<Rgb<u8>>,
+    axis_by_saturation: Option<(f32, f32, f32)>,
+    ops: Option<Chain>,
+    hue_from: Option<String>,
+    overflow: Option<Overflow>,
+    auto_angle: bool,
+    target_change: Option<f32>,
+    suggest_angle: bool,
+    reference: Option<String>,
+    kaleidoscope: Option<u32>,
+    glitch: Option<u64>,
+    glitch_amount: Option<f32>,
+}
+
+pub fn parse_args(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.peekable();
+    let command = match args.peek().map(String::as_str) {
+        None => return Ok(Command::Help(None)),
+        Some("help" | "-h" | "--help") => {
+            args.next();
+            return Ok(Command::Help(args.next()));
+        }
+        Some(name) if COMMANDS.contains(&name) => args.next().unwrap(),
+        Some(_) => {
+            eprintln!("Note: color_reflect <image> <angle> is deprecated, use color_reflect reflect <image> <angle>");
+            String::from("reflect")
+        }
+    };
+
+    if command == "color" {
+        return parse_color(args);
+    }
+    if command == "palette" {
+        return parse_palette(args);
+    }
+
+    let mut positional: Vec<String> = vec![];
+    let mut output = None;
+    let mut output_template = None;
+    let mut output_dir = None;
+    let mut output_format = None;
+    let mut dpi = None;
+    let mut preserve_mtime = false;
+    let mut checksum_log = None;
+    let mut auto_levels = false;
+    let mut input_space = ColorSpace::Srgb;
+    let mut tonemap = None;
+    let mut hue_space = HueSpace::Hsv;
+    let mut invert_value = false;
+    let mut equalize_hue = false;
+    let mut repeat = 1;
+    let mut palette_size = None;
+    let mut simulate = None;
+    let mut swap = None;
+    let mut curve = None;
+    let mut tint = None;
+    let mut jitter = None;
+    let mut max_shift = None;
+    let mut tolerance = DEFAULT_TOLERANCE;
+    let mut highlight_only = None;
+    let mut seed = None;
+    let mut vignette = 0.;
+    let mut alpha_scale = 1.;
+    let mut border = None;
+    let mut border_inset = false;
+    let mut flip_horizontal = false;
+    let mut flip_vertical = false;
+    let mut rotate = 0;
+    let mut tile = None;
+    let mut background = None;
+    let mut threads_affinity = false;
+    let mut keep_metadata = false;
+    let mut bit_depth = 8;
+    let mut indexed = None;
+    let mut interlace = false;
+    let mut compress_level = None;
+    let mut raw = false;
+    let mut preview_term = false;
+    let mut verbose_errors = false;
+    let mut strict = false;
+    let mut allow_rgb = false;
+    let mut crop = None;
+    let mut max_dimension = None;
+    let mut preview_every = None;
+    let mut metrics = vec![];
+    let mut stats = false;
+    let mut change_mask = None;
+    let mut sample_point = None;
+    let mut hue_range = HueRange::Unsigned;
+    let mut montage = None;
+    let mut concurrency = num_cpus::get();
+    let mut resume = false;
+    let mut verify_determinism = false;
+    let mut benchmark = false;
+    let mut schedule = None;
+    let mut force = false;
+    let mut reflect = ReflectArgs::default();
+    let is_reflect = command == "reflect";
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(Some(command))),
+            "-o" | "--output" => output = Some(value(&mut args, &arg)?),
+            "--output-template" => output_template = Some(Template::parse(&value(&mut args, &arg)?)?),
+            "--output-dir" => output_dir = Some(value(&mut args, &arg)?),
+            "--output-format" | "--format" => {
+                let name = value(&mut args, &arg)?;
+                output_format = Some(output::parse_format(&name).ok_or(format!("Unknown image format {}", name))?);
+            }
+            "--preserve-mtime" | "--preserve-timestamps" => preserve_mtime = true,
+            "--checksum-log" => checksum_log = Some(value(&mut args, &arg)?),
+            "--auto-levels" => auto_levels = true,
+            "--invert-value" => invert_value = true,
+            "--equalize-hue" => equalize_hue = true,
+            "--repeat" => {
+                repeat = match value(&mut args, &arg)?.parse::<u32>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(String::from("Repeat must be a whole number above 0")),
+                }
+            }
+            "--input-space" => {
+                let name = value(&mut args, &arg)?;
+                input_space = ColorSpace::parse(&name).ok_or("Input space must be srgb, p3 or linear")?;
+            }
+            "--tonemap" => {
+                let name = value(&mut args, &arg)?;
+                tonemap = Some(Tonemap::parse(&name).ok_or("Tone mapping must be reinhard or aces")?);
+            }
+            "--space" => {
+                let name = value(&mut args, &arg)?;
+                hue_space = HueSpace::parse(&name).ok_or("Space must be hsv or oklab")?;
+            }
+            "--preview-term" => preview_term = true,
+            "--verbose-errors" | "-vv" => verbose_errors = true,
+            "--strict" => strict = true,
+            "--allow-rgb" => allow_rgb = true,
+            "--metrics" => metrics = vec![Metric::Psnr, Metric::Ssim],
+            "--metric" => {
+                let name = value(&mut args, &arg)?;
+                let metric = Metric::parse(&name).ok_or("Metric must be psnr or ssim")?;
+                if !metrics.contains(&metric) {
+                    metrics.push(metric);
+                }
+            }
+            "--stats" => stats = true,
+            "--change-mask" => change_mask = Some(value(&mut args, &arg)?),
+            "--sample-point" => {
+                let value = value(&mut args, &arg)?;
+                let numbers: Option<Vec<u32>> = value.split(',').map(|n| n.trim().parse().ok()).collect();
+                sample_point = match numbers.as_deref() {
+                    Some(&[x, y]) => Some((x, y)),
+                    _ => return Err(String::from("Sample point must be two whole numbers X,Y")),
+                }
+            }
+            "--signed-hue" => hue_range = HueRange::Signed,
+            "--across-files" | "--within-files" => {
+                let picked = if arg == "--across-files" { Schedule::AcrossFiles } else { Schedule::WithinFiles };
+                if schedule.is_some_and(|schedule| schedule != picked) {
+                    return Err(String::from("--across-files and --within-files can't be used together"));
+                }
+                schedule = Some(picked);
+            }
+            "--concurrency" => {
+                concurrency = match value(&mut args, &arg)?.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(String::from("Concurrency must be a whole number above 0")),
+                }
+            }
+            "--resume" => resume = true,
+            "--verify-determinism" => verify_determinism = true,
+            "--benchmark" => benchmark = true,
+            "--force" => force = true,
+            "--montage" => {
+                montage = match value(&mut args, &arg)?.parse::<u32>() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(String::from("Montage columns must be a whole number above 0")),
+                }
+            }
+            "--dpi" => dpi = Some(number(&value(&mut args, &arg)?, "DPI")?),
+            "--crop" => {
+                let value = value(&mut args, &arg)?;
+                let numbers: Option<Vec<u32>> = value.split(',').map(|n| n.trim().parse().ok()).collect();
+                crop = match numbers.as_deref() {
+                    Some(&[x, y, width, height]) if width > 0 && height > 0 => Some((x, y, width, height)),
+                    _ => return Err(String::from("Crop must be four whole numbers X,Y,WIDTH,HEIGHT with a width and height above 0")),
+                }
+            }
+            "--max-dimension" => {
+                let value = value(&mut args, &arg)?;
+                match value.parse::<u32>() {
+                    Ok(n) if n > 0 => max_dimension = Some(n),
+                    _ => return Err(String::from("Max dimension must be a whole number above 0")),
+                }
+            }
+            "--preview-every" => {
+                let percent = number(&value(&mut args, &arg)?, "Preview step")?;
+                if !(percent > 0. && percent <= 100.) {
+                    return Err(String::from("Preview step must be above 0 and at most 100 percent"));
+                }
+                preview_every = Some((percent, value(&mut args, &arg)?));
+            }
+            "--palette" => {
+                // the color count is optional
+                let count = args.peek().and_then(|value| value.parse::<usize>().ok());
+                if count.is_some() {
+                    args.next();
+                }
+                palette_size = Some(count.unwrap_or(8));
+            }
+            "--simulate" => {
+                let name = value(&mut args, &arg)?;
+                simulate = Some(Deficiency::parse(&name).ok_or("Simulate must be protanopia, deuteranopia or tritanopia")?);
+            }
+            "--curve" => {
+                let path = value(&mut args, &arg)?;
+                let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read curve file {}: {}", path, e))?;
+                curve = Some(curve::parse(&contents).map_err(|e| format!("Curve file {}: {}", path, e))?);
+            }
+            "--swap" => {
+                let order = value(&mut args, &arg)?.to_ascii_lowercase();
+                let channels: Vec<usize> = order.chars().filter_map(|c| "rgb".find(c)).collect();
+                if order.len() != 3 || channels.len() != 3 || (0..3).any(|channel| !channels.contains(&channel)) {
+                    return Err(format!("Swap order {} must use each of r, g and b once, like bgr", order));
+                }
+                // rgb leaves every channel where it is
+                swap = Some([channels[0], channels[1], channels[2]]).filter(|&order| order != [0, 1, 2]);
+            }
+            "--tint" => {
+                let color = value(&mut args, &arg)?;
+                let color = parse_hex(&color).ok_or(format!("{} is not a hex color like #ff8800", color))?;
+                let opacity = number(&value(&mut args, &arg)?, "Tint opacity")?;
+                if !(0. ..=1.).contains(&opacity) {
+                    return Err(String::from("Tint opacity must be between 0 and 1"));
+                }
+                tint = Some((color, opacity));
+            }
+            "--jitter" => {
+                let degrees = number(&value(&mut args, &arg)?, "Jitter")?;
+                if !(degrees > 0. && degrees <= 180.) {
+                    return Err(String::from("Jitter must be above 0 and at most 180 degrees"));
+                }
+                jitter = Some(degrees);
+            }
+            "--max-shift" => {
+                let degrees = number(&value(&mut args, &arg)?, "Max shift")?;
+                if !(0. ..=180.).contains(&degrees) {
+                    return Err(String::from("Max shift must be from 0 to 180 degrees"));
+                }
+                max_shift = Some(degrees);
+            }
+            "--tolerance" => {
+                tolerance = number(&value(&mut args, &arg)?, "Tolerance")?;
+                if !(0. ..=1.).contains(&tolerance) {
+                    return Err(String::from("Tolerance must be from 0 to 1"));
+                }
+            }
+            "--highlight-only" => {
+                let lightness = number(&value(&mut args, &arg)?, "Highlight lightness")?;
+                if !(0. ..=100.).contains(&lightness) {
+                    return Err(String::from("Highlight lightness must be from 0 to 100"));
+                }
+                highlight_only = Some(lightness);
+            }
+            "--seed" => {
+                seed = Some(value(&mut args, &arg)?.parse::<u64>().map_err(|_| "Seed must be a whole number of 0 or more")?);
+            }
+            "--vignette" => {
+                vignette = number(&value(&mut args, &arg)?, "Vignette strength")?;
+                if !(0. ..=1.).contains(&vignette) {
+                    return Err(String::from("Vignette strength must be between 0 and 1"));
+                }
+            }
+            "--alpha-scale" => {
+                alpha_scale = number(&value(&mut args, &arg)?, "Alpha scale")?;
+                if !(alpha_scale >= 0. && alpha_scale.is_finite()) {
+                    return Err(String::from("Alpha scale must be a number of 0 or more"));
+                }
+            }
+            "--border" => {
+                let value = value(&mut args, &arg)?;
+                let parsed = value.split_once(',').and_then(|(width, color)| {
+                    Some((width.trim().parse::<u32>().ok().filter(|&width| width > 0)?, parse_hex(color.trim())?))
+                });
+                border = Some(parsed.ok_or("Border must be a width above 0 and a hex color, WIDTH,#RRGGBB")?);
+            }
+            "--border-inset" => border_inset = true,
+            "--flip-horizontal" => flip_horizontal = true,
+            "--flip-vertical" => flip_vertical = true,
+            "--rotate" => {
+                rotate = match value(&mut args, &arg)?.as_str() {
+                    "90" => 90,
+                    "180" => 180,
+                    "270" => 270,
+                    _ => return Err(String::from("--rotate turns by 90, 180 or 270 degrees, use the rotate command to shift hues")),
+                }
+            }
+            "--tile" => {
+                let value = value(&mut args, &arg)?;
+                let numbers: Option<Vec<u32>> = value.split(',').map(|n| n.trim().parse().ok()).collect();
+                tile = match numbers.as_deref() {
+                    Some(&[rows, columns]) if rows > 0 && columns > 0 => Some((rows, columns)),
+                    _ => return Err(String::from("Tile must be two whole numbers above 0, ROWS,COLS")),
+                }
+            }
+            "--background" => {
+                let color = value(&mut args, &arg)?;
+                background = Some(parse_hex(&color).ok_or(format!("{} is not a hex color like #ffffff", color))?);
+            }
+            "--threads-affinity" if cfg!(feature = "affinity") => threads_affinity = true,
+            "--threads-affinity" => return Err(String::from("--threads-affinity needs color_reflect built with --features affinity")),
+            "--keep-metadata" => keep_metadata = true,
+            "--strip-metadata" => keep_metadata = false,
+            "--raw" => raw = true,
+            "--interlace" => interlace = true,
+            "--compress-level" => {
+                let name = value(&mut args, &arg)?;
+                compress_level = Some(output::parse_compression(&name).ok_or(format!("Compression level must be fast, default or best, not {}", name))?);
+            }
+            "--indexed" => {
+                indexed = match value(&mut args, &arg)?.parse::<usize>() {
+                    Ok(n) if (2..=256).contains(&n) => Some(n),
+                    _ => return Err(String::from("Indexed colors must be a whole number from 2 to 256")),
+                }
+            }
+            "--bit-depth" => {
+                bit_depth = match value(&mut args, &arg)?.as_str() {
+                    "8" => 8,
+                    "16" => 16,
+                    _ => return Err(String::from("Bit depth must be 8 or 16")),
+                }
+            }
+            "--angles-file" if is_reflect => reflect.angles_file = Some(value(&mut args, &arg)?),
+            "--angle-map" if is_reflect => reflect.angle_map = Some(value(&mut args, &arg)?),
+            "--angle-gradient" if is_reflect => {
+                reflect.angle_gradient = Some(pair(&value(&mut args, &arg)?, "Angle gradient", "START,END")?);
+            }
+            "--gradient-angle" if is_reflect => {
+                let top = number(&value(&mut args, &arg)?, "Top angle")?;
+                let bottom = number(&value(&mut args, &arg)?, "Bottom angle")?;
+                reflect.gradient_angle = Some((top, bottom));
+            }
+            "--angle-gradient-direction" if is_reflect => {
+                reflect.gradient_direction = match value(&mut args, &arg)?.as_str() {
+                    "horizontal" => Some(Direction::Horizontal),
+                    "vertical" => Some(Direction::Vertical),
+                    _ => return Err(String::from("Gradient direction must be horizontal or vertical")),
+                }
+            }
+            "--radial-angle" if is_reflect => {
+                let value = value(&mut args, &arg)?;
+                reflect.radial_angle = Some(pair(&value, "Radial angle", "CENTER_ANGLE,EDGE_ANGLE")?);
+            }
+            "--axis-by-saturation" if is_reflect => {
+                let low = number(&value(&mut args, &arg)?, "Low saturation axis")?;
+                let high = number(&value(&mut args, &arg)?, "High saturation axis")?;
+                let threshold = number(&value(&mut args, &arg)?, "Saturation threshold")?;
+                if !(0. ..=100.).contains(&threshold) {
+                    return Err(String::from("Saturation threshold must be from 0 to 100"));
+                }
+                reflect.axis_by_saturation = Some((low, high, threshold));
+            }
+            "--hue-from" if is_reflect => reflect.hue_from = Some(value(&mut args, &arg)?),
+            "--ops" | "--pipeline" if is_reflect => reflect.ops = Some(parse_chain(&value(&mut args, &arg)?)?),
+            "--overflow" if is_reflect => {
+                let name = value(&mut args, &arg)?;
+                reflect.overflow = Some(Overflow::parse(&name).ok_or("Overflow must be clamp or wrap")?);
+            }
+            "--auto-angle" if is_reflect => reflect.auto_angle = true,
+            "--suggest-angle" if is_reflect => reflect.suggest_angle = true,
+            "--reference" if is_reflect => reflect.reference = Some(value(&mut args, &arg)?),
+            "--target-change" if is_reflect => {
+                let target = number(&value(&mut args, &arg)?, "Target change")?;
+                if !(0. ..=255.).contains(&target) {
+                    return Err(String::from("Target change must be from 0 to 255"));
+                }
+                reflect.target_change = Some(target);
+            }
+            "--repl" if is_reflect => reflect.repl = true,
+            "--contact-sheet" if is_reflect => {
+                reflect.contact_sheet = Some(pair(&value(&mut args, &arg)?, "Contact sheet", "STEP,COLUMNS")?);
+            }
+            "--sheet-labels" if is_reflect => reflect.sheet_labels = true,
+            "--axis-color" if is_reflect => {
+                let color = value(&mut args, &arg)?;
+                reflect.axis_color = Some(parse_hex(&color).ok_or(format!("{} is not a hex color like #3498db", color))?);
+            }
+            "--kaleidoscope" if is_reflect => {
+                reflect.kaleidoscope = match value(&mut args, &arg)?.parse::<u32>() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(String::from("Kaleidoscope folds must be a whole number above 0")),
+                }
+            }
+            "--glitch" if is_reflect => {
+                reflect.glitch = Some(value(&mut args, &arg)?.parse::<u64>().map_err(|_| "Glitch seed must be a whole number of 0 or more")?);
+            }
+            "--glitch-amount" if is_reflect => {
+                let degrees = number(&value(&mut args, &arg)?, "Glitch amount")?;
+                if !(0. ..=180.).contains(&degrees) {
+                    return Err(String::from("Glitch amount must be from 0 to 180 degrees"));
+                }
+                reflect.glitch_amount = Some(degrees);
+            }
+            "--emit-shader" if is_reflect => {
+                let name = value(&mut args, &arg)?;
+                reflect.emit_shader = Some(ShaderLanguage::parse(&name).ok_or("Shader language must be glsl or wgsl")?);
+            }
+            "--center" if is_reflect => reflect.center = Some(pair(&value(&mut args, &arg)?, "Center", "X,Y")?),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {} for {}", flag, command)),
+            _ => positional.push(arg),
+        }
+    }
+
+    if let Some(language) = reflect.emit_shader {
+        if reflect.kaleidoscope.is_some() {
+            return Err(String::from("--emit-shader can't be used with --kaleidoscope"));
+        }
+        if reflect.glitch.is_some() {
+            return Err(String::from("--emit-shader can't be used with --glitch"));
+        }
+        if hue_space != HueSpace::Hsv {
+            return Err(String::from("--emit-shader only writes hsv code, it can't be used with --space"));
+        }
+        // the shader only needs the angle, no image is read
+        if positional.len() > 1 {
+            return Err(String::from("--emit-shader takes just the angle, like reflect 90 --emit-shader glsl"));
+        }
+        let AngleSource::Single(angle) = reflect_angles(reflect, positional.pop(), hue_space)? else {
+            return Err(String::from("--emit-shader only works with a single angle"));
+        };
+        if !angle.is_finite() {
+            return Err(String::from("Angle must be a finite number"));
+        }
+        return Ok(Command::Shader { language, angle });
+    }
+
+    let output = output.unwrap_or(String::from(if raw { "output.raw" } else { "output.png" }));
+    if raw {
+        if output_format.is_some() {
+            return Err(String::from("--raw can't be used with --output-format"));
+        }
+        if output_template.is_none() && !is_raw_path(&output) {
+            return Err(format!("--raw saves plain bytes, give the output a .raw name instead of {}", output));
+        }
+        if bit_depth != 8 || indexed.is_some() || interlace || compress_level.is_some() {
+            return Err(String::from("--raw always saves uncompressed 8 bit rgba, it can't be used with --bit-depth, --indexed, --interlace or --compress-level"));
+        }
+        // these save extra images next to the output, which have no raw form
+        if montage.is_some() || palette_size.is_some() || simulate.is_some() {
+            return Err(String::from("--raw can't be used with --montage, --palette or --simulate"));
+        }
+    }
+
+    // with a template the format can only be told from each name once it is filled in
+    let format = match output_template {
+        _ if raw => None,
+        Some(_) => output_format,
+        // the results inside a zip are saved as png unless --output-format says otherwise
+        None if archive::is_archive(&output) => Some(output_format.unwrap_or(ImageFormat::Png)),
+        None => Some(output_format.or(ImageFormat::from_path(&output).ok()).ok_or(format!(
+            "Can't tell the output format from {}, pick one with --output-format",
+            output
+        ))?),
+    };
+    if let Some(format) = format && !output::supports_bit_depth(format, bit_depth) {
+        return Err(format!("--bit-depth {} needs png or tiff output, not {}", bit_depth, format.extensions_str()[0]));
+    }
+    // those formats hold floats, so there are no highlights to lose
+    if tonemap.is_some() && format.is_some_and(|format| matches!(format, ImageFormat::Hdr | ImageFormat::OpenExr)) {
+        return Err(String::from("--tonemap is for 8 and 16 bit outputs, not hdr or exr"));
+    }
+    if interlace && format.is_some_and(|format| format != ImageFormat::Png) {
+        return Err(String::from("--interlace needs png output"));
+    }
+    if compress_level.is_some() && format.is_some_and(|format| format != ImageFormat::Png) {
+        return Err(String::from("--compress-level needs png output"));
+    }
+    if indexed.is_some() {
+        if format.is_some_and(|format| format != ImageFormat::Png) {
+            return Err(String::from("--indexed needs png output"));
+        }
+        if bit_depth != 8 {
+            return Err(String::from("--indexed can't be used with --bit-depth, palette entries are 8 bit"));
+        }
+    }
+    let inverts = command == "invert" || command == "chroma-invert";
+    if output_template.as_ref().is_some_and(Template::uses_angle) && inverts {
+        return Err(format!("{} has no angle for {{angle}} in --output-template", command));
+    }
+
+    // the negative is worked out in rgb and chroma-invert always in oklab, neither measures hues
+    if inverts && hue_space != HueSpace::Hsv {
+        return Err(String::from("--space only changes reflect and rotate"));
+    }
+    if inverts && jitter.is_some() {
+        return Err(String::from("--jitter only works with reflect and rotate"));
+    }
+    if inverts && max_shift.is_some() {
+        return Err(String::from("--max-shift only works with reflect and rotate"));
+    }
+    if inverts && highlight_only.is_some() {
+        return Err(String::from("--highlight-only only works with reflect and rotate"));
+    }
+    if seed.is_some() && jitter.is_none() {
+        return Err(String::from("--seed only works with --jitter"));
+    }
+    let jitter = jitter.map(|degrees| (degrees, seed.unwrap_or(0)));
+    if reflect.glitch_amount.is_some() && reflect.glitch.is_none() {
+        return Err(String::from("--glitch-amount only works with --glitch"));
+    }
+    let glitch = reflect.glitch.map(|seed| (reflect.glitch_amount.unwrap_or(DEFAULT_GLITCH_AMOUNT), seed));
+
+    if border_inset && border.is_none() {
+        return Err(String::from("--border-inset only works with --border"));
+    }
+    let border = border.map(|(width, color)| (width, color, border_inset));
+    // a border around the result makes it bigger than the input it is compared to
+    if !metrics.is_empty() && border.is_some() && !border_inset {
+        return Err(String::from("--metrics and --metric can't be used with --border unless it is --border-inset"));
+    }
+    if !metrics.is_empty() && tile.is_some() {
+        return Err(String::from("--metrics and --metric can't be used with --tile"));
+    }
+    // the pixels no longer line up with the input's
+    if !metrics.is_empty() && (flip_horizontal || flip_vertical || rotate != 0) {
+        return Err(String::from("--metrics and --metric can't be used with --flip-horizontal, --flip-vertical or --rotate"));
+    }
+
+    // --force wins so it can be added to a command that already has --resume
+    let resume = resume && !force;
+    if allow_rgb && !strict {
+        return Err(String::from("--allow-rgb needs --strict, inputs without alpha are always accepted otherwise"));
+    }
+    if hue_range == HueRange::Signed && sample_point.is_none() {
+        return Err(String::from("--signed-hue only changes the hues --sample-point prints, it needs --sample-point"));
+    }
+    // skipped images would be missing from the grid
+    if resume && montage.is_some() {
+        return Err(String::from("--resume can't be used with --montage"));
+    }
+
+    let (output, output_template, preview_every) = match &output_dir {
+        Some(_) if archive::is_archive(&output) => return Err(String::from("--output-dir can't be used with a .zip output, the results go into the archive")),
+        Some(dir) => {
+            // joining an absolute path would replace the folder instead of going inside it
+            if Path::new(&output).is_absolute() {
+                return Err(String::from("--output-dir needs -o to be a relative path"));
+            }
+            let dir = Path::new(dir);
+            let inside = |path: &str| dir.join(path).to_string_lossy().into_owned();
+            (
+                inside(&output),
+                output_template.map(|template| template.in_directory(dir)),
+                preview_every.map(|(percent, path)| (percent, inside(&path))),
+            )
+        }
+        None => (output, output_template, preview_every),
+    };
+
+    let mut positional = positional.into_iter();
+    let input = positional.next().ok_or("Missing the image path")?;
+    let common = CommonArgs { input, output, output_template, output_format, output_dir, dpi, preserve_mtime, checksum_log, auto_levels, input_space, tonemap, hue_space, invert_value, equalize_hue, repeat, palette_size, simulate, swap, curve, tint, jitter, glitch, max_shift, tolerance, highlight_only, vignette, alpha_scale, border, flip_horizontal, flip_vertical, rotate, tile, background, threads_affinity, keep_metadata, bit_depth, indexed, interlace, compress_level, raw, preview_term, verbose_errors, strict, allow_rgb, crop, max_dimension, preview_every, metrics, stats, change_mask, sample_point, hue_range, montage, concurrency, schedule, resume, verify_determinism, benchmark };
+
+    let parsed = match command.as_str() {
+        "reflect" => {
+            let kaleidoscope = reflect.kaleidoscope;
+            let angles = reflect_angles(reflect, positional.next(), common.hue_space)?;
+            if kaleidoscope.is_some() && matches!(angles, AngleSource::Repl | AngleSource::ContactSheet { .. } | AngleSource::Chain(_) | AngleSource::HueFrom(_) | AngleSource::Auto { .. } | AngleSource::Suggest | AngleSource::Reference(_)) {
+                return Err(String::from("--kaleidoscope can't be used with --repl, --contact-sheet, --ops, --hue-from, --auto-angle, --suggest-angle or --reference"));
+            }
+            // there is no axis to turn
+            if common.glitch.is_some() && matches!(angles, AngleSource::Chain(_) | AngleSource::HueFrom(_) | AngleSource::Suggest) {
+                return Err(String::from("--glitch can't be used with --ops, --hue-from or --suggest-angle"));
+            }
+            if let Some(template) = &common.output_template {
+                if matches!(angles, AngleSource::Repl) {
+                    return Err(String::from("--output-template can't be used with --repl, save <path> names each result"));
+                }
+                // the automatic angle is only known once the image is loaded, after outputs are named
+                if template.uses_angle() && matches!(angles, AngleSource::Varying(_) | AngleSource::Map(_) | AngleSource::ContactSheet { .. } | AngleSource::Chain(_) | AngleSource::HueFrom(_) | AngleSource::Auto { .. } | AngleSource::Reference(_)) {
+                    return Err(String::from("{angle} in --output-template needs one angle per output, not a gradient, angle map, contact sheet, --ops, --hue-from, --auto-angle or --reference"));
+                }
+            }
+            if common.verify_determinism && matches!(angles, AngleSource::Repl | AngleSource::ContactSheet { .. } | AngleSource::Suggest) {
+                return Err(String::from("--verify-determinism can't be used with --repl, --contact-sheet or --suggest-angle"));
+            }
+            if common.sample_point.is_some() && matches!(angles, AngleSource::Repl | AngleSource::ContactSheet { .. } | AngleSource::Suggest) {
+                return Err(String::from("--sample-point can't be used with --repl, --contact-sheet or --suggest-angle"));
+            }
+            // nothing is made to measure
+            if matches!(angles, AngleSource::Suggest) && (!common.metrics.is_empty() || common.stats || common.montage.is_some()) {
+                return Err(String::from("--metrics, --stats and --montage can't be used with --suggest-angle"));
+            }
+            // the sheet isn't the size of the input, so there is nothing to compare it to
+            if matches!(angles, AngleSource::ContactSheet { .. }) && !common.metrics.is_empty() {
+                return Err(String::from("--metrics and --metric can't be used with --contact-sheet"));
+            }
+            if common.change_mask.is_some() && matches!(angles, AngleSource::Repl | AngleSource::ContactSheet { .. } | AngleSource::Suggest) {
+                return Err(String::from("--change-mask can't be used with --repl, --contact-sheet or --suggest-angle"));
+            }
+            Command::Reflect { common, angles, kaleidoscope }
+        }
+        "rotate" => {
+            let degrees = positional.next().ok_or("Missing the rotation in degrees")?;
+            Command::Rotate { common, degrees: number(&degrees, "Rotation")? }
+        }
+        "chroma-invert" => Command::ChromaInvert { common },
+        _ => Command::Invert { common },
+    };
+    match positional.next() {
+        Some(extra) => Err(format!("Unexpected argument {}", extra)),
+        None => Ok(parsed),
+    }
+}
+
+fn parse_color(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut positional = vec![];
+    let mut hue_range = HueRange::Unsigned;
+    for arg in args {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(Some(String::from("color")))),
+            "--signed-hue" => hue_range = HueRange::Signed,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {} for color", flag)),
+            _ => positional.push(arg),
+        }
+    }
+    let [color, angle] = positional.as_slice() else {
+        return Err(String::from("color needs a hex color and an angle"));
+    };
+    let color = parse_hex(color).ok_or(format!("{} is not a hex color like #3498db", color))?;
+    Ok(Command::Color { color, angle: number(angle, "Angle")?, hue_range })
+}
+
+fn parse_palette(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.peekable();
+    let mut positional = vec![];
+    let mut output = None;
+    let mut swatches = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(Some(String::from("palette")))),
+            "-o" | "--output" => output = Some(value(&mut args, &arg)?),
+            "--swatches" => swatches = Some(value(&mut args, &arg)?),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {} for palette", flag)),
+            _ => positional.push(arg),
+        }
+    }
+    let [input, angle] = positional.as_slice() else {
+        return Err(String::from("palette needs a palette file and an angle"));
+    };
+    Ok(Command::Palette { input: input.clone(), angle: number(angle, "Angle")?, output, swatches })
+}
+
+// degrees --glitch turns the axis by at most when --glitch-amount isn't given
+const DEFAULT_GLITCH_AMOUNT: f32 = 10.;
+
+// environment variable with the angle to use when reflect is run without one
+const ANGLE_VARIABLE: &str = "HUE_REFLECT_ANGLE";
+
+fn reflect_angles(reflect: ReflectArgs, angle: Option<String>, hue_space: HueSpace) -> Result<AngleSource, String> {
+    let sources = [
+        reflect.angles_file.is_some(),
+        reflect.angle_map.is_some(),
+        reflect.angle_gradient.is_some(),
+        reflect.gradient_angle.is_some(),
+        reflect.radial_angle.is_some(),
+        reflect.repl,
+        reflect.contact_sheet.is_some(),
+        reflect.axis_color.is_some(),
+        reflect.axis_by_saturation.is_some(),
+        reflect.ops.is_some(),
+        reflect.hue_from.is_some(),
+        reflect.auto_angle,
+        reflect.suggest_angle,
+        reflect.reference.is_some(),
+    ];
+    match sources.iter().filter(|&&given| given).count() {
+        0 => {}
+        1 if angle.is_none() => {}
+        1 => return Err(String::from("An angle argument can't be combined with the angle options")),
+        _ => return Err(String::from("Only one of --angles-file, --angle-map, --angle-gradient, --gradient-angle, --radial-angle, --repl, --contact-sheet, --axis-color, --axis-by-saturation, --ops, --hue-from, --auto-angle, --suggest-angle and --reference can be used")),
+    }
+
+    if reflect.gradient_angle.is_some() && reflect.gradient_direction.is_some() {
+        return Err(String::from("--gradient-angle always runs top to bottom, use --angle-gradient to pick a direction"));
+    }
+    if reflect.sheet_labels && reflect.contact_sheet.is_none() {
+        return Err(String::from("--sheet-labels only works with --contact-sheet"));
+    }
+    if reflect.target_change.is_some() && !reflect.auto_angle {
+        return Err(String::from("--target-change only works with --auto-angle"));
+    }
+    if reflect.overflow.is_some() && reflect.ops.is_none() {
+        return Err(String::from("--overflow only works with --ops, it changes the saturation stages"));
+    }
+    // oklch chroma has no fixed top to wrap at, colors out of srgb are brought back in instead
+    if reflect.overflow == Some(Overflow::Wrap) && hue_space == HueSpace::Oklab {
+        return Err(String::from("--overflow wrap can't be used with --space oklab"));
+    }
+
+    if let Some(color) = reflect.axis_color {
+        let [hue, saturation, _] = rgb_to_hsv(&color).0;
+        // grays have no hue to reflect about
+        if saturation == 0. {
+            return Err(format!("{} is a gray, pick a color with some saturation for --axis-color", palette::to_hex(color.0)));
+        }
+        // the axis has to be measured the same way as the hues it reflects
+        match hue_space {
+            HueSpace::Hsv => Ok(AngleSource::Single(hue)),
+            HueSpace::Oklab => Ok(AngleSource::Single(oklab_chroma_hue(&rgb_to_oklab(&color)).1)),
+        }
+    } else if reflect.repl {
+        Ok(AngleSource::Repl)
+    } else if let Some((step, columns)) = reflect.contact_sheet {
+        if !(step > 0. && step <= 180.) {
+            return Err(String::from("Contact sheet step must be above 0 and at most 180 degrees"));
+        }
+        if !(columns >= 1. && columns.fract() == 0.) {
+            return Err(String::from("Contact sheet columns must be a whole number above 0"));
+        }
+        Ok(AngleSource::ContactSheet { step, columns: columns as u32, labels: reflect.sheet_labels })
+    } else if let Some(path) = reflect.angles_file {
+        Ok(AngleSource::File(path))
+    } else if let Some(path) = reflect.angle_map {
+        Ok(AngleSource::Map(path))
+    } else if let Some((start, end)) = reflect.angle_gradient {
+        let direction = reflect.gradient_direction.unwrap_or(Direction::Horizontal);
+        Ok(AngleSource::Varying(Axis::Gradient { start, end, direction }))
+    } else if let Some((start, end)) = reflect.gradient_angle {
+        Ok(AngleSource::Varying(Axis::Gradient { start, end, direction: Direction::Vertical }))
+    } else if let Some((center_angle, edge_angle)) = reflect.radial_angle {
+        Ok(AngleSource::Varying(Axis::Radial { center_angle, edge_angle, center: reflect.center }))
+    } else if reflect.auto_angle {
+        Ok(AngleSource::Auto { target: reflect.target_change })
+    } else if reflect.suggest_angle {
+        Ok(AngleSource::Suggest)
+    } else if let Some(path) = reflect.reference {
+        Ok(AngleSource::Reference(path))
+    } else if let Some(chain) = reflect.ops {
+        Ok(AngleSource::Chain(chain.with_overflow(reflect.overflow.unwrap_or(Overflow::Clamp))))
+    } else if let Some(path) = reflect.hue_from {
+        Ok(AngleSource::HueFrom(path))
+    } else if let Some((low, high, threshold)) = reflect.axis_by_saturation {
+        Ok(AngleSource::Varying(Axis::BySaturation { low, high, threshold }))
+    } else {
+        // the argument wins, the variable is only a default for scripts that can't easily add one
+        match angle {
+            Some(angle) => Ok(AngleSource::Single(number(&angle, "Angle")?)),
+            None => {
+                let angle = env::var(ANGLE_VARIABLE).map_err(|_| format!("Missing the reflect angle, give it as an argument or set {}", ANGLE_VARIABLE))?;
+                Ok(AngleSource::Single(number(&angle, ANGLE_VARIABLE)?))
+            }
+        }
+    }
+}
+
+pub fn is_raw_path(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("raw"))
+}
+
+fn value(args: &mut Peekable<impl Iterator<Item = String>>, flag: &str) -> Result<String, String> {
+    args.next().ok_or(format!("{} needs a value", flag))
+}
+
+fn number(value: &str, name: &str) -> Result<f32, String> {
+    value.parse::<f32>().map_err(|_| format!("{} must be number", name))
+}
+
+// parses stages like reflect:90,rotate:30,saturation:1.2,brightness:0.9
+fn parse_chain(value: &str) -> Result<Chain, String> {
+    let mut stages = vec![];
+    for stage in value.split(',') {
+        let (name, amount) = stage.split_once(':').ok_or(format!("Stage {} must be name:number, like rotate:30", stage.trim()))?;
+        let amount = number(amount.trim(), &format!("The value of {}", name.trim()))?;
+        if !amount.is_finite() {
+            return Err(format!("The value of {} must be a finite number", name.trim()));
+        }
+        stages.push(match name.trim() {
+            "reflect" => Stage::Reflect(amount),
+            "rotate" => Stage::Rotate(amount),
+            "saturation" if amount >= 0. => Stage::Saturation(amount),
+            "saturation" => return Err(String::from("Saturation factor must be 0 or more")),
+            "brightness" if amount >= 0. => Stage::Brightness(amount),
+            "brightness" => return Err(String::from("Brightness factor must be 0 or more")),
+            name => return Err(format!("Unknown stage {}, use reflect, rotate, saturation or brightness", name)),
+        });
+    }
+    Chain::new(&stages).ok_or(format!("--ops takes at most {} stages", MAX_STAGES))
+}
+
+// parses two comma separated numbers, like 0,180
+fn pair(value: &str, name: &str, format: &str) -> Result<(f32, f32), String> {
+    let parsed = value.split_once(',').and_then(|(first, second)| {
+        Some((first.trim().parse().ok()?, second.trim().parse().ok()?))
+    });
+    parsed.ok_or(format!("{} must be two numbers, {}", name, format))
+}
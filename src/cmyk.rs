@@ -0,0 +1,164 @@
+use std::{fs::{self, File}, io::BufReader, path::Path};
+
+use image::{DynamicImage, ImageBuffer};
+use tiff::{decoder::{Decoder, DecodingResult}, tags::Tag, ColorType};
+
+// tiff tag an embedded icc profile is stored under
+const TIFF_ICC_TAG: u16 = 34675;
+// what a jpeg APP2 segment starts with when it holds an icc profile
+const JPEG_ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+
+pub struct CmykSource {
+    // tiffs go through decode_tiff, the image crate already converts cmyk jpegs
+    pub tiff: bool,
+    // whether the file carries its own icc profile, which can't be applied here
+    pub has_profile: bool,
+}
+
+// looks for four channel cmyk tiffs and jpegs, None for anything else
+pub fn detect(path: &str) -> Option<CmykSource> {
+    let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "tif" | "tiff" => detect_tiff(path),
+        "jpg" | "jpeg" => detect_jpeg(path),
+        _ => None,
+    }
+}
+
+fn detect_tiff(path: &str) -> Option<CmykSource> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path).ok()?)).ok()?;
+    if !matches!(decoder.colortype().ok()?, ColorType::CMYK(_)) {
+        return None;
+    }
+    let has_profile = decoder.find_tag(Tag::Unknown(TIFF_ICC_TAG)).ok()?.is_some();
+    Some(CmykSource { tiff: true, has_profile })
+}
+
+fn detect_jpeg(path: &str) -> Option<CmykSource> {
+    let bytes = fs::read(path).ok()?;
+    if bytes.get(0..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut has_profile = false;
+    let mut position = 2;
+    // the frame header tells the number of components, it comes before the scan data
+    while let [0xFF, marker, high, low, ..] = bytes[position..] {
+        let length = u16::from_be_bytes([high, low]) as usize;
+        let data = bytes.get(position + 4..position + 2 + length)?;
+        match marker {
+            0xE2 if data.starts_with(JPEG_ICC_HEADER) => has_profile = true,
+            // baseline, extended and progressive frames all put the component count at byte 5
+            0xC0..=0xC2 => return (*data.get(5)? == 4).then_some(CmykSource { tiff: false, has_profile }),
+            0xDA => break,
+            _ => {}
+        }
+        position += 2 + length;
+    }
+    None
+}
+
+// decodes a detected cmyk source into rgb
+pub fn decode(path: &str, source: &CmykSource) -> Result<DynamicImage, String> {
+    if source.tiff {
+        decode_tiff(path)
+    } else {
+        image::open(path).map_err(|e| format!("Failed to open image: {}", e))
+    }
+}
+
+// decodes a cmyk tiff of 8 or 16 bits per channel into rgb of the same depth,
+// the image crate only takes 8 bit ones
+fn decode_tiff(path: &str) -> Result<DynamicImage, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open image: {}", e))?;
+    let mut decoder = Decoder::new(BufReader::new(file)).map_err(|e| format!("Failed to open image: {}", e))?;
+    let (width, height) = decoder.dimensions().map_err(|e| format!("Failed to open image: {}", e))?;
+    let data = decoder.read_image().map_err(|e| format!("Failed to open image: {}", e))?;
+    let img = match data {
+        DecodingResult::U8(data) => {
+            let rgb = data.chunks_exact(4).flat_map(|cmyk| to_rgb(cmyk, u8::MAX as f32).map(|c| c as u8)).collect();
+            ImageBuffer::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+        }
+        DecodingResult::U16(data) => {
+            let rgb = data.chunks_exact(4).flat_map(|cmyk| to_rgb(cmyk, u16::MAX as f32).map(|c| c as u16)).collect();
+            ImageBuffer::from_raw(width, height, rgb).map(DynamicImage::ImageRgb16)
+        }
+        _ => None,
+    };
+    img.ok_or_else(|| "Failed to open image: only 8 and 16 bit cmyk tiffs are supported".to_string())
+}
+
+// the naive conversion, ink subtracts from white and black darkens what is left
+fn to_rgb<T: Copy + Into<f32>>(cmyk: &[T], max: f32) -> [f32; 3] {
+    let [c, m, y, k] = [0, 1, 2, 3].map(|i| cmyk[i].into() / max);
+    [c, m, y].map(|ink| ((1. - ink) * (1. - k) * max).round())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // category of a jpeg dc difference and its bits, negative ones stored as one less than
+    // the next power of two above them
+    fn magnitude(diff: i32) -> (u8, u32) {
+        let category = (32 - diff.unsigned_abs().leading_zeros()) as u8;
+        let bits = if diff < 0 { (diff + (1 << category) - 1) as u32 } else { diff as u32 };
+        (category, bits)
+    }
+
+    // a baseline 8x8 jpeg of four flat components, tagged as adobe cmyk, which stores its
+    // channels inverted (255 is no ink), like the files photoshop writes
+    fn flat_cmyk_jpeg(stored: [u8; 4]) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        let mut segment = |marker: u8, data: &[u8]| {
+            jpeg.extend([0xFF, marker]);
+            jpeg.extend(((data.len() + 2) as u16).to_be_bytes());
+            jpeg.extend(data);
+        };
+        segment(0xEE, b"Adobe\x00\x64\x00\x00\x00\x00\x00");
+        segment(0xDB, &[[0].as_slice(), &[1; 64]].concat());
+        segment(0xC0, &[8, 0, 8, 0, 8, 4, 1, 0x11, 0, 2, 0x11, 0, 3, 0x11, 0, 4, 0x11, 0]);
+        // dc categories 0 to 11 all 4 bits long, ac only has end of block, 1 bit long
+        let mut dc = vec![0x00, 0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        dc.extend(0..12);
+        segment(0xC4, &dc);
+        segment(0xC4, &[0x10, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00]);
+        segment(0xDA, &[4, 1, 0x00, 2, 0x00, 3, 0x00, 4, 0x00, 0, 63, 0]);
+        let mut bits: Vec<bool> = vec![];
+        let mut push = |value: u32, length: u8| bits.extend((0..length).rev().map(|i| value >> i & 1 == 1));
+        for value in stored {
+            // the dc coefficient of a flat block is 8 times its level shifted value
+            let (category, extra) = magnitude(8 * (value as i32 - 128));
+            push(category as u32, 4);
+            push(extra, category);
+            push(0, 1);
+        }
+        bits.resize(bits.len().div_ceil(8) * 8, true);
+        for byte in bits.chunks(8).map(|bits| bits.iter().fold(0u8, |byte, &bit| byte << 1 | bit as u8)) {
+            jpeg.push(byte);
+            if byte == 0xFF {
+                jpeg.push(0);
+            }
+        }
+        jpeg.extend([0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn cmyk_jpeg_decodes_to_the_expected_rgb() {
+        let dir = std::env::temp_dir().join(format!("color_reflect-test-{}-cmyk", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // no cyan, half magenta, full yellow, then the same under some black
+        for (name, stored, expected) in [("orange.jpg", [255, 128, 0, 255], [255, 128, 0]), ("dark.jpg", [255, 128, 0, 150], [150, 75, 0])] {
+            let path = dir.join(name).to_string_lossy().into_owned();
+            fs::write(&path, flat_cmyk_jpeg(stored)).unwrap();
+            let source = detect(&path).unwrap_or_else(|| panic!("{} isn't taken as cmyk", name));
+            assert!(!source.tiff && !source.has_profile);
+            let rgb = decode(&path, &source).unwrap().to_rgb8();
+            assert_eq!(rgb.dimensions(), (8, 8));
+            for pixel in rgb.pixels() {
+                assert!(pixel.0.iter().zip(expected).all(|(&got, want)| got.abs_diff(want) <= 2), "{}: {:?} instead of {:?}", name, pixel.0, expected);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
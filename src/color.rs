@@ -0,0 +1,514 @@
+use image::Rgb;
+
+use crate::space::{decode, encode, multiply};
+
+// rgb↔hsv conversion functions taken from https://gist.github.com/bmgxyz/a5b5b58e492cbca099b468eddd04cc97
+
+pub struct Hsv(pub [f32; 3]);
+
+// the range hues are shown in, the conversions and hue operations take a hue from either
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum HueRange {
+    // 0 to 360, rgb_to_hsv's own hues for reds just under 360 are slightly negative instead,
+    // which keeps their precision for the sector math, moving them up a turn can round them
+    // by a hair so this is for showing hues rather than feeding them back in
+    #[default]
+    Unsigned,
+    // -180 to 180, for tools that measure hues either way from red
+    Signed,
+}
+
+impl HueRange {
+    // the same hue moved into the range by whole turns, hues already in it are left as they are
+    pub fn apply(self, hue: f32) -> f32 {
+        match self {
+            HueRange::Unsigned if (0. ..360.).contains(&hue) => hue,
+            HueRange::Signed if (-180. ..180.).contains(&hue) => hue,
+            // a turn taken off a hue from 180 to 540 is exact, so the color doesn't move
+            HueRange::Signed if (180. ..540.).contains(&hue) => hue - 360.,
+            HueRange::Unsigned => wrap_turn(hue),
+            HueRange::Signed => wrap_turn(hue + 180.) - 180.,
+        }
+    }
+}
+
+// rem_euclid rounds hues a hair under a whole turn up to 360
+fn wrap_turn(hue: f32) -> f32 {
+    let wrapped = hue.rem_euclid(360.);
+    if wrapped >= 360. { 0. } else { wrapped }
+}
+
+// signed hues under -60 back to where rgb_to_hsv puts them, adding the turn back gives exactly the
+// hue HueRange::Signed took it off, so the operations below give the same colors in either range
+fn unsigned_hue(hue: f32) -> f32 {
+    if hue < -60. { HueRange::Unsigned.apply(hue) } else { hue }
+}
+
+impl Hsv {
+    // the same color with its hue in range
+    pub fn in_range(self, range: HueRange) -> Hsv {
+        let [hue, saturation, value] = self.0;
+        Hsv([range.apply(hue), saturation, value])
+    }
+}
+
+pub fn rgb_to_hsv(pixel: &Rgb<u8>) -> Hsv {
+    let [r, g, b] = pixel.0;
+    let big_m = *[r, g, b].iter().max().unwrap() as f32 / 255.;
+    let little_m = *[r, g, b].iter().min().unwrap() as f32 / 255.;
+    let c = big_m - little_m;
+    // black has no saturation, rather than 0/0
+    let s = if big_m == 0. { 0. } else { (c / big_m) * 100. };
+    let (little_r, little_g, little_b) = (r as f32 / 255., g as f32 / 255., b as f32 / 255.);
+    let (big_r, big_g, big_b) = (
+        (big_m - little_r) / c,
+        (big_m - little_g) / c,
+        (big_m - little_b) / c,
+    );
+    let h_prime = match big_m {
+        x if x == little_m => 0.,
+        x if x == little_r => big_b - big_g,
+        x if x == little_g => 2. + big_r - big_b,
+        x if x == little_b => 4. + big_g - big_r,
+        _ => unreachable!(),
+    };
+    let h = h_prime / 6. * 360.;
+    let v = big_m * 100.;
+    Hsv([h, s, v])
+}
+
+pub fn hsv_to_rgb(pixel: &Hsv) -> Rgb<u8> {
+    let [hue, saturation, value] = sanitize_hsv(pixel);
+    let [saturation, value] = [saturation / 100., value / 100.];
+    let max = value;
+    let c = saturation * value;
+    let min = max - c;
+    // each sector edge (0, 60, ... 300) falls in the arm that starts there, whose formula already gives
+    // the exact primary or secondary color at it, 360 is wrapped to 0 by sanitize_hsv
+    let h_prime = if hue >= 300. {
+        (hue - 360.) / 60.
+    } else {
+        hue / 60.
+    };
+    let (r, g, b) = match h_prime {
+        x if (-1. ..1.).contains(&x) => {
+            if h_prime < 0. {
+                (max, min, min - h_prime * c)
+            } else {
+                (max, min + h_prime * c, min)
+            }
+        }
+        x if (1. ..3.).contains(&x) => {
+            if h_prime < 2. {
+                (min - (h_prime - 2.) * c, max, min)
+            } else {
+                (min, max, min + (h_prime - 2.) * c)
+            }
+        }
+        x if (3. ..5.).contains(&x) => {
+            if h_prime < 4. {
+                (min, min - (h_prime - 4.) * c, max)
+            } else {
+                (min + (h_prime - 4.) * c, min, max)
+            }
+        }
+        _ => unreachable!(),
+    };
+    Rgb([channel_to_u8(r), channel_to_u8(g), channel_to_u8(b)])
+}
+
+// replaces non-finite channels with 0 and wraps hues that fall outside
+// -60..360 so every branch of hsv_to_rgb can be reached safely
+fn sanitize_hsv(pixel: &Hsv) -> [f32; 3] {
+    let [hue, saturation, value] = pixel.0.map(|channel| if channel.is_finite() { channel } else { 0. });
+    let hue = if (-60. ..360.).contains(&hue) { hue } else { hue.rem_euclid(360.) };
+    [hue, saturation, value]
+}
+
+// last line of defence before the cast, NaN and infinities become 0
+// channels are cut down to the step below, after a nudge of a thousandth of a step so that float
+// error leaving a whole step a hair short (99.99999 for 100) doesn't drop the channel by one,
+// which would also make a hue given in either HueRange come out a step apart
+fn channel_to_u8(channel: f32) -> u8 {
+    if channel.is_finite() {
+        (channel.clamp(0., 1.) * 255. + CHANNEL_NUDGE) as u8
+    } else {
+        0
+    }
+}
+
+const CHANNEL_NUDGE: f32 = 1e-3;
+
+// --tolerance when it is left out, tolerances are fractions of the full range of what they bound,
+// of a channel for is_gray, of the oklch lightness scale for chroma and of a whole turn for hues
+// off the axis, so this takes only exact 8 bit grays and hues a thousandth of a degree off
+pub const DEFAULT_TOLERANCE: f32 = 1e-3 / 360.;
+
+// hues on the reflection axis, or opposite it, map to themselves
+pub fn is_fixed_point(hue: f32, reflect_angle: f32) -> bool {
+    is_fixed_point_within(hue, reflect_angle, DEFAULT_TOLERANCE)
+}
+
+// is_fixed_point with hues up to tolerance of a whole turn (tolerance * 360 degrees) off the axis
+// counting as on it
+pub fn is_fixed_point_within(hue: f32, reflect_angle: f32, tolerance: f32) -> bool {
+    let degrees = tolerance * 360.;
+    let offset = (unsigned_hue(hue) - reflect_angle).rem_euclid(180.);
+    !(degrees..=180. - degrees).contains(&offset)
+}
+
+// whether a color is close enough to gray to have no hue worth moving, with its highest and lowest
+// channel at most tolerance apart (as a fraction of full), the default only takes exact grays
+pub fn is_gray(color: Rgb<u8>, tolerance: f32) -> bool {
+    let [r, g, b] = color.0;
+    (r.max(g).max(b) - r.min(g).min(b)) as f32 / 255. <= tolerance
+}
+
+// hue reflection algorithm
+#[inline]
+pub fn hsv_reflect(pixel: &Hsv, reflect_angle: f32) -> Hsv {
+    hsv_reflect_within(pixel, reflect_angle, DEFAULT_TOLERANCE)
+}
+
+// hsv_reflect, leaving hues within tolerance of the axis (see is_fixed_point_within) where they are
+#[inline]
+pub fn hsv_reflect_within(pixel: &Hsv, reflect_angle: f32, tolerance: f32) -> Hsv {
+    let [hue, saturation, value] = [unsigned_hue(pixel.0[0]), pixel.0[1], pixel.0[2]];
+
+    // keep hues on the axis exactly instead of letting rounding in the sum nudge them off
+    if is_fixed_point_within(hue, reflect_angle, tolerance) {
+        return Hsv([hue, saturation, value]);
+    }
+
+    // for a hue angle C and reflection angle A
+    // output angle is 360-(C-A)+A mod 360
+    // or, 360-C+2A mod 360
+    // rem_euclid keeps the result in 0..360 even for negative angles,
+    // where % would leave it negative, 2A is brought into 0..360 first so
+    // that large angles don't swallow the hue's precision
+    let angle = (360. - hue + (2.*reflect_angle).rem_euclid(360.)).rem_euclid(360.);
+
+    Hsv([angle, saturation, value])
+}
+
+// how far apart two hues can be, going the short way round, and still count as the same
+const INVOLUTION_EPSILON: f32 = 1e-3;
+
+// whether reflecting along reflect_angle twice gives back every hue of a 0.1 degree grid,
+// with every result inside 0..360, which makes the hue mapping its own inverse and so a bijection
+pub fn is_involution(reflect_angle: f32) -> bool {
+    // a NaN angle leaves every hue alone, which would pass without being a reflection
+    reflect_angle.is_finite() && (0..3600).all(|step| {
+        let hue = step as f32 / 10.;
+        let once = hsv_reflect(&Hsv([hue, 100., 100.]), reflect_angle).0[0];
+        let twice = hsv_reflect(&Hsv([once, 100., 100.]), reflect_angle).0[0];
+        let distance = (twice - hue).rem_euclid(360.);
+        (0. ..360.).contains(&once) && distance.min(360. - distance) <= INVOLUTION_EPSILON
+    })
+}
+
+// the line a hue is reflected across when the wheel is split by folds evenly spaced rays from
+// reflect_angle, the ray closest to the hue, so each sector is mirrored onto itself
+pub fn kaleidoscope_axis(hue: f32, reflect_angle: f32, folds: u32) -> f32 {
+    let sector = 360. / folds as f32;
+    let nearest = ((unsigned_hue(hue) - reflect_angle).rem_euclid(360.) / sector).round() * sector;
+    // rays 180 degrees apart make the same line, this keeps 1 and 2 folds identical to hsv_reflect
+    reflect_angle + nearest.rem_euclid(180.)
+}
+
+// hue rotation, shifts every hue by the same number of degrees
+#[inline]
+pub fn hsv_rotate(pixel: &Hsv, degrees: f32) -> Hsv {
+    let [hue, saturation, value] = [unsigned_hue(pixel.0[0]), pixel.0[1], pixel.0[2]];
+    Hsv([(hue + degrees).rem_euclid(360.), saturation, value])
+}
+
+// reflects a single rgb color along the line at reflect_angle degrees from red
+pub fn reflect_color(color: &Rgb<u8>, reflect_angle: f32) -> Rgb<u8> {
+    let hsv = rgb_to_hsv(color);
+    // the round trip through hsv can be off by one step, colors on the axis are returned as they are
+    if is_fixed_point(hsv.0[0], reflect_angle) {
+        return *color;
+    }
+    hsv_to_rgb(&hsv_reflect(&hsv, reflect_angle))
+}
+
+// oklab conversion from https://bottosson.github.io/posts/oklab/
+// lightness 0-1 and the two opponent axes a (green to red) and b (blue to yellow)
+pub struct Oklab(pub [f32; 3]);
+
+const SRGB_TO_LMS: [[f32; 3]; 3] = [
+    [0.41222147, 0.53633254, 0.051445993],
+    [0.2119035, 0.6806995, 0.10739696],
+    [0.08830246, 0.28171884, 0.6299787],
+];
+
+const LMS_TO_OKLAB: [[f32; 3]; 3] = [
+    [0.21045426, 0.7936178, -0.004072047],
+    [1.9779985, -2.4285922, 0.4505937],
+    [0.025904037, 0.78277177, -0.80867577],
+];
+
+const OKLAB_TO_LMS: [[f32; 3]; 3] = [
+    [1., 0.39633778, 0.21580376],
+    [1., -0.105561346, -0.06385417],
+    [1., -0.08948418, -1.2914855],
+];
+
+const LMS_TO_SRGB: [[f32; 3]; 3] = [
+    [4.0767417, -3.3077116, 0.23096993],
+    [-1.268438, 2.6097574, -0.34131938],
+    [-0.0041960863, -0.7034186, 1.7076147],
+];
+
+pub fn rgb_to_oklab(pixel: &Rgb<u8>) -> Oklab {
+    let linear = pixel.0.map(|c| decode(c as f32 / 255.));
+    let lms = multiply(&SRGB_TO_LMS, linear).map(f32::cbrt);
+    Oklab(multiply(&LMS_TO_OKLAB, lms))
+}
+
+fn oklab_to_linear(pixel: &Oklab) -> [f32; 3] {
+    let lms = multiply(&OKLAB_TO_LMS, pixel.0).map(|c| c * c * c);
+    multiply(&LMS_TO_SRGB, lms)
+}
+
+// colors outside srgb lose chroma until they fit, keeping their lightness and hue
+// rather than clipping each channel, which would shift the hue
+pub fn oklab_to_rgb(pixel: &Oklab) -> Rgb<u8> {
+    let in_gamut = |linear: &[f32; 3]| linear.iter().all(|c| (-1e-4..=1. + 1e-4).contains(c));
+    let mut linear = oklab_to_linear(pixel);
+    if !in_gamut(&linear) {
+        let [lightness, a, b] = pixel.0;
+        let (mut low, mut high) = (0., 1.);
+        for _ in 0..16 {
+            let scale = (low + high) / 2.;
+            if in_gamut(&oklab_to_linear(&Oklab([lightness, a * scale, b * scale]))) {
+                low = scale;
+            } else {
+                high = scale;
+            }
+        }
+        linear = oklab_to_linear(&Oklab([lightness, a * low, b * low]));
+    }
+    Rgb(linear.map(|c| (encode(c.clamp(0., 1.)) * 255.).round() as u8))
+}
+
+// the oklch chroma and hue, hue in degrees 0-360 from the +a axis (a pinkish red)
+pub fn oklab_chroma_hue(pixel: &Oklab) -> (f32, f32) {
+    let [_, a, b] = pixel.0;
+    (a.hypot(b), b.atan2(a).to_degrees().rem_euclid(360.))
+}
+
+fn from_chroma_hue(lightness: f32, chroma: f32, hue: f32) -> Oklab {
+    let (sin, cos) = hue.to_radians().sin_cos();
+    Oklab([lightness, chroma * cos, chroma * sin])
+}
+
+// hsv_reflect on the oklch hue, lightness and chroma stay the same
+pub fn oklab_reflect(pixel: &Oklab, reflect_angle: f32) -> Oklab {
+    let (chroma, hue) = oklab_chroma_hue(pixel);
+    from_chroma_hue(pixel.0[0], chroma, (360. - hue + (2. * reflect_angle).rem_euclid(360.)).rem_euclid(360.))
+}
+
+pub fn oklab_rotate(pixel: &Oklab, degrees: f32) -> Oklab {
+    let (chroma, hue) = oklab_chroma_hue(pixel);
+    from_chroma_hue(pixel.0[0], chroma, (hue + degrees).rem_euclid(360.))
+}
+
+// the opposite point of the a/b plane, the same as turning the oklch hue by 180,
+// lightness and chroma stay the same
+pub fn oklab_chroma_invert(pixel: &Oklab) -> Oklab {
+    Oklab([pixel.0[0], -pixel.0[1], -pixel.0[2]])
+}
+
+// flips brightness, keeping hue and saturation
+pub fn hsv_invert_value(pixel: &Hsv) -> Hsv {
+    let [hue, saturation, value] = [pixel.0[0], pixel.0[1], pixel.0[2]];
+    Hsv([hue, saturation, 100. - value])
+}
+
+// parses #rrggbb, the # is optional
+pub fn parse_hex(value: &str) -> Option<Rgb<u8>> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+// stretches value so that the range min..max covers the full 0-100
+pub fn hsv_stretch_value(pixel: &Hsv, min: f32, max: f32) -> Hsv {
+    let [hue, saturation, value] = [pixel.0[0], pixel.0[1], pixel.0[2]];
+    Hsv([hue, saturation, (value - min) / (max - min) * 100.])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn red_magenta_hue_in_either_range() {
+        let hsv = rgb_to_hsv(&Rgb([255, 0, 128]));
+        assert_eq!(format!("{:.1}", Hsv(hsv.0).in_range(HueRange::Unsigned).0[0]), "329.9");
+        assert_eq!(format!("{:.1}", hsv.in_range(HueRange::Signed).0[0]), "-30.1");
+    }
+
+    // the textbook conversion in f64, each channel from how far its own anchor hue is
+    fn reference_hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> Rgb<u8> {
+        let channel = |n: f64| {
+            let k = (n + hue / 60.).rem_euclid(6.);
+            let (saturation, value) = (saturation / 100., value / 100.);
+            let level = value - value * saturation * k.min(4. - k).clamp(0., 1.);
+            (level * 255.).round() as u8
+        };
+        Rgb([channel(5.), channel(3.), channel(1.)])
+    }
+
+    #[test]
+    fn sector_edges_give_primaries_and_secondaries() {
+        let edges = [
+            (0., [255, 0, 0]),
+            (60., [255, 255, 0]),
+            (120., [0, 255, 0]),
+            (180., [0, 255, 255]),
+            (240., [0, 0, 255]),
+            (300., [255, 0, 255]),
+            (360., [255, 0, 0]),
+        ];
+        for (hue, rgb) in edges {
+            let color = hsv_to_rgb(&Hsv([hue, 100., 100.]));
+            assert_eq!(color, Rgb(rgb), "hue {}", hue);
+            assert_eq!(color, reference_hsv_to_rgb(hue as f64, 100., 100.), "hue {}", hue);
+            // and back, rgb_to_hsv gives magenta as -60 and red as 0, the same edges a turn apart
+            let [back, saturation, value] = rgb_to_hsv(&color).0;
+            assert_eq!((back - hue).rem_euclid(360.), 0., "hue {}", hue);
+            assert_eq!((saturation, value), (100., 100.), "hue {}", hue);
+            assert_eq!(hsv_to_rgb(&rgb_to_hsv(&color)), color, "hue {}", hue);
+        }
+    }
+
+    #[test]
+    fn sector_edges_match_the_reference() {
+        for hue in [0., 60., 120., 180., 240., 300., 360.] {
+            for (saturation, value) in [(100., 50.), (50., 100.), (25., 60.), (0., 80.)] {
+                let color = hsv_to_rgb(&Hsv([hue, saturation, value]));
+                let expected = reference_hsv_to_rgb(hue as f64, saturation as f64, value as f64);
+                // hsv_to_rgb truncates where the reference rounds, so they can be a step apart
+                for (got, want) in color.0.iter().zip(expected.0) {
+                    assert!(got.abs_diff(want) <= 1, "hue {} saturation {} value {}: {:?} against {:?}", hue, saturation, value, color, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reflect_matches_the_old_formula() {
+        for step in 0..3600 {
+            let hue = step as f32 / 10.;
+            for angle in [-270., -90., -12.5, 0., 1., 37.5, 90., 131., 179.9, 180., 270., 359.] {
+                let reflected = hsv_reflect(&Hsv([hue, 100., 100.]), angle).0[0];
+                // the expression before rem_euclid, which could come out negative
+                let old = (360. - hue + 2. * angle) % 360.;
+                let distance = (reflected - old).rem_euclid(360.);
+                assert!((0. ..360.).contains(&reflected), "hue {} angle {}: {}", hue, angle, reflected);
+                assert!(distance.min(360. - distance) <= 1e-4, "hue {} angle {}: {} against {}", hue, angle, reflected, old);
+            }
+        }
+    }
+
+    #[test]
+    fn reflecting_twice_gives_back_the_hue() {
+        let angles = [-450., -90., 0., 15., 45.5, 90., 123.25, 180., 300., 720.];
+        for angle in angles {
+            assert!(is_involution(angle), "angle {}", angle);
+            for step in 0..720 {
+                let hue = step as f32 / 2.;
+                let once = hsv_reflect(&Hsv([hue, 80., 60.]), angle);
+                let [twice, saturation, value] = hsv_reflect(&once, angle).0;
+                let distance = (twice - hue).rem_euclid(360.);
+                assert!(distance.min(360. - distance) <= INVOLUTION_EPSILON, "hue {} angle {}: {}", hue, angle, twice);
+                assert_eq!((saturation, value), (80., 60.));
+            }
+        }
+        assert!(!is_involution(f32::NAN));
+    }
+
+    #[test]
+    fn non_finite_channels_convert_as_zero() {
+        let (nan, inf) = (f32::NAN, f32::INFINITY);
+        assert_eq!(hsv_to_rgb(&Hsv([nan, 100., 100.])), Rgb([255, 0, 0]));
+        assert_eq!(hsv_to_rgb(&Hsv([-inf, 100., 100.])), Rgb([255, 0, 0]));
+        assert_eq!(hsv_to_rgb(&Hsv([120., nan, 50.])), hsv_to_rgb(&Hsv([120., 0., 50.])));
+        assert_eq!(hsv_to_rgb(&Hsv([120., inf, 50.])), hsv_to_rgb(&Hsv([120., 0., 50.])));
+        assert_eq!(hsv_to_rgb(&Hsv([120., 100., nan])), Rgb([0, 0, 0]));
+        assert_eq!(hsv_to_rgb(&Hsv([nan, nan, nan])), Rgb([0, 0, 0]));
+        for channel in [nan, inf, -inf] {
+            assert_eq!(channel_to_u8(channel), 0, "{}", channel);
+        }
+    }
+
+    #[test]
+    fn a_color_on_the_axis_keeps_its_rgb() {
+        for rgb in [[255, 128, 0], [52, 152, 219], [10, 200, 99], [255, 0, 128], [1, 2, 3], [200, 40, 41]] {
+            let color = Rgb(rgb);
+            let hue = rgb_to_hsv(&color).0[0];
+            for axis in [hue, hue + 180., hue - 360.] {
+                assert_eq!(reflect_color(&color, axis), color, "{:?} about {}", rgb, axis);
+                assert_eq!(hsv_reflect(&rgb_to_hsv(&color), axis).0[0], unsigned_hue(hue), "{:?} about {}", rgb, axis);
+            }
+        }
+    }
+
+    #[test]
+    fn oklab_round_trip_gives_back_the_color() {
+        for r in (0..=255).step_by(17) {
+            for g in (0..=255).step_by(17) {
+                for b in (0..=255).step_by(17) {
+                    let color = Rgb([r as u8, g as u8, b as u8]);
+                    assert_eq!(oklab_to_rgb(&rgb_to_oklab(&color)), color);
+                }
+            }
+        }
+        // white is lightness 1 with no chroma
+        let [lightness, a, b] = rgb_to_oklab(&Rgb([255, 255, 255])).0;
+        assert!((lightness - 1.).abs() < 1e-3 && a.abs() < 1e-3 && b.abs() < 1e-3);
+    }
+
+    #[test]
+    fn blue_reflects_differently_in_oklab() {
+        let blue = Rgb([30, 60, 230]);
+        let in_hsv = hsv_to_rgb(&hsv_reflect(&rgb_to_hsv(&blue), 90.));
+        let in_oklab = oklab_to_rgb(&oklab_reflect(&rgb_to_oklab(&blue), 90.));
+        let apart: u32 = in_hsv.0.iter().zip(in_oklab.0).map(|(&a, b)| a.abs_diff(b) as u32).sum();
+        assert!(apart > 60, "{:?} in hsv and {:?} in oklab", in_hsv, in_oklab);
+        // oklab keeps the lightness, clamped into srgb
+        let lightness = |color: &Rgb<u8>| rgb_to_oklab(color).0[0];
+        assert!((lightness(&in_oklab) - lightness(&blue)).abs() < 0.05);
+    }
+
+    #[test]
+    fn reflect_and_rotate_give_the_same_colors_in_either_range() {
+        // every color of a coarse grid whose hue is within 6 degrees of 180, where signed hues
+        // turn over, or of 0
+        let near_the_seams: Vec<Rgb<u8>> = (0..=255u32)
+            .step_by(5)
+            .flat_map(|r| (0..=255u32).step_by(5).flat_map(move |g| (0..=255u32).step_by(5).map(move |b| Rgb([r as u8, g as u8, b as u8]))))
+            .filter(|color| {
+                let hsv = rgb_to_hsv(color).in_range(HueRange::Unsigned);
+                let apart = (hsv.0[0] - 180.).abs().min(hsv.0[0].min(360. - hsv.0[0]));
+                hsv.0[1] > 0. && apart < 6.
+            })
+            .collect();
+        assert!(near_the_seams.len() > 100);
+        let buffer = |range: HueRange, operation: &dyn Fn(&Hsv) -> Hsv| -> Vec<u8> {
+            near_the_seams.iter().flat_map(|color| hsv_to_rgb(&operation(&rgb_to_hsv(color).in_range(range))).0).collect()
+        };
+        for angle in [0., 45., 90., 179.5, 200.] {
+            let reflect = |hsv: &Hsv| hsv_reflect(hsv, angle);
+            assert!(buffer(HueRange::Unsigned, &reflect) == buffer(HueRange::Signed, &reflect), "reflect along {}", angle);
+            let rotate = |hsv: &Hsv| hsv_rotate(hsv, angle);
+            assert!(buffer(HueRange::Unsigned, &rotate) == buffer(HueRange::Signed, &rotate), "rotate by {}", angle);
+        }
+    }
+}
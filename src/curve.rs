@@ -0,0 +1,99 @@
+// tone curves from a file of control points, turned into one 256 entry lookup table per channel
+// each line is "input output", for every channel, or "r input output" (or g, b) for just one,
+// values are 0-255 and # starts a comment, a channel with its own points ignores the shared ones
+
+pub type Tables = [[u8; 256]; 3];
+
+pub fn parse(contents: &str) -> Result<Tables, String> {
+    let mut shared = vec![];
+    let mut own: [Vec<(f32, f32)>; 3] = [vec![], vec![], vec![]];
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (points, values) = match words.as_slice() {
+            [channel, values @ ..] if values.len() == 2 => match *channel {
+                "r" => (&mut own[0], values),
+                "g" => (&mut own[1], values),
+                "b" => (&mut own[2], values),
+                _ => return Err(format!("line {}: the channel must be r, g or b, not {}", number + 1, channel)),
+            },
+            values if values.len() == 2 => (&mut shared, values),
+            _ => return Err(format!("line {}: expected \"input output\" or a channel followed by them", number + 1)),
+        };
+        let value = |word: &str| match word.parse::<f32>() {
+            Ok(value) if (0. ..=255.).contains(&value) => Ok(value),
+            _ => Err(format!("line {}: {} is not a value from 0 to 255", number + 1, word)),
+        };
+        points.push((value(values[0])?, value(values[1])?));
+    }
+
+    if shared.is_empty() && own.iter().all(Vec::is_empty) {
+        return Err(String::from("the curve file has no points"));
+    }
+    let mut tables = [[0; 256]; 3];
+    for (table, points) in tables.iter_mut().zip(own) {
+        let points = if points.is_empty() { shared.clone() } else { points };
+        *table = match points.is_empty() {
+            // a channel nothing was given for is left alone
+            true => std::array::from_fn(|i| i as u8),
+            false => lookup_table(points)?,
+        };
+    }
+    Ok(tables)
+}
+
+// a monotone cubic through the points (Fritsch and Carlson), so the curve never overshoots
+// between them, flat before the first point and after the last
+fn lookup_table(mut points: Vec<(f32, f32)>) -> Result<[u8; 256], String> {
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if points.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+        return Err(String::from("a curve has two points with the same input"));
+    }
+    if points.len() == 1 {
+        return Err(String::from("a curve needs at least two points"));
+    }
+    let slopes: Vec<f32> = points.windows(2).map(|pair| (pair[1].1 - pair[0].1) / (pair[1].0 - pair[0].0)).collect();
+    let mut tangents = vec![0.; points.len()];
+    tangents[0] = slopes[0];
+    tangents[points.len() - 1] = slopes[slopes.len() - 1];
+    for k in 1..points.len() - 1 {
+        if slopes[k - 1] * slopes[k] > 0. {
+            tangents[k] = (slopes[k - 1] + slopes[k]) / 2.;
+        }
+    }
+    // tangents too steep for their segment would make the curve turn back
+    for k in 0..slopes.len() {
+        if slopes[k] == 0. {
+            tangents[k] = 0.;
+            tangents[k + 1] = 0.;
+            continue;
+        }
+        let (a, b) = (tangents[k] / slopes[k], tangents[k + 1] / slopes[k]);
+        let length = a.hypot(b);
+        if length > 3. {
+            tangents[k] = 3. * a / length * slopes[k];
+            tangents[k + 1] = 3. * b / length * slopes[k];
+        }
+    }
+
+    let (first, last) = (points[0], points[points.len() - 1]);
+    Ok(std::array::from_fn(|i| {
+        let x = i as f32;
+        let y = if x <= first.0 {
+            first.1
+        } else if x >= last.0 {
+            last.1
+        } else {
+            let k = points.windows(2).position(|pair| x < pair[1].0).unwrap();
+            let ((x0, y0), (x1, y1)) = (points[k], points[k + 1]);
+            let h = x1 - x0;
+            let t = (x - x0) / h;
+            let (t2, t3) = (t * t, t * t * t);
+            (2. * t3 - 3. * t2 + 1.) * y0 + (t3 - 2. * t2 + t) * h * tangents[k] + (-2. * t3 + 3. * t2) * y1 + (t3 - t2) * h * tangents[k + 1]
+        };
+        y.round().clamp(0., 255.) as u8
+    }))
+}
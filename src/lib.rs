@@ -0,0 +1,26 @@
+// color conversions and image processing behind the color_reflect command line tool
+
+mod affinity;
+pub mod apng;
+pub mod archive;
+pub mod checksum;
+pub mod cmyk;
+pub mod color;
+pub mod curve;
+pub mod mapped;
+pub mod memory;
+pub mod metadata;
+pub mod metrics;
+pub mod output;
+pub mod palette;
+pub mod preview;
+pub mod shader;
+pub mod sheet;
+pub mod space;
+pub mod stats;
+pub mod template;
+pub mod tonemap;
+pub mod transform;
+pub mod vision;
+#[cfg(feature = "wasm")]
+pub mod wasm;
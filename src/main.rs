@@ -1,147 +1,1308 @@
-use std::{env, sync::{Arc, Mutex}, thread};
+use std::{collections::{BTreeMap, HashSet}, env, error::Error, fs::{self, File}, io, path::{Path, PathBuf}, process, sync::{mpsc, Arc, Mutex}, thread, time::{Duration, Instant, SystemTime}};
 
-use image::{GenericImageView, ImageBuffer, Rgb, Rgba};
+use cli::{AngleSource, Command, CommonArgs, Schedule};
+use color_reflect::color::{hsv_reflect, reflect_color, rgb_to_hsv, HueRange, Hsv};
+use color_reflect::output::{Colors, Encoding, PngStream};
+use color_reflect::transform::{self, Axis, Operation, Snapshot, Transform};
+use color_reflect::template::{self, Template, Values};
+use color_reflect::apng::{self, Animation};
+use color_reflect::archive::{self, Archive};
+use color_reflect::{checksum, cmyk, mapped, memory, metadata, metrics, output, palette, preview, shader, sheet, stats, tonemap, vision};
+use image::{imageops::FilterType, ColorType, DynamicImage, GenericImageView, GrayImage, ImageFormat, Rgb, Rgba, RgbaImage};
 
-// rgb↔hsv conversion functions taken from https://gist.github.com/bmgxyz/a5b5b58e492cbca099b468eddd04cc97
+mod cli;
+mod repl;
 
-struct Hsv([f32; 3]);
+fn main() {
+    let command = match cli::parse_args(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(message) => {
+            println!("{}\n", message);
+            println!("{}", cli::usage(None));
+            process::exit(2);
+        }
+    };
 
-fn rgb_to_hsv(pixel: &Rgb<u8>) -> Hsv {
-    let [r, g, b] = pixel.0;
-    let big_m = *[r, g, b].iter().max().unwrap() as f32 / 255.;
-    let little_m = *[r, g, b].iter().min().unwrap() as f32 / 255.;
-    let c = big_m - little_m;
-    let s = (c / big_m) * 100.;
-    let (little_r, little_g, little_b) = (r as f32 / 255., g as f32 / 255., b as f32 / 255.);
-    let (big_r, big_g, big_b) = (
-        (big_m - little_r) / c,
-        (big_m - little_g) / c,
-        (big_m - little_b) / c,
-    );
-    let h_prime = match big_m {
-        x if x == little_m => 0.,
-        x if x == little_r => big_b - big_g,
-        x if x == little_g => 2. + big_r - big_b,
-        x if x == little_b => 4. + big_g - big_r,
-        _ => unreachable!(),
-    };
-    let h = h_prime / 6. * 360.;
-    let v = big_m * 100.;
-    Hsv([h, s, v])
-}
-
-fn hsv_to_rgb(pixel: &Hsv) -> Rgb<u8> {
-    let [hue, saturation, value] = [pixel.0[0], pixel.0[1] / 100., pixel.0[2] / 100.];
-    let max = value;
-    let c = saturation * value;
-    let min = max - c;
-    let h_prime = if hue >= 300. {
-        (hue - 360.) / 60.
-    } else {
-        hue / 60.
+    let (mut common, mode): (CommonArgs, Mode) = match command {
+        Command::Help(command) => {
+            println!("{}", cli::usage(command.as_deref()));
+            return;
+        }
+        Command::Color { color, angle, hue_range } => {
+            print!("{}", describe_reflection(color, angle, hue_range));
+            return;
+        }
+        Command::Shader { language, angle } => {
+            print!("{}", shader::emit(language, angle));
+            return;
+        }
+        Command::Palette { input, angle, output, swatches } => {
+            if let Err(message) = reflect_palette(&input, angle, output.as_deref(), swatches.as_deref()) {
+                println!("{}", message);
+                process::exit(1);
+            }
+            return;
+        }
+        Command::Reflect { common, angles, kaleidoscope } => {
+            let reflect = |axis| match kaleidoscope {
+                Some(folds) => Operation::Kaleidoscope { axis, folds },
+                None => Operation::Reflect(axis),
+            };
+            let mode = match angles {
+                AngleSource::Single(angle) => Mode::Jobs(vec![(reflect(Axis::Fixed(angle)), common.output.clone())]),
+                AngleSource::File(path) => match file_jobs(&path, reflect, &common.output) {
+                    Ok(jobs) => Mode::Jobs(jobs),
+                    Err(message) => {
+                        println!("{}", message);
+                        process::exit(1);
+                    }
+                },
+                AngleSource::Varying(axis) => Mode::Jobs(vec![(reflect(axis), common.output.clone())]),
+                AngleSource::Map(path) => {
+                    let map = match image::open(&path) {
+                        Ok(map) => map.to_luma8(),
+                        Err(e) => {
+                            println!("Failed to open angle map: {}", describe_error(&e, common.verbose_errors));
+                            process::exit(1);
+                        }
+                    };
+                    // every image of the run reads from it until the end, so it is never freed
+                    let map: &'static GrayImage = Box::leak(Box::new(map));
+                    Mode::Jobs(vec![(reflect(Axis::Map(map)), common.output.clone())])
+                }
+                AngleSource::Chain(chain) => Mode::Jobs(vec![(Operation::Chain(chain), common.output.clone())]),
+                AngleSource::HueFrom(path) => {
+                    let companion = match image::open(&path) {
+                        Ok(companion) => companion.to_rgba8(),
+                        Err(e) => {
+                            println!("Failed to open the image for --hue-from: {}", describe_error(&e, common.verbose_errors));
+                            process::exit(1);
+                        }
+                    };
+                    // read until the end of the run like the angle map
+                    let companion: &'static RgbaImage = Box::leak(Box::new(companion));
+                    Mode::Jobs(vec![(Operation::HueFrom(companion), common.output.clone())])
+                }
+                AngleSource::Auto { target } => Mode::AutoAngle { target, output: common.output.clone() },
+                AngleSource::Suggest => Mode::SuggestAngle,
+                AngleSource::Reference(path) => {
+                    let reference = match image::open(&path) {
+                        Ok(reference) => reference,
+                        Err(e) => {
+                            println!("Failed to open the --reference image: {}", describe_error(&e, common.verbose_errors));
+                            process::exit(1);
+                        }
+                    };
+                    // read the same way as the input
+                    let Some(hue) = transform::mean_hue(&reference, common.input_space, common.hue_space, common.tolerance) else {
+                        println!("The --reference image has no hue to match, its pixels are all gray or transparent or their hues cancel out");
+                        process::exit(1);
+                    };
+                    Mode::MatchHue { hue, output: common.output.clone() }
+                }
+                AngleSource::Repl => Mode::Repl,
+                AngleSource::ContactSheet { step, columns, labels } => {
+                    Mode::ContactSheet { angles: sheet::angles(step), columns, labels, output: common.output.clone() }
+                }
+            };
+            (common, mode)
+        }
+        Command::Rotate { common, degrees } => {
+            let mode = Mode::Jobs(vec![(Operation::Rotate(degrees), common.output.clone())]);
+            (common, mode)
+        }
+        Command::Invert { common } => {
+            let mode = Mode::Jobs(vec![(Operation::Invert, common.output.clone())]);
+            (common, mode)
+        }
+        Command::ChromaInvert { common } => {
+            let mode = Mode::Jobs(vec![(Operation::ChromaInvert, common.output.clone())]);
+            (common, mode)
+        }
     };
-    let (r, g, b) = match h_prime {
-        x if -1. <= x && x < 1. => {
-            if h_prime < 0. {
-                (max, min, min - h_prime * c)
-            } else {
-                (max, min + h_prime * c, min)
+
+    let snapshots = preview_snapshots(&common);
+
+    if let Some(dir) = &common.output_dir && let Err(message) = create_output_dir(dir) {
+        println!("{}", message);
+        process::exit(1);
+    }
+
+    if let Some(log_path) = &common.checksum_log && let Err(e) = File::create(log_path) {
+        println!("Failed to create checksum log {}: {}", log_path, e);
+        process::exit(1);
+    }
+
+    // the same date for every output, even when a run goes past midnight
+    let date = template::today();
+
+    if !Path::new(&common.input).is_dir() && !archive::is_archive(&common.input) {
+        if archive::is_archive(&common.output) {
+            println!("A .zip output needs a folder or a .zip archive as input");
+            process::exit(2);
+        }
+        if common.montage.is_some() {
+            println!("--montage needs a folder as input");
+            process::exit(2);
+        }
+        if common.resume {
+            println!("--resume needs a folder as input");
+            process::exit(2);
+        }
+        if common.schedule.is_some() {
+            println!("--across-files and --within-files need a folder as input");
+            process::exit(2);
+        }
+        let mode = match &common.output_template {
+            Some(template) => mode.with_template(template, Path::new(&common.input), 0, &date),
+            None => mode,
+        };
+        if let Err(message) = check_unique(&[&mode]) {
+            println!("{}", message);
+            process::exit(2);
+        }
+        let result = match mode {
+            Mode::Repl => load_input(&common.input, &common).map(|loaded| repl::run(&loaded, &common)),
+            _ => process_input(&common.input, &mode, &common, &snapshots).map(|_| ()),
+        };
+        if let Err(message) = result {
+            println!("{}", message);
+            process::exit(1);
+        }
+        return;
+    }
+    if let Mode::Repl = mode {
+        println!("--repl needs a single image, not a folder");
+        process::exit(2);
+    }
+
+    // a zip archive in or out goes through folders of the temporary directory, removed when done
+    let work_dir = env::temp_dir().join(format!("color_reflect-{}", process::id()));
+    let output_archive = archive::is_archive(&common.output).then(|| common.output.clone());
+    let uses_archives = archive::is_archive(&common.input) || output_archive.is_some();
+    let prepared = match uses_archives {
+        true => prepare_archives(&mut common, &mode, &work_dir),
+        false => Ok(0),
+    };
+    let prepared_ok = prepared.is_ok();
+    let mut result = prepared.and_then(|skipped| match process_folder(&mut common, &mode, &snapshots, &date, skipped)? {
+        BatchCounts { failed: 0, .. } => Ok(()),
+        _ => Err(1),
+    });
+    // what was saved goes into the archive even when some images failed
+    if prepared_ok && let Some(path) = &output_archive && let Err(message) = pack_results(path, &work_dir.join("results")) {
+        println!("{}", message);
+        result = Err(1);
+    }
+    if uses_archives {
+        let _ = fs::remove_dir_all(&work_dir);
+    }
+    if let Err(code) = result {
+        process::exit(code);
+    }
+}
+
+// for --preview-every, a preview that can't be saved is only a warning, the output doesn't depend on it
+fn preview_snapshots(common: &CommonArgs) -> Option<(f32, Snapshot)> {
+    common.preview_every.clone().map(|(every, path)| {
+        let snapshot: Snapshot = Arc::new(move |partial, percent| {
+            let preview_path = suffixed_path(&path, &percent.to_string());
+            if let Err(e) = partial.save(&preview_path) {
+                println!("Warning: failed to save preview {}: {}", preview_path, e);
             }
+        });
+        (every, snapshot)
+    })
+}
+
+// the palette subcommand, the reflected colors go to output or are printed, and are drawn to swatches
+fn reflect_palette(input: &str, angle: f32, output: Option<&str>, swatches: Option<&str>) -> Result<(), String> {
+    let contents = fs::read_to_string(input).map_err(|e| format!("Failed to read palette file {}: {}", input, e))?;
+    let colors = palette::parse_palette(&contents)?;
+    let reflected: Vec<[u8; 3]> = colors.iter().map(|color| reflect_color(color, angle).0).collect();
+    let list: String = reflected.iter().map(|&color| palette::to_hex(color) + "\n").collect();
+    match output {
+        Some(path) => fs::write(path, list).map_err(|e| format!("Failed to write palette {}: {}", path, e))?,
+        None => print!("{}", list),
+    }
+    if let Some(path) = swatches && !reflected.is_empty() {
+        palette::swatches(&reflected).save(path).map_err(|e| format!("Failed to save swatches {}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+// unpacks the images of a zip input into work_dir/input to be worked on as a folder and points
+// the outputs of a zip output into work_dir/results, the count is of entries skipped as not images
+fn prepare_archives(common: &mut CommonArgs, mode: &Mode, work_dir: &Path) -> Result<usize, i32> {
+    if archive::is_archive(&common.input) && common.resume {
+        println!("--resume needs a folder as input");
+        return Err(2);
+    }
+    if archive::is_archive(&common.output) && (common.montage.is_some() || common.checksum_log.is_some() || common.resume) {
+        println!("--montage, --checksum-log and --resume can't be used with a .zip output");
+        return Err(2);
+    }
+    let mut skipped = 0;
+    if archive::is_archive(&common.input) {
+        let folder = work_dir.join("input");
+        skipped = unpack(&common.input, &folder).map_err(|message| {
+            println!("{}", message);
+            1
+        })?;
+        common.input = folder.to_string_lossy().into_owned();
+    }
+    if archive::is_archive(&common.output) {
+        let results = work_dir.join("results");
+        if let Err(message) = create_output_dir(&results.to_string_lossy()) {
+            println!("{}", message);
+            return Err(1);
         }
-        x if 1. <= x && x < 3. => {
-            if h_prime < 2. {
-                (min - (h_prime - 2.) * c, max, min)
-            } else {
-                (min, max, min + (h_prime - 2.) * c)
+        // named after each image inside the archive, and the angle when there is more than one result
+        let extension = common.output_format.map_or("png", |format| format.extensions_str()[0]);
+        let template = common.output_template.take().unwrap_or_else(|| Template::after_input(mode.output_count() > 1, extension));
+        common.output_template = Some(template.in_directory(&results));
+    }
+    Ok(skipped)
+}
+
+// entries in folders of the archive are worked on like the rest, with just their file name
+fn unpack(path: &str, folder: &Path) -> Result<usize, String> {
+    let zip = Archive::open(path)?;
+    fs::create_dir_all(folder).map_err(|e| format!("Failed to create {}: {}", folder.display(), e))?;
+    let mut names = HashSet::new();
+    let mut skipped = 0;
+    for entry in &zip.entries {
+        let name = Path::new(&entry.name).file_name().unwrap_or_default();
+        if ImageFormat::from_path(name).is_err() {
+            println!("Skipping {}, not an image", entry.name);
+            skipped += 1;
+            continue;
+        }
+        if !names.insert(name) {
+            return Err(format!("{} has more than one image named {}", path, name.to_string_lossy()));
+        }
+        match zip.read(entry) {
+            Ok(contents) => fs::write(folder.join(name), contents).map_err(|e| format!("Failed to unpack {}: {}", entry.name, e))?,
+            Err(message) => {
+                println!("Skipping {}, {}", entry.name, message);
+                skipped += 1;
+            }
+        }
+    }
+    Ok(skipped)
+}
+
+// the archive is only written when some result was saved
+fn pack_results(path: &str, results: &Path) -> Result<(), String> {
+    let mut files: Vec<PathBuf> = fs::read_dir(results)
+        .map_err(|e| format!("Failed to read {}: {}", results.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.is_file())
+        .collect();
+    if files.is_empty() {
+        return Ok(());
+    }
+    files.sort();
+    let named: Vec<(String, &Path)> = files.iter().map(|file| (file.file_name().unwrap_or_default().to_string_lossy().into_owned(), file.as_path())).collect();
+    archive::write(path, &named)?;
+    println!("{} results saved to {}", named.len(), path);
+    Ok(())
+}
+
+// batch mode, every image in the folder is processed and a bad file doesn't stop the rest,
+// messages are printed here and Err has the exit code
+// how many images of a folder were saved, failed and were skipped
+#[derive(Debug, PartialEq)]
+struct BatchCounts {
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+// the images that fail are counted and the rest carry on, the error is the exit code for a
+// problem that stops the whole folder
+fn process_folder(common: &mut CommonArgs, mode: &Mode, snapshots: &Option<(f32, Snapshot)>, date: &str, skipped_entries: usize) -> Result<BatchCounts, i32> {
+    let entries = match fs::read_dir(&common.input) {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("Failed to read folder {}: {}", common.input, e);
+            return Err(1);
+        }
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    let (images, others): (Vec<PathBuf>, Vec<PathBuf>) = paths.into_iter().partition(|path| ImageFormat::from_path(path).is_ok());
+    for path in &others {
+        println!("Skipping {}, not an image", path.display());
+    }
+
+    let modes: Vec<Mode> = images
+        .iter()
+        .enumerate()
+        .map(|(index, path)| match &common.output_template {
+            Some(template) => mode.with_template(template, path, index * mode.output_count(), date),
+            // output.png becomes output_<input name>.png
+            None => mode.with_suffix(&path.file_stem().unwrap_or_default().to_string_lossy()),
+        })
+        .collect();
+    if let Err(message) = check_unique(&modes.iter().collect::<Vec<_>>()) {
+        println!("{}", message);
+        return Err(2);
+    }
+
+    let (queued, done): (Vec<_>, Vec<_>) = images
+        .into_iter()
+        .zip(&modes)
+        .partition(|(path, mode)| !(common.resume && is_up_to_date(path, mode)));
+    for (path, mode) in &done {
+        println!("Skipping {}, its output is up to date", path.display());
+        // still listed in the log, which covers every output of the folder
+        for output_path in mode.output_paths() {
+            if let Err(message) = log_checksum(output_path, common) {
+                println!("{}", message);
+                return Err(1);
             }
         }
-        x if 3. <= x && x < 5. => {
-            if h_prime < 4. {
-                (min, min - (h_prime - 4.) * c, max)
-            } else {
-                (min + (h_prime - 4.) * c, min, max)
+    }
+    let skipped = skipped_entries + others.len() + done.len();
+
+    let queued_paths: Vec<&Path> = queued.iter().map(|(path, _)| path.as_path()).collect();
+    let schedule = common.schedule.unwrap_or_else(|| pick_schedule(&queued_paths));
+    if schedule == Schedule::AcrossFiles {
+        println!("Working on each image with a single core");
+    }
+    common.schedule = Some(schedule);
+
+    // at most concurrency images are decoded and held in memory at a time
+    let common = &*common;
+    let finished = run_queue(queued, common.concurrency, |(path, mode)| {
+        let input = path.to_string_lossy().into_owned();
+        let result = process_input(&input, mode, common, snapshots);
+        if let Err(message) = &result {
+            println!("{}: {}", input, message);
+        }
+        result
+    });
+    let failed = finished.iter().filter(|result| result.is_err()).count();
+    let succeeded = finished.len() - failed;
+    let montage_cells: Vec<RgbaImage> = finished.into_iter().filter_map(|result| result.ok()).flatten().collect();
+    println!("Batch done: {} succeeded, {} failed, {} skipped", succeeded, failed, skipped);
+
+    if let Some(columns) = common.montage && !montage_cells.is_empty() {
+        let width = montage_cells.iter().map(|cell| cell.width()).max().unwrap();
+        let height = montage_cells.iter().map(|cell| cell.height()).max().unwrap();
+        let cells: Vec<RgbaImage> = montage_cells.iter().map(|cell| sheet::fit_cell(cell, width, height)).collect();
+        let montage_path = suffixed_path(&common.output, "montage");
+        let mut montage = sheet::grid(&cells, columns);
+        if let Some(color) = background(&montage_path, common) {
+            montage = sheet::flatten(&montage, color);
+        }
+        if let Err(e) = output::save(&montage, &montage_path, common.output_format, common.dpi, &[], encoding(common), output_colors(common.indexed, false)) {
+            println!("Failed to save montage: {}", e);
+            return Err(1);
+        }
+        if let Err(message) = log_checksum(&montage_path, common) {
+            println!("{}", message);
+            return Err(1);
+        }
+        println!("Montage of {} results saved to {}", cells.len(), montage_path);
+    }
+    Ok(BatchCounts { succeeded, failed, skipped })
+}
+
+// work on every item with up to workers threads, each taking the next item off the queue when it
+// is done with one, the results come back in the order of the items
+fn run_queue<T: Send, R: Send>(items: Vec<T>, workers: usize, work: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let workers = workers.min(items.len());
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let finished = Mutex::new(vec![]);
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let Some((index, item)) = queue.lock().unwrap().next() else { break };
+                let result = work(item);
+                finished.lock().unwrap().push((index, result));
+            });
+        }
+    });
+    let mut finished = finished.into_inner().unwrap();
+    finished.sort_by_key(|&(index, _)| index);
+    finished.into_iter().map(|(_, result)| result).collect()
+}
+
+// what to do with the loaded image
+enum Mode {
+    // each job is one operation and where to save its result
+    Jobs(Vec<(Operation, String)>),
+    // reflect at every angle and save the results together as one grid
+    ContactSheet { angles: Vec<f32>, columns: u32, labels: bool, output: String },
+    // reflect along the angle picked_angle finds for the image
+    AutoAngle { target: Option<f32>, output: String },
+    // print what transform::suggest_angles makes of the image's hues
+    SuggestAngle,
+    // reflect along the angle that takes the image's mean hue to this one, from --reference
+    MatchHue { hue: f32, output: String },
+    // angles come in while running
+    Repl,
+}
+
+impl Mode {
+    // the same work with _suffix added to every output path
+    fn with_suffix(&self, suffix: &str) -> Mode {
+        match self {
+            Mode::Jobs(jobs) => Mode::Jobs(
+                jobs.iter()
+                    .map(|(operation, output_path)| (*operation, suffixed_path(output_path, suffix)))
+                    .collect(),
+            ),
+            Mode::ContactSheet { angles, columns, labels, output } => Mode::ContactSheet {
+                angles: angles.clone(),
+                columns: *columns,
+                labels: *labels,
+                output: suffixed_path(output, suffix),
+            },
+            Mode::AutoAngle { target, output } => Mode::AutoAngle { target: *target, output: suffixed_path(output, suffix) },
+            Mode::MatchHue { hue, output } => Mode::MatchHue { hue: *hue, output: suffixed_path(output, suffix) },
+            Mode::SuggestAngle => Mode::SuggestAngle,
+            Mode::Repl => Mode::Repl,
+        }
+    }
+
+    // the same work with every output named by template, counting outputs from first_index
+    fn with_template(&self, template: &Template, input: &Path, first_index: usize, date: &str) -> Mode {
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = input.extension().unwrap_or_default().to_string_lossy();
+        let name = |angle, mode, index| template.expand(&Values { stem: &stem, ext: &ext, angle, mode, index, date });
+        match self {
+            Mode::Jobs(jobs) => Mode::Jobs(
+                jobs.iter()
+                    .enumerate()
+                    .map(|(i, (operation, _))| {
+                        let (mode, angle) = describe_operation(operation);
+                        (*operation, name(angle, mode, first_index + i))
+                    })
+                    .collect(),
+            ),
+            Mode::ContactSheet { angles, columns, labels, .. } => Mode::ContactSheet {
+                angles: angles.clone(),
+                columns: *columns,
+                labels: *labels,
+                output: name(None, "contact-sheet", first_index),
+            },
+            Mode::AutoAngle { target, .. } => Mode::AutoAngle { target: *target, output: name(None, "reflect", first_index) },
+            Mode::MatchHue { hue, .. } => Mode::MatchHue { hue: *hue, output: name(None, "reflect", first_index) },
+            Mode::SuggestAngle => Mode::SuggestAngle,
+            Mode::Repl => Mode::Repl,
+        }
+    }
+
+    // how many files the work saves for each input
+    fn output_count(&self) -> usize {
+        match self {
+            Mode::Jobs(jobs) => jobs.len(),
+            _ => 1,
+        }
+    }
+
+    fn output_paths(&self) -> Vec<&str> {
+        match self {
+            Mode::Jobs(jobs) => jobs.iter().map(|(_, output_path)| output_path.as_str()).collect(),
+            Mode::ContactSheet { output, .. } | Mode::AutoAngle { output, .. } | Mode::MatchHue { output, .. } => vec![output],
+            Mode::SuggestAngle | Mode::Repl => vec![],
+        }
+    }
+}
+
+// the name of the operation for {mode}, and its angle for {angle} if it has just one
+fn describe_operation(operation: &Operation) -> (&'static str, Option<f32>) {
+    let fixed = |axis: &Axis| match *axis {
+        Axis::Fixed(angle) => Some(angle),
+        _ => None,
+    };
+    match operation {
+        Operation::Reflect(axis) => ("reflect", fixed(axis)),
+        Operation::Kaleidoscope { axis, .. } => ("kaleidoscope", fixed(axis)),
+        Operation::Rotate(degrees) => ("rotate", Some(*degrees)),
+        Operation::Invert => ("invert", None),
+        Operation::ChromaInvert => ("chroma-invert", None),
+        Operation::Chain(_) => ("chain", None),
+        Operation::HueFrom(_) => ("hue-from", None),
+    }
+}
+
+// two outputs with the same name would silently overwrite each other
+fn check_unique(modes: &[&Mode]) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for path in modes.iter().flat_map(|mode| mode.output_paths()) {
+        if !seen.insert(path) {
+            return Err(format!("More than one output would be saved as {}, add {{stem}}, {{angle}} or {{index}} to --output-template", path));
+        }
+    }
+    Ok(())
+}
+
+// every output exists and was written after the input last changed, outputs given the input's
+// time by --preserve-mtime count as up to date too
+fn is_up_to_date(input: &Path, mode: &Mode) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let Some(input_modified) = modified(input) else { return false };
+    let output_paths = mode.output_paths();
+    !output_paths.is_empty()
+        && output_paths.iter().all(|output_path| modified(Path::new(output_path)).is_some_and(|output_modified| output_modified >= input_modified))
+}
+
+// images up to this many pixels are done on one core each when the schedule is left to pick_schedule,
+// splitting so few rows over threads costs about as much as it saves
+const SMALL_IMAGE_PIXELS: u64 = 512 * 512;
+
+// one core per image when every image is small, the image sizes are read from the file headers
+// without decoding, files that can't be read are left out as they fail later anyway
+fn pick_schedule(images: &[&Path]) -> Schedule {
+    let small = |path: &&Path| image::image_dimensions(path).ok().is_none_or(|(width, height)| width as u64 * height as u64 <= SMALL_IMAGE_PIXELS);
+    // a single image has nothing to share the cores with
+    if images.len() > 1 && images.iter().all(small) {
+        Schedule::AcrossFiles
+    } else {
+        Schedule::WithinFiles
+    }
+}
+
+// a decoded input with everything worked out from it ahead of processing
+struct LoadedImage {
+    path: String,
+    img: DynamicImage,
+    levels: Option<(f32, f32)>,
+    hue_equalization: Option<[f32; 361]>,
+    dpi: Option<f32>,
+    // text metadata to copy into the output, empty unless --keep-metadata
+    text: Vec<(String, String)>,
+    // every frame of an animated png, img is then its default image
+    animation: Option<Animation>,
+    // decoding and everything done to the image before the operation, for --benchmark
+    load_time: Duration,
+}
+
+fn load_input(input: &str, common: &CommonArgs) -> Result<LoadedImage, String> {
+    let timer = Instant::now();
+    let mut img = match cmyk::detect(input) {
+        Some(source) => {
+            if source.has_profile {
+                output::warn(common.strict, String::from("the embedded color profile can't be applied"), ", converting CMYK to RGB without it")?;
             }
+            println!("Converting CMYK input to RGB, the result is saved as RGB");
+            cmyk::decode(input, &source)?
+        }
+        None => match mapped::load(input) {
+            Some(result) => result,
+            None => image::open(input),
         }
-        _ => unreachable!(),
+        .map_err(|e| format!("Failed to open image: {}", describe_error(&e, common.verbose_errors)))?,
     };
-    Rgb([(r * 255.) as u8, (g * 255.) as u8, (b * 255.) as u8])
+    // every pixel of an input without alpha is taken as fully opaque
+    if common.strict && !common.allow_rgb && !img.color().has_alpha() {
+        let kind = if img.color().has_color() { "an rgb" } else { "a grayscale" };
+        return Err(format!("Stopping for --strict: {} is {} image without an alpha channel, add --allow-rgb to take it as fully opaque", input, kind));
+    }
+    if let Some(tonemap) = common.tonemap {
+        match tonemap::apply(&img, tonemap) {
+            Some(mapped) => img = mapped,
+            None => println!("Note: {} isn't a high dynamic range image, --tonemap leaves it as it is", input),
+        }
+    }
+    if let Some((x, y, width, height)) = common.crop {
+        // checked once the size is known, every image of a folder can be a different size
+        if x as u64 + width as u64 > img.width() as u64 || y as u64 + height as u64 > img.height() as u64 {
+            return Err(format!("Crop {},{},{},{} goes past the edge of the {}x{} image", x, y, width, height, img.width(), img.height()));
+        }
+    }
+    img = crop_and_resize(img, common);
+    // the frames are the size of the default image, so the crop fits them too
+    let animation = match apng::read(input) {
+        Some(Ok(animation)) => {
+            println!("Animated png with {} frames, each one is processed", animation.frames.len());
+            Some(animation.map(|frame| crop_and_resize(DynamicImage::ImageRgba8(frame.clone()), common).to_rgba8()))
+        }
+        Some(Err(e)) => {
+            output::warn(common.strict, format!("can't read the frames of the animated png ({})", e), ", using only its default image")?;
+            None
+        }
+        None => None,
+    };
+    let levels = if common.auto_levels { transform::value_range(&img, common.input_space) } else { None };
+    let hue_equalization = if common.equalize_hue { transform::hue_distribution(&img, common.input_space, common.tolerance) } else { None };
+    let dpi = common.dpi.or_else(|| metadata::read_dpi(input));
+    let text = if common.keep_metadata { metadata::read_text(input) } else { vec![] };
+
+    let load_time = timer.elapsed();
+    println!("Image loaded in {}ms", load_time.as_millis());
+    Ok(LoadedImage { path: input.to_string(), img, levels, hue_equalization, dpi, text, animation, load_time })
 }
 
-// hue reflection algorithm
-fn hsv_reflect(pixel: &Hsv, reflect_angle: f32) -> Hsv {
-    let [hue, saturation, value] = [pixel.0[0], pixel.0[1], pixel.0[2]];
+// --crop (already checked to fit) and then --max-dimension
+fn crop_and_resize(mut img: DynamicImage, common: &CommonArgs) -> DynamicImage {
+    if let Some((x, y, width, height)) = common.crop {
+        img = img.crop_imm(x, y, width, height);
+    }
+    if let Some(max) = common.max_dimension && (img.width() > max || img.height() > max) {
+        // resize keeps the aspect ratio, fitting the image inside max by max
+        img = img.resize(max, max, FilterType::Lanczos3);
+    }
+    img
+}
 
-    // for a hue angle C and reflection angle A
-    // output angle is 360-(C-A)+A mod 360
-    // or, 360-C+2A mod 360
-    let angle = (360. - hue + 2.*reflect_angle) % 360.;
+// the settings from the command line with the operation to run
+fn build_transform(operation: Operation, loaded: &LoadedImage, common: &CommonArgs) -> Transform {
+    Transform {
+        operation,
+        repeat: common.repeat,
+        space: common.input_space,
+        hue_space: common.hue_space,
+        levels: loaded.levels,
+        invert_value: common.invert_value,
+        hue_equalization: loaded.hue_equalization,
+        highlight_only: common.highlight_only,
+        swap: common.swap,
+        curve: common.curve,
+        tint: common.tint,
+        jitter: common.jitter,
+        glitch: common.glitch,
+        max_shift: common.max_shift,
+        tolerance: common.tolerance,
+        vignette: common.vignette,
+        alpha_scale: common.alpha_scale,
+        pin_workers: common.threads_affinity,
+        threads: (common.schedule == Some(Schedule::AcrossFiles)).then_some(1),
+    }
+}
+
+// returns the results when they are needed for --montage, nothing otherwise
+fn process_input(input: &str, mode: &Mode, common: &CommonArgs, snapshots: &Option<(f32, Snapshot)>) -> Result<Vec<RgbaImage>, String> {
+    let loaded = load_input(input, common)?;
+    let mut results = vec![];
+    let auto_jobs;
+    let jobs = match mode {
+        Mode::Jobs(jobs) => jobs,
+        Mode::AutoAngle { target, output } => {
+            let (angle, change) = picked_angle(&loaded, *target, common);
+            println!("Picked angle {}, changing each channel by {:.1} on average", angle, change);
+            auto_jobs = vec![(Operation::Reflect(Axis::Fixed(angle)), output.clone())];
+            &auto_jobs
+        }
+        Mode::MatchHue { hue, output } => {
+            auto_jobs = vec![(matching_operation(&loaded, *hue, common)?, output.clone())];
+            &auto_jobs
+        }
+        Mode::ContactSheet { angles, columns, labels, output } => {
+            let contact_sheet = contact_sheet(&loaded, angles, *columns, *labels, output, common)?;
+            if common.montage.is_some() {
+                results.push(contact_sheet);
+            }
+            return Ok(results);
+        }
+        Mode::SuggestAngle => {
+            print_suggestion(&loaded, common);
+            return Ok(results);
+        }
+        Mode::Repl => return Ok(results),
+    };
+    for (operation, output_path) in jobs {
+        // checked once the size is known, like --crop, a folder can hold images of different sizes
+        if let Operation::Reflect(Axis::Map(map)) | Operation::Kaleidoscope { axis: Axis::Map(map), .. } = operation
+            && map.dimensions() != loaded.img.dimensions()
+        {
+            let (width, height) = loaded.img.dimensions();
+            return Err(format!("The angle map is {}x{} but the image is {}x{}, they have to be the same size", map.width(), map.height(), width, height));
+        }
+        if let Operation::HueFrom(companion) = operation
+            && companion.dimensions() != loaded.img.dimensions()
+        {
+            let (width, height) = loaded.img.dimensions();
+            return Err(format!("The --hue-from image is {}x{} but the image is {}x{}, they have to be the same size", companion.width(), companion.height(), width, height));
+        }
+        let timer = Instant::now();
+        println!("Processing...");
+        let transform = build_transform(*operation, &loaded, common);
+        let (new_img, streamed) = match streams_result(output_path, &loaded, common) {
+            true => stream_result(&loaded, transform, snapshots, output_path, common),
+            false => match snapshots {
+                Some(snapshots) => (transform::reflect_image_with_snapshots(&loaded.img, transform, Some(snapshots.clone())), None),
+                None => (transform::reflect_image(&loaded.img, transform), None),
+            },
+        };
+
+        print_done(timer);
+        let process_time = timer.elapsed();
+
+        let saved = (|| {
+            if let Some(point) = common.sample_point {
+                print_sample(&loaded.img, point, transform, common.hue_range)?;
+            }
+            if common.verify_determinism {
+                verify_determinism(&loaded.img, transform, &new_img)?;
+            }
+            // other formats get just the default image
+            let animation = match &loaded.animation {
+                Some(animation) if saves_png(output_path, common) => {
+                    Some(animation.map(|frame| transform::reflect_image(&DynamicImage::ImageRgba8(frame.clone()), transform)))
+                }
+                Some(_) => {
+                    println!("Note: {} isn't a png, only the default image of the animation is saved", output_path);
+                    None
+                }
+                None => None,
+            };
+            write_result(&new_img, animation.as_ref(), output_path, streamed.as_deref(), &loaded, common)
+        })();
+        // a failed run leaves no output behind, streamed or not
+        if saved.is_err() && let Some(partial) = &streamed {
+            let _ = fs::remove_file(partial);
+        }
+        saved?;
+        if common.benchmark {
+            println!("{}", benchmark_line(input, output_path, new_img.dimensions(), loaded.load_time, process_time, memory::peak_resident_bytes()));
+        }
+        if let Some(mask_path) = &common.change_mask {
+            // one mask per output when there are several, named after it
+            let mask_path = match jobs.len() > 1 || Path::new(&common.input).is_dir() {
+                true => suffixed_path(mask_path, Path::new(output_path).file_stem().and_then(|s| s.to_str()).unwrap_or("output")),
+                false => mask_path.clone(),
+            };
+            metrics::change_mask(&loaded.img.to_rgba8(), &new_img).save(&mask_path).map_err(|e| format!("Failed to save change mask: {}", e))?;
+        }
+        if common.montage.is_some() {
+            results.push(new_img);
+        }
+    }
+    Ok(results)
+}
+
+// rows waiting for the encoder before the workers have to wait for it
+const STREAMED_ROWS: usize = 64;
 
-    Hsv([angle, saturation, value])
+// whether the result can be saved while it is made, as a plain 8 bit rgba png, with nothing
+// done to the whole image first and no chance of it being saved as grayscale
+fn streams_result(output_path: &str, loaded: &LoadedImage, common: &CommonArgs) -> bool {
+    let orients = common.flip_horizontal || common.flip_vertical || common.rotate != 0;
+    saves_png(output_path, common)
+        && !common.raw
+        && loaded.animation.is_none()
+        && common.bit_depth == 8
+        && !common.interlace
+        && common.indexed.is_none()
+        && !gray_input(loaded)
+        && !orients
+        && common.tile.is_none()
+        && common.border.is_none()
+        && background(output_path, common).is_none()
 }
 
-fn main() {
-    let timer = std::time::Instant::now();
-    let core_count: u32 = num_cpus::get() as u32;
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        println!("Usage: input a file path and reflect angle as command line arguments");
-        return;
+// transforms the image while an encoder thread compresses the rows the workers have finished into
+// a partial file next to the output, so encoding overlaps the work instead of all following it,
+// the workers finish rows a little out of order so they are put back in order first
+// returns the result with the partial file, which write_result moves into place, or None if
+// writing it failed, the result is then saved the usual way and that reports the error
+fn stream_result(loaded: &LoadedImage, transform: Transform, snapshots: &Option<(f32, Snapshot)>, output_path: &str, common: &CommonArgs) -> (RgbaImage, Option<String>) {
+    let partial = partial_path(output_path);
+    let Ok(mut stream) = PngStream::create(&partial, loaded.img.dimensions(), loaded.dpi, &loaded.text, common.compress_level) else {
+        let _ = fs::remove_file(&partial);
+        return (transform::reflect_image_with_snapshots(&loaded.img, transform, snapshots.clone()), None);
+    };
+    let (sender, receiver) = mpsc::sync_channel::<(u32, Vec<u8>)>(STREAMED_ROWS);
+    let (new_img, stream) = thread::scope(|scope| {
+        let encoder = scope.spawn(move || {
+            let mut waiting = BTreeMap::new();
+            let mut next = 0;
+            let mut written = true;
+            for (y, row) in receiver {
+                waiting.insert(y, row);
+                while let Some(row) = waiting.remove(&next) {
+                    // rows are still taken after a failure so the workers never wait forever
+                    written = written && stream.write_row(&row).is_ok();
+                    next += 1;
+                }
+            }
+            written.then_some(stream)
+        });
+        let send_row = |y: u32, row: &[Rgba<u8>]| {
+            let _ = sender.send((y, row.iter().flat_map(|pixel| pixel.0).collect()));
+        };
+        let new_img = transform::reflect_image_with_rows(&loaded.img, transform, snapshots.clone(), &send_row);
+        drop(sender);
+        (new_img, encoder.join().unwrap())
+    });
+    match stream.map(|stream| stream.finish(&new_img)) {
+        Some(Ok(())) => (new_img, Some(partial)),
+        _ => {
+            let _ = fs::remove_file(&partial);
+            (new_img, None)
+        }
     }
-    let file_path: &String = &args[1];
-    let reflect_angle: f32 = args[2].parse::<f32>().expect("Angle must be number");
-    let img = Arc::new(image::open(file_path).expect("Failed to open image"));
+}
 
+// the colors of one pixel along the way, on stderr so they don't mix with the usual output
+fn print_sample(img: &DynamicImage, point: (u32, u32), transform: Transform, hue_range: HueRange) -> Result<(), String> {
+    eprint!("{}", sample_report(img, point, transform, hue_range)?);
+    Ok(())
+}
+
+// the lines --sample-point prints for one pixel
+fn sample_report(img: &DynamicImage, (x, y): (u32, u32), transform: Transform, hue_range: HueRange) -> Result<String, String> {
     let (width, height) = img.dimensions();
+    if x >= width || y >= height {
+        return Err(format!("The sample point {},{} is outside the {}x{} image", x, y, width, height));
+    }
+    let (result, hsv) = transform::trace_pixel(img, x, y, transform);
+    let describe = |pixel: Rgba<u8>| {
+        let rgb = Rgb([pixel[0], pixel[1], pixel[2]]);
+        format!("{}  alpha {}", describe_color(rgb, &rgb_to_hsv(&rgb).in_range(hue_range)), pixel[3])
+    };
+    let mut report = format!("Sample {},{}\n", x, y);
+    report += &format!("  input:   {}\n", describe(img.get_pixel(x, y)));
+    match hsv {
+        Some((before, after)) => {
+            let [h, s, v] = before.in_range(hue_range).0;
+            report += &format!("  hsv:     hsv({:.3}, {:.3}, {:.3})\n", h, s, v);
+            let [h, s, v] = after.in_range(hue_range).0;
+            report += &format!("  new hsv: hsv({:.3}, {:.3}, {:.3})\n", h, s, v);
+        }
+        None => report += "  (no hue step, the pixel doesn't go through hsv)\n",
+    }
+    report += &format!("  output:  {}\n", describe(result));
+    Ok(report)
+}
+
+// redoes the transform with 1 thread, 2 threads and more threads than cores, which interleave rows
+// differently, each pixel only depends on itself so anything other than the same bytes is a bug
+fn verify_determinism(img: &DynamicImage, transform: Transform, expected: &RgbaImage) -> Result<(), String> {
+    let many = (num_cpus::get() as u32 * 2).max(4);
+    for threads in [1, 2, many] {
+        let result = transform::reflect_image(img, Transform { threads: Some(threads), ..transform });
+        if let Some((x, y)) = metrics::first_difference(expected, &result) {
+            return Err(format!(
+                "The result with {} threads is different, first at pixel {},{}: {:?} instead of {:?}",
+                threads,
+                x,
+                y,
+                result.get_pixel(x, y).0,
+                expected.get_pixel(x, y).0
+            ));
+        }
+    }
+    println!("Same result with 1, 2 and {} threads", many);
+    Ok(())
+}
+
+// longest side of the copy angles are tried on
+const AUTO_ANGLE_SIZE: u32 = 128;
+
+// tries every 5 degrees, then every degree around the best of those, reflecting a small copy of
+// the image with the same settings as the real run, and returns the angle with the largest mean change
+// (or the one closest to target) together with that change
+// angles from 180 on give the same reflections again, so only 0 to 179 are tried
+fn picked_angle(loaded: &LoadedImage, target: Option<f32>, common: &CommonArgs) -> (f32, f64) {
+    let img = &loaded.img;
+    let small = if img.width() > AUTO_ANGLE_SIZE || img.height() > AUTO_ANGLE_SIZE {
+        img.thumbnail(AUTO_ANGLE_SIZE, AUTO_ANGLE_SIZE)
+    } else {
+        img.clone()
+    };
+    let original = small.to_rgba8();
+    let change = |angle: i32| {
+        let transform = build_transform(Operation::Reflect(Axis::Fixed(angle as f32)), loaded, common);
+        metrics::mean_difference(&original, &transform::reflect_image(&small, transform))
+    };
+    // how far a change is from what is wanted, lower is better
+    let miss = |change: f64| match target {
+        Some(target) => (change - target as f64).abs(),
+        None => -change,
+    };
+    let best = |angles: &mut dyn Iterator<Item = i32>| {
+        angles.map(|angle| (angle, change(angle))).min_by(|a, b| miss(a.1).total_cmp(&miss(b.1))).unwrap()
+    };
+    let (coarse, _) = best(&mut (0..180).step_by(5));
+    let (angle, change) = best(&mut (coarse - 4..=coarse + 4));
+    (angle.rem_euclid(180) as f32, change)
+}
+
+// the operation taking the image's mean hue to the target
+fn matching_operation(loaded: &LoadedImage, target: f32, common: &CommonArgs) -> Result<Operation, String> {
+    let hue = transform::mean_hue(&loaded.img, common.input_space, common.hue_space, common.tolerance)
+        .ok_or("The image has no hue to match to the reference, its pixels are all gray or transparent or their hues cancel out")?;
+    match transform::matching_axis(hue, target, common.tolerance) {
+        Some(angle) => {
+            // in full, so passing the printed angle back in gives the same result
+            println!("Picked angle {}, taking the mean hue from {:.1} to {:.1}", angle, hue, target);
+            Ok(Operation::Reflect(Axis::Fixed(angle)))
+        }
+        None => {
+            println!("The mean hue is already {:.1}, leaving the hues as they are", target);
+            Ok(Operation::Rotate(0.))
+        }
+    }
+}
+
+// a most dramatic angle moving hues less than this on average is hardly better than any other
+const EVEN_SPREAD_SHIFT: f32 = 100.;
+
+fn print_suggestion(loaded: &LoadedImage, common: &CommonArgs) {
+    let histogram = transform::hue_histogram(&loaded.img, common.input_space, common.hue_space, common.tolerance);
+    match transform::suggest_angles(&histogram) {
+        Some(((dramatic, dramatic_shift), (balanced, balanced_shift))) => {
+            println!("Suggested angle {}, moving hues by {:.0} degrees on average", dramatic, dramatic_shift);
+            println!("For a more balanced change, angle {} moves them by {:.0} degrees on average", balanced, balanced_shift);
+            // every image's hues move 90 degrees on average over all angles, an even spread gets that from each one
+            if dramatic_shift < EVEN_SPREAD_SHIFT {
+                println!("The hues are spread all around the wheel, so every angle changes them about as much");
+            }
+        }
+        None => println!("No angle to suggest, the image has no hues, every pixel is gray or transparent"),
+    }
+}
+
+fn contact_sheet(loaded: &LoadedImage, angles: &[f32], columns: u32, labels: bool, output_path: &str, common: &CommonArgs) -> Result<RgbaImage, String> {
+    let timer = Instant::now();
+    println!("Processing {} angles...", angles.len());
+    let cells: Vec<RgbaImage> = angles
+        .iter()
+        .map(|&angle| {
+            let transform = build_transform(Operation::Reflect(Axis::Fixed(angle)), loaded, common);
+            let mut cell = transform::reflect_image(&loaded.img, transform);
+            if labels {
+                sheet::label(&mut cell, &angle.to_string());
+            }
+            cell
+        })
+        .collect();
+    let contact_sheet = sheet::grid(&cells, columns);
+
+    print_done(timer);
+    write_result(&contact_sheet, None, output_path, None, loaded, common)?;
+    Ok(contact_sheet)
+}
+
+// one line of json for --benchmark, the peak memory is left out where it can't be had
+fn benchmark_line(input: &str, output_path: &str, (width, height): (u32, u32), load_time: Duration, process_time: Duration, peak: Option<u64>) -> String {
+    let milliseconds = |time: Duration| time.as_secs_f64() * 1000.;
+    let mut line = format!(
+        "{{\"input\": {}, \"output\": {}, \"width\": {}, \"height\": {}, \"load_ms\": {:.3}, \"process_ms\": {:.3}",
+        json_string(input),
+        json_string(output_path),
+        width,
+        height,
+        milliseconds(load_time),
+        milliseconds(process_time)
+    );
+    if let Some(bytes) = peak {
+        line += &format!(", \"peak_resident_bytes\": {}", bytes);
+    }
+    line + "}"
+}
+
+// text as a quoted json string
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            character if (character as u32) < 0x20 => quoted += &format!("\\u{:04x}", character as u32),
+            character => quoted.push(character),
+        }
+    }
+    quoted + "\""
+}
+
+// time since timer plus the peak memory so far, which covers every image when several run at once
+fn print_done(timer: Instant) {
+    let elapsed = timer.elapsed().as_millis();
+    match memory::peak_resident_bytes() {
+        Some(bytes) => println!("Done in {}ms, peak memory {:.1} MB", elapsed, bytes as f64 / 1e6),
+        None => println!("Done in {}ms", elapsed),
+    }
+}
+
+// saves the result and runs the reporting options (palette, stats, previews, ...) on it
+// a grayscale input is saved as grayscale too, unless something like a tint or a colored border added color
+fn keeps_grayscale(loaded: &LoadedImage, result: &RgbaImage) -> bool {
+    gray_input(loaded) && result.pixels().all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2])
+}
+
+fn gray_input(loaded: &LoadedImage) -> bool {
+    matches!(loaded.img.color(), ColorType::L8 | ColorType::La8 | ColorType::L16 | ColorType::La16)
+}
+
+fn encoding(common: &CommonArgs) -> Encoding {
+    Encoding { bit_depth: common.bit_depth, interlaced: common.interlace, compression: common.compress_level, strict: common.strict }
+}
+
+// --indexed wins over keeping a grayscale input gray, the palette is then all grays anyway
+fn output_colors(indexed: Option<usize>, grayscale: bool) -> Colors {
+    match indexed {
+        Some(max_colors) => Colors::Indexed(max_colors),
+        None if grayscale => Colors::Grayscale,
+        None => Colors::Rgba,
+    }
+}
+
+fn saves_png(output_path: &str, common: &CommonArgs) -> bool {
+    common.output_format.or(ImageFormat::from_path(output_path).ok()) == Some(ImageFormat::Png)
+}
+
+// what transparent pixels are flattened onto, --background or white for formats without alpha
+fn background(output_path: &str, common: &CommonArgs) -> Option<Rgb<u8>> {
+    let format = common.output_format.or(ImageFormat::from_path(output_path).ok());
+    match common.background {
+        Some(color) => Some(color),
+        None if format.is_some_and(|format| !output::supports_alpha(format)) => Some(Rgb([255, 255, 255])),
+        None => None,
+    }
+}
 
-    let new_img = Arc::new(Mutex::new(ImageBuffer::new(width, height)));
-
-    let mut handles = vec![];
-
-    let timer_elapsed = timer.elapsed();
-    println!("Image loaded in {}ms", timer_elapsed.as_millis());
-
-    println!("Processing...");
-    // process main image
-    for y in 0..height/core_count {
-        for y_inner in 0..core_count { // divide image rows by number of cores in device
-            let img_clone = Arc::clone(&img);
-            let new_img_clone = Arc::clone(&new_img);
-            handles.push(thread::spawn(move || {
-                for x in 0..width {
-                    let pixel = img_clone.get_pixel(x, y*core_count+y_inner);
-                    let pxl: Rgb<u8> = Rgb([pixel[0], pixel[1], pixel[2]]);
-                    let hsv = rgb_to_hsv(&pxl);
-
-                    let new_hsv = hsv_reflect(&hsv, reflect_angle);
-                    let new_rgb = hsv_to_rgb(&new_hsv);
-                    let new_pixel = Rgba([new_rgb[0], new_rgb[1], new_rgb[2], pixel[3]]);
-                    new_img_clone.lock().unwrap().put_pixel(x, y*core_count+y_inner, new_pixel);
+// with an animation its frames are saved as an animated png, the reports are about new_img,
+// the result for the default image
+// streamed is the partial file stream_result already wrote the result to, if it did
+fn write_result(new_img: &RgbaImage, animation: Option<&Animation>, output_path: &str, streamed: Option<&str>, loaded: &LoadedImage, common: &CommonArgs) -> Result<(), String> {
+    let (file_path, img) = (&loaded.path, &loaded.img);
+    let background = background(output_path, common);
+    if common.strict && common.background.is_none() && background.is_some() && new_img.pixels().any(|pixel| pixel[3] < 255) {
+        return Err(format!("Stopping for --strict: {} can't store transparency, pick what it is drawn over with --background", output_path));
+    }
+    // a quarter turn swaps the sides before they are tiled
+    let (width, height) = if common.rotate % 180 == 90 { (new_img.height(), new_img.width()) } else { new_img.dimensions() };
+    if let Some((rows, columns)) = common.tile
+        && (width.checked_mul(columns).is_none() || height.checked_mul(rows).is_none())
+    {
+        return Err(format!("Tiling the {}x{} result {},{} times is too large", width, height, rows, columns));
+    }
+    let orients = common.flip_horizontal || common.flip_vertical || common.rotate != 0;
+    // flips and turns go on the result itself, then it is tiled so the border goes around the whole
+    // canvas, and the border comes before the background so its color is flattened too, it is always opaque anyway
+    let finish = |img: &RgbaImage| {
+        let oriented = match orients {
+            true => sheet::orient(img, common.flip_horizontal, common.flip_vertical, common.rotate),
+            false => img.clone(),
+        };
+        let tiled = match common.tile {
+            Some((rows, columns)) => sheet::tile(&oriented, rows, columns),
+            None => oriented,
+        };
+        let framed = match common.border {
+            Some((width, color, inset)) => sheet::border(&tiled, width, Rgba([color[0], color[1], color[2], 255]), inset),
+            None => tiled,
+        };
+        match background {
+            Some(color) => sheet::flatten(&framed, color),
+            None => framed,
+        }
+    };
+    let changes = orients || common.tile.is_some() || common.border.is_some() || background.is_some();
+    // read before saving, the output can be the input itself
+    let input_modified = match common.preserve_mtime {
+        true => Some(fs::metadata(file_path).and_then(|metadata| metadata.modified()).map_err(|e| format!("Failed to read input modification time: {}", e))?),
+        false => None,
+    };
+    let finished;
+    let new_img = match changes {
+        true => {
+            finished = finish(new_img);
+            &finished
+        }
+        false => new_img,
+    };
+    match animation {
+        _ if common.raw => {
+            // a template can still have named it something else
+            if !cli::is_raw_path(output_path) {
+                return Err(format!("--raw saves plain bytes, {} needs to end in .raw", output_path));
+            }
+            if animation.is_some() && common.strict {
+                return Err(String::from("Stopping for --strict: raw outputs hold one image, not every frame of the animation"));
+            }
+            if animation.is_some() {
+                println!("Note: raw outputs hold one image, only the default image of the animation is saved");
+            }
+            println!("Saving {}x{} rgba8", new_img.width(), new_img.height());
+            // never the input, raw files can't be read
+            output::save_raw(new_img, output_path)
+        }
+        Some(animation) => {
+            // the frames are always written as 8 bit rgba, in order
+            if common.indexed.is_some() || common.bit_depth == 16 || common.interlace {
+                return Err(String::from("--indexed, --bit-depth 16 and --interlace can't be used with an animated png input"));
+            }
+            let finished_animation;
+            let animation = match changes {
+                true => {
+                    finished_animation = animation.map(finish);
+                    &finished_animation
                 }
-            }));
+                false => animation,
+            };
+            save_over_input(file_path, output_path, |path| output::save_apng(animation, path, loaded.dpi, &loaded.text, common.compress_level))
         }
+        // moved into place like save_over_input does, so it replaces the output in one go
+        None if let Some(partial) = streamed => fs::rename(partial, output_path).map_err(Box::from),
+        None => save_over_input(file_path, output_path, |path| {
+            output::save(new_img, path, common.output_format, loaded.dpi, &loaded.text, encoding(common), output_colors(common.indexed, keeps_grayscale(loaded, new_img)))
+        }),
     }
-    // process remainder of image
-    for y in height/core_count*core_count..height {
-        for x in 0..width {
-            let pixel = img.get_pixel(x, y);
-            let pxl: Rgb<u8> = Rgb([pixel[0], pixel[1], pixel[2]]);
-            let hsv = rgb_to_hsv(&pxl);
+    .map_err(|e| format!("Failed to save image: {}", describe_error(&*e, common.verbose_errors)))?;
+    log_checksum(output_path, common)?;
 
-            let new_hsv = hsv_reflect(&hsv, reflect_angle);
-            let new_rgb = hsv_to_rgb(&new_hsv);
-            let new_pixel = Rgba([new_rgb[0], new_rgb[1], new_rgb[2], pixel[3]]);
-            new_img.lock().unwrap().put_pixel(x, y, new_pixel);
+    if let Some(count) = common.palette_size {
+        let colors = palette::dominant_colors(new_img, count);
+        for &color in &colors {
+            println!("{}", palette::to_hex(color));
+        }
+        if !colors.is_empty() {
+            let swatch_path = suffixed_path(output_path, "palette");
+            palette::swatches(&colors).save(&swatch_path).map_err(|e| format!("Failed to save palette: {}", e))?;
         }
     }
 
-    for handle in handles {
-        handle.join().unwrap();
+    if let Some(deficiency) = common.simulate {
+        let simulated_path = suffixed_path(output_path, deficiency.name());
+        vision::simulate(new_img, deficiency).save(&simulated_path).map_err(|e| format!("Failed to save simulation: {}", e))?;
     }
 
-    let timer_elapsed = timer.elapsed();
-    println!("Done in {}ms", timer_elapsed.as_millis());
+    if common.stats {
+        let before = stats::image_stats(&img.to_rgba8(), common.tolerance);
+        let after = stats::image_stats(new_img, common.tolerance);
+        eprint!("{}", stats::table(&before, &after));
+    }
+
+    if !common.metrics.is_empty() {
+        let original = img.to_rgba8();
+        let values: Vec<String> = common.metrics.iter().map(|metric| metric.describe(&original, new_img)).collect();
+        eprintln!("{}", values.join(", "));
+    }
 
-    new_img.lock().unwrap().save("output.png").unwrap();
+    if common.preview_term {
+        print!("{}", preview::render(new_img, preview::terminal_columns()));
+    }
+
+    if let Some(modified) = input_modified {
+        set_mtime(output_path, modified).map_err(|e| format!("Failed to set output modification time: {}", e))?;
+    }
+    Ok(())
+}
+
+// the error as it is usually shown, with --verbose-errors followed by its debug form, which names
+// the format and the exact decoder error, and every error it was caused by
+fn describe_error(e: &dyn Error, verbose: bool) -> String {
+    if !verbose {
+        return e.to_string();
+    }
+    let mut description = format!("{}\n  details: {:?}", e, e);
+    let mut source = e.source();
+    while let Some(cause) = source {
+        description += &format!("\n  caused by: {}", cause);
+        source = cause.source();
+    }
+    description
+}
+
+fn log_checksum(output_path: &str, common: &CommonArgs) -> Result<(), String> {
+    match &common.checksum_log {
+        Some(log_path) => checksum::log_file(log_path, output_path).map_err(|e| format!("Failed to write checksum log: {}", e)),
+        None => Ok(()),
+    }
+}
+
+// hex code followed by the rgb and hsv triples
+// what the color subcommand prints, the color before and after
+fn describe_reflection(color: Rgb<u8>, angle: f32, hue_range: HueRange) -> String {
+    let hsv = rgb_to_hsv(&color);
+    let new_hsv = hsv_reflect(&hsv, angle);
+    let new_color = reflect_color(&color, angle);
+    format!("input:  {}\noutput: {}\n", describe_color(color, &hsv.in_range(hue_range)), describe_color(new_color, &new_hsv.in_range(hue_range)))
+}
+
+fn describe_color(color: Rgb<u8>, hsv: &Hsv) -> String {
+    let [r, g, b] = color.0;
+    let [h, s, v] = hsv.0;
+    format!("{}  rgb({}, {}, {})  hsv({:.1}, {:.1}, {:.1})", palette::to_hex(color.0), r, g, b, h, s, v)
+}
+
+// one job per angle of an --angles-file, each saved with its angle in the name
+fn file_jobs(path: &str, reflect: impl Fn(Axis) -> Operation, output_path: &str) -> Result<Vec<(Operation, String)>, String> {
+    let angles = read_angles(path)?;
+    Ok(angles.into_iter().map(|angle| (reflect(Axis::Fixed(angle)), angle_output_path(output_path, angle))).collect())
+}
+
+// reads one angle per line, ignoring blank lines and anything after a #
+fn read_angles(path: &str) -> Result<Vec<f32>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read angles file {}: {}", path, e))?;
+    let mut angles = vec![];
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let angle = line.parse::<f32>().map_err(|_| format!("Angles file {}, line {}: \"{}\" is not a number", path, number + 1, line))?;
+        angles.push(angle);
+    }
+    Ok(angles)
+}
+
+// inserts the angle before the extension, output.png becomes output_90.png
+fn angle_output_path(output_path: &str, angle: f32) -> String {
+    suffixed_path(output_path, &angle.to_string())
+}
+
+// inserts _suffix before the extension
+fn suffixed_path(output_path: &str, suffix: &str) -> String {
+    let path = Path::new(output_path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+    let file_name = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{}_{}.{}", stem, suffix, extension),
+        None => format!("{}_{}", stem, suffix),
+    };
+    path.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+// makes the folder and any missing parents, an existing folder is fine but a file in the way isn't
+fn create_output_dir(dir: &str) -> Result<(), String> {
+    if Path::new(dir).exists() && !Path::new(dir).is_dir() {
+        return Err(format!("--output-dir {} is a file, not a folder", dir));
+    }
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create output folder {}: {}", dir, e))
+}
+
+// sets the modification time of target to that of source
+fn set_mtime(target: &str, modified: SystemTime) -> io::Result<()> {
+    File::options().write(true).open(target)?.set_modified(modified)
+}
+
+// save writes output_path, unless it is the input itself, then it writes a file next to it that is
+// renamed over the input once complete, so a failed or interrupted save leaves the input as it was
+fn save_over_input(input: &str, output_path: &str, save: impl FnOnce(&str) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    let same_file = fs::canonicalize(input).ok().is_some_and(|input| fs::canonicalize(output_path).ok() == Some(input));
+    if !same_file {
+        return save(output_path);
+    }
+    let partial = partial_path(output_path);
+    if let Err(e) = save(&partial) {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, output_path)?;
+    Ok(())
+}
+
+// a hidden file next to the output to save into before moving it over, the extension stays last
+// so the format is still told from it
+fn partial_path(output_path: &str) -> String {
+    let path = Path::new(output_path);
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("output");
+    let partial_name = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => format!(".{}.partial.{}", file_name, extension),
+        None => format!(".{}.partial", file_name),
+    };
+    path.with_file_name(partial_name).to_string_lossy().into_owned()
 }
 
 // fn inputf32() -> f32 {
@@ -172,4 +1333,926 @@ fn main() {
 //             Err(_) => continue,
 //         };
 //     }
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a folder of its own in the temporary directory, emptied first
+    fn scratch(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("color_reflect-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn path_in(dir: &Path, name: &str) -> String {
+        dir.join(name).to_string_lossy().into_owned()
+    }
+
+    // the options of a command line, as after color_reflect
+    fn common(args: &[&str]) -> CommonArgs {
+        match cli::parse_args(args.iter().map(|arg| arg.to_string())) {
+            Ok(Command::Reflect { common, .. } | Command::Rotate { common, .. } | Command::Invert { common } | Command::ChromaInvert { common }) => common,
+            Ok(_) => panic!("{:?} isn't an image command", args),
+            Err(message) => panic!("{:?}: {}", args, message),
+        }
+    }
+
+    // a small opaque image with every pixel a different color
+    fn colorful(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba([(x * 37) as u8, (y * 23) as u8, ((x + y) * 11) as u8, 255]))
+    }
+
+    fn reflect_job(angle: f32, output: &str) -> Mode {
+        Mode::Jobs(vec![(Operation::Reflect(Axis::Fixed(angle)), output.to_string())])
+    }
+
+    fn read(path: &str) -> RgbaImage {
+        image::open(path).unwrap().to_rgba8()
+    }
+
+    fn modified(path: &str) -> SystemTime {
+        fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    #[test]
+    fn preserve_mtime_copies_the_input_time() {
+        let dir = scratch("mtime");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        colorful(8, 8).save(&input).unwrap();
+        let then = SystemTime::now() - Duration::from_secs(3 * 24 * 3600);
+        set_mtime(&input, then).unwrap();
+        for flag in ["--preserve-mtime", "--preserve-timestamps"] {
+            let args = common(&["reflect", &input, "90", "-o", &output, flag]);
+            process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+            let apart = modified(&output).duration_since(then).unwrap_or_else(|e| e.duration());
+            // some filesystems keep times to a second or two
+            assert!(apart <= Duration::from_secs(2), "{}: the output is {:?} off", flag, apart);
+        }
+        let args = common(&["reflect", &input, "90", "-o", &output]);
+        process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+        assert!(modified(&output) > then + Duration::from_secs(24 * 3600), "the time was kept without the flag");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn outputs_carry_the_input_dpi_or_the_override() {
+        let dir = scratch("dpi");
+        let input = path_in(&dir, "in.png");
+        let plain = Encoding { bit_depth: 8, interlaced: false, compression: None, strict: false };
+        output::save(&colorful(8, 8), &input, None, Some(150.), &[], plain, Colors::Rgba).unwrap();
+        for (name, dpi, expected) in [("kept.png", None, 150.), ("kept.tif", None, 150.), ("kept.jpg", None, 150.), ("forced.png", Some("300"), 300.), ("forced.tif", Some("300"), 300.)] {
+            let output = path_in(&dir, name);
+            let mut args = vec!["reflect", &input, "90", "-o", &output];
+            args.extend(dpi.iter().flat_map(|dpi| ["--dpi", *dpi]));
+            process_input(&input, &reflect_job(90., &output), &common(&args), &None).unwrap();
+            let read = metadata::read_dpi(&output).unwrap_or_else(|| panic!("{} has no dpi", name));
+            // png stores whole pixels per meter, which is up to 0.013 dpi off
+            assert!((read - expected).abs() < 0.02, "{}: {} instead of {}", name, read, expected);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn auto_levels_stretches_a_faded_gradient_to_the_full_range() {
+        let dir = scratch("levels");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        // values from 100 to 163 only, in a warm color so there is a hue to reflect
+        RgbaImage::from_fn(64, 4, |x, _| Rgba([100 + x as u8, 80 + x as u8 / 2, 60, 255])).save(&input).unwrap();
+        let args = common(&["reflect", &input, "90", "-o", &output, "--auto-levels"]);
+        process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+        let values: Vec<u8> = read(&output).pixels().map(|pixel| pixel[0].max(pixel[1]).max(pixel[2])).collect();
+        assert_eq!(values.iter().min(), Some(&0));
+        assert_eq!(values.iter().max(), Some(&255));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_angles_file_of_five_gives_five_outputs() {
+        let dir = scratch("angles-file");
+        let (input, angles, output) = (path_in(&dir, "in.png"), path_in(&dir, "angles.txt"), path_in(&dir, "out.png"));
+        colorful(8, 8).save(&input).unwrap();
+        fs::write(&angles, "# a comment line\n0\n45.5\n\n90 # after an angle\n180\n-30\n").unwrap();
+        let args = common(&["reflect", &input, "--angles-file", &angles, "-o", &output]);
+        let jobs = file_jobs(&angles, Operation::Reflect, &output).unwrap();
+        process_input(&input, &Mode::Jobs(jobs), &args, &None).unwrap();
+        for angle in ["0", "45.5", "90", "180", "-30"] {
+            assert!(Path::new(&path_in(&dir, &format!("out_{}.png", angle))).exists(), "no output for {}", angle);
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2 + 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_two_color_image_gives_a_two_color_palette() {
+        let dir = scratch("palette-extract");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        let (red, blue) = (Rgb([255, 0, 0]), Rgb([0, 0, 255]));
+        // three quarters red, so red comes first
+        RgbaImage::from_fn(8, 8, |x, _| if x < 6 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) }).save(&input).unwrap();
+        let args = common(&["reflect", &input, "90", "-o", &output, "--palette", "5"]);
+        process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+        let swatches = read(&path_in(&dir, "out_palette.png"));
+        assert_eq!(swatches.width(), 2 * swatches.height());
+        let first = swatches.get_pixel(0, 0);
+        let second = swatches.get_pixel(swatches.width() - 1, 0);
+        assert_eq!(Rgb([first[0], first[1], first[2]]), reflect_color(&red, 90.));
+        assert_eq!(Rgb([second[0], second[1], second[2]]), reflect_color(&blue, 90.));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_opaque_tint_replaces_every_visible_pixel() {
+        let dir = scratch("tint");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        let mut img = colorful(8, 8);
+        img.put_pixel(3, 3, Rgba([10, 20, 30, 0]));
+        img.save(&input).unwrap();
+        let args = common(&["reflect", &input, "90", "-o", &output, "--tint", "#ff8800", "1"]);
+        process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+        for (x, y, pixel) in read(&output).enumerate_pixels() {
+            match (x, y) {
+                (3, 3) => assert_eq!(pixel[3], 0, "the transparent pixel"),
+                _ => assert_eq!(pixel.0, [255, 136, 0, 255], "{},{}", x, y),
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn max_dimension_shrinks_the_longest_side() {
+        let dir = scratch("max-dimension");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        colorful(2000, 1200).save(&input).unwrap();
+        let args = common(&["reflect", &input, "90", "-o", &output, "--max-dimension", "500"]);
+        process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+        let (width, height) = image::image_dimensions(&output).unwrap();
+        assert_eq!(width, 500);
+        assert!(height <= 500 && height.abs_diff(300) <= 1, "{}x{}", width, height);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_preview_every_25_percent_gives_four_previews() {
+        let dir = scratch("preview");
+        let (input, output, preview) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"), path_in(&dir, "preview.png"));
+        colorful(16, 40).save(&input).unwrap();
+        let args = common(&["reflect", &input, "90", "-o", &output, "--preview-every", "25", &preview]);
+        process_input(&input, &reflect_job(90., &output), &args, &preview_snapshots(&args)).unwrap();
+        let names = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned());
+        let mut previews: Vec<String> = names.filter(|name| name.starts_with("preview")).collect();
+        previews.sort();
+        assert_eq!(previews, ["preview_100.png", "preview_25.png", "preview_50.png", "preview_75.png"]);
+        // one that can't be saved doesn't stop the run
+        let args = common(&["reflect", &input, "90", "-o", &output, "--preview-every", "25", &path_in(&dir, "missing/preview.png")]);
+        process_input(&input, &reflect_job(90., &output), &args, &preview_snapshots(&args)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_unchanged_output_has_infinite_psnr_and_an_ssim_of_one() {
+        let dir = scratch("metrics");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        colorful(24, 24).save(&input).unwrap();
+        // a 0 degree reflect still mirrors the hues, rotating by 0 is what leaves them alone
+        let args = common(&["rotate", &input, "0", "-o", &output, "--metrics"]);
+        process_input(&input, &Mode::Jobs(vec![(Operation::Rotate(0.), output.clone())]), &args, &None).unwrap();
+        let (before, after) = (read(&input), read(&output));
+        assert_eq!(metrics::psnr(&before, &after), f64::INFINITY);
+        assert!((metrics::ssim(&before, &after) - 1.).abs() < 1e-9);
+        assert_eq!(metrics::Metric::Psnr.describe(&before, &after), "PSNR: inf dB");
+        assert_eq!(metrics::Metric::Ssim.describe(&before, &after), "SSIM: 1.0000");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn output_format_overrides_the_extension() {
+        let dir = scratch("output-format");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "result.dat"));
+        colorful(8, 8).save(&input).unwrap();
+        let Err(error) = cli::parse_args(["reflect", &input, "90", "-o", &output].map(String::from).into_iter()) else {
+            panic!("a .dat output without --output-format was taken");
+        };
+        assert!(error.contains("--output-format"), "{}", error);
+        let args = common(&["reflect", &input, "90", "-o", &output, "--output-format", "png"]);
+        process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+        let bytes = fs::read(&output).unwrap();
+        assert_eq!(image::guess_format(&bytes).unwrap(), ImageFormat::Png);
+        let expected = transform::reflect_image(&DynamicImage::ImageRgba8(colorful(8, 8)), Transform::new(Operation::Reflect(Axis::Fixed(90.))));
+        assert_eq!(image::load_from_memory(&bytes).unwrap().to_rgba8(), expected);
+        // an extension naming another format loses to the flag too
+        let jpg = path_in(&dir, "result.jpg");
+        let args = common(&["reflect", &input, "90", "-o", &jpg, "--format", "png"]);
+        process_input(&input, &reflect_job(90., &jpg), &args, &None).unwrap();
+        let bytes = fs::read(&jpg).unwrap();
+        assert_eq!(image::guess_format(&bytes).unwrap(), ImageFormat::Png);
+        assert_eq!(image::load_from_memory(&bytes).unwrap().to_rgba8(), expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reflecting_a_known_color_gives_the_expected_hex() {
+        let Ok(Command::Color { color, angle, hue_range }) = cli::parse_args(["color", "#3498db", "90"].map(String::from).into_iter()) else {
+            panic!("the color command wasn't parsed");
+        };
+        let printed = describe_reflection(color, angle, hue_range);
+        // hue 204.1 about 90 is 335.9, the same saturation and value
+        assert_eq!(printed, "input:  #3498db  rgb(52, 152, 219)  hsv(204.1, 76.3, 85.9)\noutput: #db3477  rgb(219, 52, 119)  hsv(335.9, 76.3, 85.9)\n");
+    }
+
+    #[test]
+    fn a_four_color_palette_reflects_to_four_colors() {
+        let dir = scratch("palette");
+        let (input, output, swatches) = (path_in(&dir, "in.txt"), path_in(&dir, "out.txt"), path_in(&dir, "swatches.png"));
+        fs::write(&input, "#ff0000 red\n#00ff00\n0000ff blue\n#808080\n").unwrap();
+        reflect_palette(&input, 60., Some(&output), Some(&swatches)).unwrap();
+        // about 60 red and green trade places, blue is on the axis (240 is 60 plus a half turn)
+        assert_eq!(fs::read_to_string(&output).unwrap(), "#00ff00\n#ff0000\n#0000ff\n#808080\n");
+        let drawn = read(&swatches);
+        assert_eq!(drawn.dimensions(), (4 * 32, 32));
+        assert_eq!(drawn.get_pixel(32, 0).0, [255, 0, 0, 255]);
+        let error = reflect_palette(&path_in(&dir, "missing.txt"), 60., None, None).unwrap_err();
+        assert!(error.starts_with("Failed to read palette file"), "{}", error);
+        let error = reflect_palette(&input, 60., Some(&path_in(&dir, "missing/out.txt")), None).unwrap_err();
+        assert!(error.starts_with("Failed to write palette"), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_folder_counts_what_succeeded_failed_and_was_skipped() {
+        let dir = scratch("batch");
+        let folder = dir.join("in");
+        fs::create_dir(&folder).unwrap();
+        colorful(8, 8).save(folder.join("good.png")).unwrap();
+        fs::write(folder.join("broken.png"), b"not a png").unwrap();
+        fs::write(folder.join("notes.txt"), b"not an image").unwrap();
+        let (input, output) = (folder.to_string_lossy().into_owned(), path_in(&dir, "out.png"));
+        let mut args = common(&["reflect", &input, "90", "-o", &output]);
+        let counts = process_folder(&mut args, &reflect_job(90., &output), &None, "2026-01-01", 0).unwrap();
+        assert_eq!(counts, BatchCounts { succeeded: 1, failed: 1, skipped: 1 });
+        assert!(Path::new(&path_in(&dir, "out_good.png")).exists());
+        assert!(!Path::new(&path_in(&dir, "out_broken.png")).exists());
+        let mut args = common(&["reflect", &input, "90", "-o", &output]);
+        args.input = path_in(&dir, "missing");
+        assert_eq!(process_folder(&mut args, &reflect_job(90., &output), &None, "2026-01-01", 0), Err(1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_contact_sheet_has_a_cell_for_every_angle() {
+        let dir = scratch("contact-sheet");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "sheet.png"));
+        colorful(10, 6).save(&input).unwrap();
+        let args = common(&["reflect", &input, "--contact-sheet", "30,3", "-o", &output]);
+        let angles = sheet::angles(30.);
+        assert_eq!(angles, [0., 30., 60., 90., 120., 150., 180.]);
+        let mode = Mode::ContactSheet { angles: angles.clone(), columns: 3, labels: false, output: output.clone() };
+        process_input(&input, &mode, &args, &None).unwrap();
+        // seven cells take three rows of three
+        let saved = read(&output);
+        assert_eq!(saved.dimensions(), (3 * 10, 3 * 6));
+        let original = DynamicImage::ImageRgba8(colorful(10, 6));
+        for (i, &angle) in angles.iter().enumerate() {
+            let cell = transform::reflect_image(&original, Transform::new(Operation::Reflect(Axis::Fixed(angle))));
+            let (left, top) = (i as u32 % 3 * 10, i as u32 / 3 * 6);
+            assert_eq!(image::imageops::crop_imm(&saved, left, top, 10, 6).to_image(), cell, "angle {}", angle);
+        }
+        assert_eq!(saved.get_pixel(29, 17)[3], 0, "the unused spots are transparent");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_montage_of_three_in_two_columns() {
+        let dir = scratch("montage");
+        let folder = dir.join("in");
+        fs::create_dir(&folder).unwrap();
+        let sizes = [("a.png", 8, 6), ("b.png", 10, 4), ("c.png", 6, 8)];
+        for (name, width, height) in sizes {
+            colorful(width, height).save(folder.join(name)).unwrap();
+        }
+        let (input, output) = (folder.to_string_lossy().into_owned(), path_in(&dir, "out.png"));
+        let mut args = common(&["reflect", &input, "90", "-o", &output, "--montage", "2"]);
+        process_folder(&mut args, &reflect_job(90., &output), &None, "2026-01-01", 0).unwrap();
+        // cells the size of the largest, 10x8, two across and two down
+        let montage = read(&path_in(&dir, "out_montage.png"));
+        assert_eq!(montage.dimensions(), (20, 16));
+        for (i, (_, width, height)) in sizes.into_iter().enumerate() {
+            let reflected = transform::reflect_image(&DynamicImage::ImageRgba8(colorful(width, height)), Transform::new(Operation::Reflect(Axis::Fixed(90.))));
+            let (left, top) = (i as u32 % 2 * 10, i as u32 / 2 * 8);
+            let cell = image::imageops::crop_imm(&montage, left, top, 10, 8).to_image();
+            assert_eq!(cell, sheet::fit_cell(&reflected, 10, 8), "cell {}", i);
+        }
+        assert_eq!(montage.get_pixel(15, 12)[3], 0, "the fourth spot is empty");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_queue_never_runs_more_than_k_at_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        for workers in [1, 3] {
+            let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+            let results = run_queue((0..12).collect(), workers, |item: u32| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(5));
+                running.fetch_sub(1, Ordering::SeqCst);
+                item * 2
+            });
+            assert!(most.into_inner() <= workers, "more than {} at once", workers);
+            assert_eq!(results, (0..12).map(|item| item * 2).collect::<Vec<_>>());
+        }
+        assert!(run_queue(Vec::<u32>::new(), 4, |item| item).is_empty());
+    }
+
+    #[test]
+    fn benchmark_lines_are_json_and_leave_out_a_missing_peak() {
+        let line = benchmark_line("in \"big\".png", "C:\\out.png", (640, 480), Duration::from_micros(12500), Duration::from_millis(40), Some(52_428_800));
+        assert_eq!(line, r#"{"input": "in \"big\".png", "output": "C:\\out.png", "width": 640, "height": 480, "load_ms": 12.500, "process_ms": 40.000, "peak_resident_bytes": 52428800}"#);
+        let line = benchmark_line("a.png", "b.png", (1, 2), Duration::ZERO, Duration::ZERO, None);
+        assert_eq!(line, r#"{"input": "a.png", "output": "b.png", "width": 1, "height": 2, "load_ms": 0.000, "process_ms": 0.000}"#);
+        assert_eq!(json_string("tab\there"), r#""tab\u0009here""#);
+    }
+
+    // the angle of a reflect command line with a single fixed angle
+    fn single_angle(args: &[&str]) -> f32 {
+        match cli::parse_args(args.iter().map(|arg| arg.to_string())) {
+            Ok(Command::Reflect { angles: AngleSource::Single(angle), .. }) => angle,
+            Ok(_) => panic!("{:?} doesn't give a single angle", args),
+            Err(message) => panic!("{:?}: {}", args, message),
+        }
+    }
+
+    #[test]
+    fn a_pure_red_axis_color_is_angle_0() {
+        let dir = scratch("axis-color");
+        let input = path_in(&dir, "in.png");
+        colorful(16, 16).save(&input).unwrap();
+        let by_color = single_angle(&["reflect", &input, "--axis-color", "#ff0000"]);
+        let by_angle = single_angle(&["reflect", &input, "0"]);
+        assert_eq!(by_color, by_angle);
+        let mut results = vec![];
+        for (angle, name) in [(by_color, "color.png"), (by_angle, "angle.png")] {
+            let output = path_in(&dir, name);
+            process_input(&input, &reflect_job(angle, &output), &common(&["reflect", &input, "0", "-o", &output]), &None).unwrap();
+            results.push(fs::read(&output).unwrap());
+        }
+        assert_eq!(results[0], results[1]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_border_grows_the_output_by_twice_its_width() {
+        let dir = scratch("border");
+        let input = path_in(&dir, "in.png");
+        colorful(10, 7).save(&input).unwrap();
+        let reflected = transform::reflect_image(&DynamicImage::ImageRgba8(colorful(10, 7)), Transform::new(Operation::Reflect(Axis::Fixed(90.))));
+        let green = Rgba([0, 255, 0, 255]);
+        let output = path_in(&dir, "framed.png");
+        process_input(&input, &reflect_job(90., &output), &common(&["reflect", &input, "90", "-o", &output, "--border", "3,#00ff00"]), &None).unwrap();
+        let framed = read(&output);
+        assert_eq!(framed.dimensions(), (10 + 2 * 3, 7 + 2 * 3));
+        for (x, y, pixel) in framed.enumerate_pixels() {
+            match x < 3 || y < 3 || x >= 13 || y >= 10 {
+                true => assert_eq!(*pixel, green, "border at {},{}", x, y),
+                false => assert_eq!(pixel, reflected.get_pixel(x - 3, y - 3), "image at {},{}", x, y),
+            }
+        }
+        // inset, the frame covers the edges and the size stays
+        let output = path_in(&dir, "inset.png");
+        process_input(&input, &reflect_job(90., &output), &common(&["reflect", &input, "90", "-o", &output, "--border", "2,#00ff00", "--border-inset"]), &None).unwrap();
+        let inset = read(&output);
+        assert_eq!(inset.dimensions(), (10, 7));
+        assert_eq!(*inset.get_pixel(1, 6), green);
+        assert_eq!(inset.get_pixel(2, 2), reflected.get_pixel(2, 2));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keep_metadata_carries_a_text_chunk_through() {
+        let dir = scratch("keep-metadata");
+        let input = path_in(&dir, "in.png");
+        let text = vec![(String::from("Comment"), String::from("shot at dawn")), (String::from("Author"), String::from("Ünal"))];
+        let plain = Encoding { bit_depth: 8, interlaced: false, compression: None, strict: false };
+        output::save(&colorful(8, 8), &input, None, None, &text, plain, Colors::Rgba).unwrap();
+        assert_eq!(metadata::read_text(&input), text);
+        for (name, keep, expected) in [("kept.png", true, text.clone()), ("kept.jpg", true, vec![(String::from("Comment"), String::from("shot at dawn"))]), ("stripped.png", false, vec![])] {
+            let output = path_in(&dir, name);
+            let mut args = vec!["reflect", &input, "90", "-o", &output];
+            if keep {
+                args.push("--keep-metadata");
+            }
+            process_input(&input, &reflect_job(90., &output), &common(&args), &None).unwrap();
+            let read = metadata::read_text(&output);
+            assert!(expected.iter().all(|entry| read.contains(entry)), "{}: {:?}", name, read);
+            if !keep {
+                assert!(read.is_empty(), "{}: {:?}", name, read);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // bits per channel of a saved png
+    fn png_bit_depth(path: &str) -> u8 {
+        let reader = png::Decoder::new(File::open(path).unwrap()).read_info().unwrap();
+        reader.info().bit_depth as u8
+    }
+
+    #[test]
+    fn bit_depth_8_brings_a_16_bit_png_down_to_8() {
+        let dir = scratch("bit-depth");
+        let input = path_in(&dir, "in.png");
+        let deep = image::ImageBuffer::<Rgba<u16>, _>::from_fn(8, 8, |x, y| Rgba([(x * 8000) as u16, (y * 9000) as u16, 40000, 65535]));
+        deep.save(&input).unwrap();
+        assert_eq!(png_bit_depth(&input), 16);
+        for (name, depth, expected) in [("forced.png", Some("8"), 8), ("default.png", None, 8), ("wide.png", Some("16"), 16)] {
+            let output = path_in(&dir, name);
+            let mut args = vec!["reflect", &input, "90", "-o", &output];
+            args.extend(depth.iter().flat_map(|depth| ["--bit-depth", *depth]));
+            process_input(&input, &reflect_job(90., &output), &common(&args), &None).unwrap();
+            assert_eq!(png_bit_depth(&output), expected, "{}", name);
+        }
+        // the 16 bit output is the 8 bit one widened, the input's extra bits are gone
+        let (narrow, wide) = (read(&path_in(&dir, "forced.png")), image::open(path_in(&dir, "wide.png")).unwrap().to_rgba16());
+        for (a, b) in narrow.pixels().zip(wide.pixels()) {
+            assert_eq!(a.0.map(|channel| channel as u16 * 257), b.0);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn crop_keeps_just_the_rectangle() {
+        let dir = scratch("crop");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        colorful(20, 12).save(&input).unwrap();
+        let args = common(&["reflect", &input, "90", "-o", &output, "--crop", "3,2,10,6"]);
+        process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+        let cropped = DynamicImage::ImageRgba8(image::imageops::crop_imm(&colorful(20, 12), 3, 2, 10, 6).to_image());
+        assert_eq!(read(&output), transform::reflect_image(&cropped, Transform::new(Operation::Reflect(Axis::Fixed(90.)))));
+        // then shrunk, the crop is taken from the full size input
+        let args = common(&["reflect", &input, "90", "-o", &output, "--crop", "3,2,10,6", "--max-dimension", "5"]);
+        process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+        assert_eq!(image::image_dimensions(&output).unwrap(), (5, 3));
+        let args = common(&["reflect", &input, "90", "-o", &output, "--crop", "12,2,10,6"]);
+        let error = process_input(&input, &reflect_job(90., &output), &args, &None).unwrap_err();
+        assert_eq!(error, "Crop 12,2,10,6 goes past the edge of the 20x12 image");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_grayscale_input_comes_out_byte_identical() {
+        let dir = scratch("grayscale");
+        let gray = GrayImage::from_fn(16, 9, |x, y| image::Luma([(x * 15 + y) as u8]));
+        let gray_alpha = image::GrayAlphaImage::from_fn(16, 9, |x, y| image::LumaA([(x * 15 + y) as u8, (y * 28) as u8]));
+        for (name, img) in [("gray.png", DynamicImage::ImageLuma8(gray)), ("gray_alpha.png", DynamicImage::ImageLumaA8(gray_alpha))] {
+            let (input, output) = (path_in(&dir, name), path_in(&dir, &format!("out_{}", name)));
+            img.save(&input).unwrap();
+            process_input(&input, &reflect_job(131., &output), &common(&["reflect", &input, "131", "-o", &output]), &None).unwrap();
+            let saved = image::open(&output).unwrap();
+            assert_eq!(saved.color(), img.color(), "{}", name);
+            assert_eq!(saved.as_bytes(), img.as_bytes(), "{}", name);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_stem_and_angle_template_names_every_angle() {
+        let dir = scratch("template");
+        let (input, angles) = (path_in(&dir, "photo.png"), path_in(&dir, "angles.txt"));
+        colorful(8, 8).save(&input).unwrap();
+        fs::write(&angles, "30\n90.5\n-45\n").unwrap();
+        let output = path_in(&dir, "out.png");
+        let pattern = format!("{}/{{stem}}_{{angle}}.{{ext}}", dir.display());
+        let args = common(&["reflect", &input, "--angles-file", &angles, "-o", &output, "--output-template", &pattern]);
+        let mode = Mode::Jobs(file_jobs(&angles, Operation::Reflect, &output).unwrap());
+        let mode = mode.with_template(args.output_template.as_ref().unwrap(), Path::new(&input), 0, "2026-01-01");
+        let names = ["photo_30.png", "photo_90.5.png", "photo_-45.png"].map(|name| path_in(&dir, name));
+        assert_eq!(mode.output_paths(), names);
+        process_input(&input, &mode, &args, &None).unwrap();
+        assert!(names.iter().all(|name| Path::new(name).exists()));
+        // what a .zip output names its entries without a template
+        let unnamed = mode.with_template(&Template::after_input(true, "png"), Path::new(&input), 0, "2026-01-01");
+        assert_eq!(unnamed.output_paths(), ["photo_30.png", "photo_90.5.png", "photo_-45.png"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resume_skips_an_output_that_is_already_there() {
+        let dir = scratch("resume");
+        let folder = dir.join("in");
+        fs::create_dir(&folder).unwrap();
+        for name in ["a.png", "b.png"] {
+            colorful(8, 8).save(folder.join(name)).unwrap();
+            set_mtime(&folder.join(name).to_string_lossy(), SystemTime::now() - Duration::from_secs(3600)).unwrap();
+        }
+        let (input, output) = (folder.to_string_lossy().into_owned(), path_in(&dir, "out.png"));
+        // left by an earlier run, newer than its input
+        let done = path_in(&dir, "out_a.png");
+        colorful(3, 3).save(&done).unwrap();
+        let mut args = common(&["reflect", &input, "90", "-o", &output, "--resume"]);
+        let counts = process_folder(&mut args, &reflect_job(90., &output), &None, "2026-01-01", 0).unwrap();
+        assert_eq!(counts, BatchCounts { succeeded: 1, failed: 0, skipped: 1 });
+        assert_eq!(read(&done), colorful(3, 3), "the finished output was redone");
+        assert!(Path::new(&path_in(&dir, "out_b.png")).exists());
+        let mut args = common(&["reflect", &input, "90", "-o", &output, "--resume", "--force"]);
+        let counts = process_folder(&mut args, &reflect_job(90., &output), &None, "2026-01-01", 0).unwrap();
+        assert_eq!(counts, BatchCounts { succeeded: 2, failed: 0, skipped: 0 });
+        assert_eq!(read(&done).dimensions(), (8, 8));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn output_dir_collects_the_outputs() {
+        let dir = scratch("output_dir");
+        let folder = dir.join("in");
+        fs::create_dir(&folder).unwrap();
+        for name in ["a.png", "b.png"] {
+            colorful(8, 8).save(folder.join(name)).unwrap();
+        }
+        let (input, results) = (folder.to_string_lossy().into_owned(), path_in(&dir, "results/nested"));
+        let mut args = common(&["reflect", &input, "90", "-o", "out.png", "--output-dir", &results]);
+        create_output_dir(&results).unwrap();
+        let job = reflect_job(90., &args.output.clone());
+        let counts = process_folder(&mut args, &job, &None, "2026-01-01", 0).unwrap();
+        assert_eq!(counts, BatchCounts { succeeded: 2, failed: 0, skipped: 0 });
+        let mut names: Vec<_> = fs::read_dir(&results).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, ["out_a.png", "out_b.png"]);
+        assert!(!Path::new("out_a.png").exists(), "an output landed in the working directory");
+        let in_the_way = path_in(&dir, "taken");
+        fs::write(&in_the_way, "").unwrap();
+        assert_eq!(create_output_dir(&in_the_way), Err(format!("--output-dir {} is a file, not a folder", in_the_way)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checksum_log_matches_the_saved_file() {
+        let dir = scratch("checksum_log");
+        let (input, output, log) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"), path_in(&dir, "sums.txt"));
+        colorful(8, 8).save(&input).unwrap();
+        let args = common(&["reflect", &input, "90", "-o", &output, "--checksum-log", &log]);
+        process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+        // hashed again from what is on disk now, the way sha256sum -c would check it
+        let digest = checksum::sha256(&fs::read(&output).unwrap());
+        let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(fs::read_to_string(&log).unwrap(), format!("{}  {}\n", hex, output));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn schedule_follows_image_size() {
+        let dir = scratch("pick_schedule");
+        let (small, edge, large) = (dir.join("small.png"), dir.join("edge.png"), dir.join("large.png"));
+        RgbaImage::new(16, 16).save(&small).unwrap();
+        RgbaImage::new(512, 512).save(&edge).unwrap();
+        RgbaImage::new(513, 512).save(&large).unwrap();
+        assert!(pick_schedule(&[&small, &edge]) == Schedule::AcrossFiles);
+        assert!(pick_schedule(&[&small, &edge, &large]) == Schedule::WithinFiles, "one image over SMALL_IMAGE_PIXELS");
+        assert!(pick_schedule(&[&small]) == Schedule::WithinFiles, "a single image");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn indexed_png_has_at_most_the_asked_colors() {
+        let dir = scratch("indexed");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        let distinct = |img: &RgbaImage| img.pixels().collect::<std::collections::HashSet<_>>().len();
+        // a two color image asked for more colors than it has
+        for (img, max_colors) in [(colorful(32, 32), 8), (RgbaImage::from_fn(4, 4, |x, _| Rgba([255 * (x % 2) as u8, 0, 40, 255])), 16)] {
+            img.save(&input).unwrap();
+            let limit = max_colors.to_string();
+            let args = common(&["reflect", &input, "90", "-o", &output, "--indexed", &limit]);
+            process_input(&input, &reflect_job(90., &output), &args, &None).unwrap();
+            let reader = png::Decoder::new(File::open(&output).unwrap()).read_info().unwrap();
+            assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+            let entries = reader.info().palette.as_ref().unwrap().len() / 3;
+            assert!(entries <= max_colors.min(distinct(&img)), "{} palette entries for at most {}", entries, max_colors);
+            assert!(distinct(&read(&output)) <= max_colors);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn auto_angle_turns_a_single_hue_to_its_opposite() {
+        let dir = scratch("auto_angle");
+        let input = path_in(&dir, "in.png");
+        // reflecting hue h along a gives 2a - h, the opposite hue h + 180 is reached at a = h + 90
+        for (color, angle) in [([255, 0, 0], 90.), ([255, 255, 0], 150.)] {
+            RgbaImage::from_pixel(8, 8, Rgba([color[0], color[1], color[2], 255])).save(&input).unwrap();
+            let args = common(&["reflect", &input, "--auto-angle", "-o", "out.png"]);
+            let loaded = load_input(&input, &args).unwrap();
+            let (picked, change) = picked_angle(&loaded, None, &args);
+            assert_eq!(picked, angle, "{:?}", color);
+            assert!(change > 0., "{:?}", color);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn swap_bgr_trades_red_and_blue() {
+        let dir = scratch("swap");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        RgbaImage::from_pixel(2, 2, Rgba([200, 100, 30, 128])).save(&input).unwrap();
+        // invert is exact, so only the swap is left to check
+        let args = common(&["invert", &input, "-o", &output, "--swap", "bgr"]);
+        process_input(&input, &Mode::Jobs(vec![(Operation::Invert, output.clone())]), &args, &None).unwrap();
+        assert_eq!(read(&output).get_pixel(1, 1), &Rgba([225, 155, 55, 128]));
+        let Err(error) = cli::parse_args(["invert", &input, "--swap", "bgb"].map(String::from).into_iter()) else {
+            panic!("a repeated channel was taken");
+        };
+        assert_eq!(error, "Swap order bgb must use each of r, g and b once, like bgr");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn two_frame_apng_keeps_its_frames_and_delays() {
+        let dir = scratch("apng");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        let (first, second) = (colorful(6, 4), RgbaImage::from_pixel(6, 4, Rgba([30, 200, 90, 255])));
+        let animation = Animation { default_image: Some(RgbaImage::from_pixel(6, 4, Rgba([250, 10, 10, 255]))), frames: vec![(first, (1, 10)), (second, (3, 20))], plays: 2 };
+        output::save_apng(&animation, &input, None, &[], None).unwrap();
+        process_input(&input, &reflect_job(90., &output), &common(&["reflect", &input, "90", "-o", &output]), &None).unwrap();
+        let result = apng::read(&output).expect("the output isn't animated").unwrap();
+        let reflected = |img: &RgbaImage| transform::reflect_image(&DynamicImage::ImageRgba8(img.clone()), Transform::new(Operation::Reflect(Axis::Fixed(90.))));
+        assert_eq!(result.frames.len(), 2);
+        for ((frame, delay), (original, original_delay)) in result.frames.iter().zip(&animation.frames) {
+            assert_eq!(delay, original_delay);
+            assert!(*frame == reflected(original));
+        }
+        assert!(result.default_image == animation.default_image.as_ref().map(reflected));
+        assert_eq!(result.plays, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn angle_variable_fills_in_a_missing_angle() {
+        // SAFETY: no other test reads or writes this variable, nor does anything in the threads they start
+        unsafe { env::set_var("HUE_REFLECT_ANGLE", "72.5") };
+        assert_eq!(single_angle(&["reflect", "in.png"]), 72.5);
+        assert_eq!(single_angle(&["reflect", "in.png", "30"]), 30., "the argument wins");
+        unsafe { env::set_var("HUE_REFLECT_ANGLE", "wide") };
+        let error = cli::parse_args(["reflect", "in.png"].map(String::from).into_iter()).err();
+        assert_eq!(error.as_deref(), Some("HUE_REFLECT_ANGLE must be number"));
+        unsafe { env::remove_var("HUE_REFLECT_ANGLE") };
+        let Err(error) = cli::parse_args(["reflect", "in.png"].map(String::from).into_iter()) else {
+            panic!("reflect ran without an angle");
+        };
+        assert_eq!(error, "Missing the reflect angle, give it as an argument or set HUE_REFLECT_ANGLE");
+    }
+
+    #[test]
+    fn raw_output_is_the_reflected_pixels_row_by_row() {
+        let dir = scratch("raw");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.raw"));
+        // not square, so swapped rows and columns would show
+        colorful(7, 3).save(&input).unwrap();
+        process_input(&input, &reflect_job(90., &output), &common(&["reflect", &input, "90", "-o", &output, "--raw"]), &None).unwrap();
+        let bytes = fs::read(&output).unwrap();
+        assert_eq!(bytes.len(), 7 * 3 * 4);
+        let expected = transform::reflect_image(&DynamicImage::ImageRgba8(colorful(7, 3)), Transform::new(Operation::Reflect(Axis::Fixed(90.))));
+        assert!(bytes == expected.into_raw());
+        assert_eq!(fs::read_to_string(path_in(&dir, "out.json")).unwrap(), "{\"width\": 7, \"height\": 3, \"format\": \"rgba8\"}\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sample_point_reports_a_known_pixel() {
+        let mut img = RgbaImage::from_pixel(3, 2, Rgba([0, 0, 0, 255]));
+        img.put_pixel(2, 1, Rgba([255, 102, 0, 200]));
+        let img = DynamicImage::ImageRgba8(img);
+        // hue 24 reflected along 90 is 156
+        let report = sample_report(&img, (2, 1), Transform::new(Operation::Reflect(Axis::Fixed(90.))), HueRange::Unsigned).unwrap();
+        assert_eq!(report.lines().collect::<Vec<_>>(), [
+            "Sample 2,1",
+            "  input:   #ff6600  rgb(255, 102, 0)  hsv(24.0, 100.0, 100.0)  alpha 200",
+            "  hsv:     hsv(24.000, 100.000, 100.000)",
+            "  new hsv: hsv(156.000, 100.000, 100.000)",
+            "  output:  #00ff99  rgb(0, 255, 153)  hsv(156.0, 100.0, 100.0)  alpha 200",
+        ]);
+        let outside = sample_report(&img, (3, 0), Transform::new(Operation::Invert), HueRange::Unsigned);
+        assert_eq!(outside, Err(String::from("The sample point 3,0 is outside the 3x2 image")));
+    }
+
+    #[test]
+    fn tiled_output_repeats_the_result() {
+        let dir = scratch("tile");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        colorful(5, 4).save(&input).unwrap();
+        process_input(&input, &reflect_job(90., &output), &common(&["reflect", &input, "90", "-o", &output, "--tile", "2,3"]), &None).unwrap();
+        let tiled = read(&output);
+        // 2 rows of 3 columns
+        assert_eq!(tiled.dimensions(), (5 * 3, 4 * 2));
+        let single = transform::reflect_image(&DynamicImage::ImageRgba8(colorful(5, 4)), Transform::new(Operation::Reflect(Axis::Fixed(90.))));
+        for (column, row) in [(0, 0), (2, 1)] {
+            let tile = image::imageops::crop_imm(&tiled, column * 5, row * 4, 5, 4).to_image();
+            assert!(tile == single, "tile {},{} isn't the result", column, row);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn two_stage_pipeline_matches_one_stage_after_the_other() {
+        let Ok(Command::Reflect { angles: AngleSource::Chain(chain), .. }) = cli::parse_args(["reflect", "in.png", "--pipeline", "reflect:45,brightness:0.8"].map(String::from).into_iter()) else {
+            panic!("--pipeline didn't give a chain");
+        };
+        let img = DynamicImage::ImageRgba8(colorful(16, 12));
+        let piped = transform::reflect_image(&img, Transform::new(Operation::Chain(chain)));
+        let reflected = transform::reflect_image(&img, Transform::new(Operation::Reflect(Axis::Fixed(45.))));
+        let darker = Operation::Chain(transform::Chain::new(&[transform::Stage::Brightness(0.8)]).unwrap());
+        let sequential = transform::reflect_image(&DynamicImage::ImageRgba8(reflected), Transform::new(darker));
+        // the two runs round to 8 bits between the stages
+        for (x, y, pixel) in piped.enumerate_pixels() {
+            let other = sequential.get_pixel(x, y);
+            assert!(pixel.0.iter().zip(other.0).all(|(&a, b)| a.abs_diff(b) <= 1), "{},{}: {:?} {:?}", x, y, pixel, other);
+        }
+    }
+
+    #[test]
+    fn flip_horizontal_swaps_the_columns_of_the_reflected_image() {
+        let dir = scratch("flip");
+        let (input, output) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"));
+        let colors = [[255, 0, 0, 255], [0, 200, 0, 255], [0, 0, 255, 255], [240, 200, 30, 255]];
+        let img = RgbaImage::from_fn(2, 2, |x, y| Rgba(colors[(y * 2 + x) as usize]));
+        img.save(&input).unwrap();
+        process_input(&input, &reflect_job(90., &output), &common(&["reflect", &input, "90", "-o", &output, "--flip-horizontal"]), &None).unwrap();
+        let reflected = transform::reflect_image(&DynamicImage::ImageRgba8(img.clone()), Transform::new(Operation::Reflect(Axis::Fixed(90.))));
+        let flipped = read(&output);
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            assert_eq!(flipped.get_pixel(x, y), reflected.get_pixel(1 - x, y), "{},{}", x, y);
+            assert_ne!(flipped.get_pixel(x, y), img.get_pixel(1 - x, y), "{},{} wasn't reflected", x, y);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn loaded_crop_is_exactly_its_width_and_height() {
+        let dir = scratch("crop_size");
+        let input = path_in(&dir, "in.png");
+        colorful(20, 12).save(&input).unwrap();
+        // one touching the right and bottom edges, one of the whole image and a single pixel
+        for (crop, size) in [("3,2,10,6", (10, 6)), ("11,5,9,7", (9, 7)), ("0,0,20,12", (20, 12)), ("19,11,1,1", (1, 1))] {
+            let loaded = load_input(&input, &common(&["reflect", &input, "90", "--crop", crop])).unwrap();
+            assert_eq!(loaded.img.dimensions(), size, "--crop {}", crop);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn in_place_overwrite_is_whole_and_correct() {
+        let dir = scratch("in_place");
+        fs::create_dir(dir.join("sub")).unwrap();
+        let input = path_in(&dir, "photo.png");
+        // the same file by another name
+        let output = path_in(&dir, "sub/../photo.png");
+        let original = colorful(64, 48);
+        original.save(&input).unwrap();
+        process_input(&input, &reflect_job(90., &output), &common(&["reflect", &input, "90", "-o", &output]), &None).unwrap();
+        let expected = transform::reflect_image(&DynamicImage::ImageRgba8(original), Transform::new(Operation::Reflect(Axis::Fixed(90.))));
+        assert!(read(&input) == expected, "the overwritten input isn't the reflected image");
+        // a save that fails midway leaves the input as it was and nothing next to it
+        let before = fs::read(&input).unwrap();
+        let failed = save_over_input(&input, &output, |partial| {
+            fs::write(partial, &before[..before.len() / 2])?;
+            Err("the disk filled up".into())
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read(&input).unwrap(), before);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2, "a partial file was left behind");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verbose_errors_show_the_decoder_details() {
+        let dir = scratch("verbose_errors");
+        let input = path_in(&dir, "broken.png");
+        colorful(4, 4).save(&input).unwrap();
+        // a wrong checksum on the header chunk, which sits right after the 8 byte signature
+        let mut bytes = fs::read(&input).unwrap();
+        bytes[8 + 4 + 4 + 13] ^= 0xff;
+        fs::write(&input, bytes).unwrap();
+        let error = |flags: &[&str]| {
+            let args = common(&[&["reflect", &input, "90"], flags].concat());
+            load_input(&input, &args).err().expect("a broken png was opened")
+        };
+        let (terse, verbose) = (error(&[]), error(&["--verbose-errors"]));
+        assert!(terse.starts_with("Failed to open image: Format error decoding Png: CRC error"), "{}", terse);
+        assert!(!terse.contains('\n'), "the default isn't one line: {}", terse);
+        let lines: Vec<&str> = verbose.lines().collect();
+        assert_eq!(lines[0], terse);
+        assert!(lines[1].starts_with("  details: Decoding(DecodingError { format: Exact(Png)") && lines[1].contains("CrcMismatch"), "{}", verbose);
+        assert!(lines[2].starts_with("  caused by: CRC error"), "{}", verbose);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interlaced_png_has_the_same_pixels() {
+        let dir = scratch("interlace");
+        let (input, plain, interlaced) = (path_in(&dir, "in.png"), path_in(&dir, "plain.png"), path_in(&dir, "interlaced.png"));
+        // odd sizes leave some of the seven passes partly empty
+        colorful(37, 21).save(&input).unwrap();
+        process_input(&input, &reflect_job(90., &plain), &common(&["reflect", &input, "90", "-o", &plain]), &None).unwrap();
+        process_input(&input, &reflect_job(90., &interlaced), &common(&["reflect", &input, "90", "-o", &interlaced, "--interlace"]), &None).unwrap();
+        let is_interlaced = |path: &str| png::Decoder::new(File::open(path).unwrap()).read_info().unwrap().info().interlaced;
+        assert!(is_interlaced(&interlaced) && !is_interlaced(&plain));
+        assert!(read(&interlaced) == read(&plain));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn best_compression_is_no_larger_than_fast() {
+        let dir = scratch("compress_level");
+        let input = path_in(&dir, "in.png");
+        // smooth areas with some texture, so the levels have something to find
+        RgbaImage::from_fn(96, 64, |x, y| Rgba([(x * 2) as u8, ((x * y) % 7 * 30) as u8, (y * 3) as u8, 255])).save(&input).unwrap();
+        let mut sizes = vec![];
+        for level in ["fast", "default", "best"] {
+            let output = path_in(&dir, &format!("{}.png", level));
+            process_input(&input, &reflect_job(90., &output), &common(&["reflect", &input, "90", "-o", &output, "--compress-level", level]), &None).unwrap();
+            sizes.push((fs::metadata(&output).unwrap().len(), read(&output)));
+        }
+        let (fast, best) = (&sizes[0], &sizes[2]);
+        assert!(best.0 <= fast.0, "best is {} bytes and fast {}", best.0, fast.0);
+        assert!(sizes.iter().all(|(_, pixels)| *pixels == fast.1), "the levels gave different pixels");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip_in_zip_out_round_trip() {
+        let dir = scratch("zip");
+        let (first, second, notes) = (dir.join("a.png"), dir.join("b.png"), dir.join("notes.txt"));
+        colorful(6, 4).save(&first).unwrap();
+        RgbaImage::from_pixel(3, 5, Rgba([250, 120, 10, 255])).save(&second).unwrap();
+        fs::write(&notes, "not an image").unwrap();
+        let (input, output) = (path_in(&dir, "in.zip"), path_in(&dir, "out.zip"));
+        let files = [("a.png", &first), ("pictures/b.png", &second), ("notes.txt", &notes)].map(|(name, path)| (name.to_string(), path.as_path()));
+        archive::write(&input, &files).unwrap();
+        // the steps main goes through for a zip input and output
+        let (mut args, mode, work_dir) = (common(&["reflect", &input, "90", "-o", &output]), reflect_job(90., &output), dir.join("work"));
+        let skipped = prepare_archives(&mut args, &mode, &work_dir).unwrap();
+        assert_eq!(skipped, 1, "notes.txt isn't an image");
+        let counts = process_folder(&mut args, &mode, &None, "2026-01-01", skipped).unwrap();
+        assert_eq!(counts, BatchCounts { succeeded: 2, failed: 0, skipped: 1 });
+        pack_results(&output, &work_dir.join("results")).unwrap();
+
+        let results = Archive::open(&output).unwrap();
+        let mut names: Vec<&str> = results.entries.iter().map(|entry| entry.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["a.png", "b.png"]);
+        for entry in &results.entries {
+            let original = image::open(dir.join(&entry.name)).unwrap();
+            let expected = transform::reflect_image(&original, Transform::new(Operation::Reflect(Axis::Fixed(90.))));
+            assert!(image::load_from_memory(&results.read(entry).unwrap()).unwrap().to_rgba8() == expected, "{}", entry.name);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_rotate_0_change_mask_is_all_black() {
+        let dir = scratch("change_mask");
+        let (input, output, mask) = (path_in(&dir, "in.png"), path_in(&dir, "out.png"), path_in(&dir, "mask.png"));
+        colorful(12, 9).save(&input).unwrap();
+        // reflecting along 0 moves every hue but red and cyan, a turn by 0 is what leaves them all
+        let args = common(&["rotate", &input, "0", "-o", &output, "--change-mask", &mask]);
+        process_input(&input, &Mode::Jobs(vec![(Operation::Rotate(0.), output.clone())]), &args, &None).unwrap();
+        let black = image::open(&mask).unwrap().to_luma8();
+        assert_eq!(black.dimensions(), (12, 9));
+        assert!(black.pixels().all(|pixel| pixel[0] == 0), "a turn by 0 changed some pixel");
+        let args = common(&["reflect", &input, "0", "-o", &output, "--change-mask", &mask]);
+        process_input(&input, &reflect_job(0., &output), &args, &None).unwrap();
+        assert!(image::open(&mask).unwrap().to_luma8().pixels().any(|pixel| pixel[0] == 255));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn streamed_png_decodes_like_a_batch_saved_one() {
+        let dir = scratch("stream");
+        let (input, streamed, batch) = (path_in(&dir, "in.png"), path_in(&dir, "streamed.png"), path_in(&dir, "batch.png"));
+        colorful(90, 70).save(&input).unwrap();
+        let args = common(&["reflect", &input, "90", "-o", &streamed]);
+        let loaded = load_input(&input, &args).unwrap();
+        assert!(streams_result(&streamed, &loaded, &args));
+        // several workers, so rows reach the encoder out of order
+        let transform = Transform { threads: Some(4), ..build_transform(Operation::Reflect(Axis::Fixed(90.)), &loaded, &args) };
+        let (new_img, partial) = stream_result(&loaded, transform, &None, &streamed, &args);
+        fs::rename(partial.expect("the stream failed"), &streamed).unwrap();
+        let plain = Encoding { bit_depth: 8, interlaced: false, compression: None, strict: false };
+        output::save(&new_img, &batch, None, None, &[], plain, Colors::Rgba).unwrap();
+        assert!(read(&streamed) == read(&batch));
+        assert!(read(&streamed) == transform::reflect_image(&loaded.img, transform));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn strict_refuses_an_rgb_input_without_allow_rgb() {
+        let dir = scratch("strict_rgb");
+        let input = path_in(&dir, "in.png");
+        DynamicImage::ImageRgba8(colorful(5, 5)).to_rgb8().save(&input).unwrap();
+        let error = load_input(&input, &common(&["reflect", &input, "90", "--strict"])).err();
+        let expected = format!("Stopping for --strict: {} is an rgb image without an alpha channel, add --allow-rgb to take it as fully opaque", input);
+        assert_eq!(error, Some(expected));
+        assert!(load_input(&input, &common(&["reflect", &input, "90", "--strict", "--allow-rgb"])).is_ok());
+        assert!(load_input(&input, &common(&["reflect", &input, "90"])).is_ok(), "without --strict an rgb input is fine");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
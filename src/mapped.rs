@@ -0,0 +1,63 @@
+// decoding inputs straight from a memory map of the file, with the mmap feature on unix,
+// elsewhere load always gives None and inputs are read the usual way
+
+use image::{DynamicImage, ImageError};
+
+// None when mapping isn't available or the file couldn't be mapped, the caller then opens it normally
+#[cfg(all(feature = "mmap", unix))]
+pub fn load(path: &str) -> Option<Result<DynamicImage, ImageError>> {
+    let mapped = Mapped::open(path)?;
+    // the extension decides like image::open would, formats without one are told from their first bytes
+    Some(match image::ImageFormat::from_path(path) {
+        Ok(format) => image::load_from_memory_with_format(mapped.bytes(), format),
+        Err(_) => image::load_from_memory(mapped.bytes()),
+    })
+}
+
+#[cfg(not(all(feature = "mmap", unix)))]
+pub fn load(_path: &str) -> Option<Result<DynamicImage, ImageError>> {
+    None
+}
+
+// a read only mapping of a whole file, unmapped when dropped
+#[cfg(all(feature = "mmap", unix))]
+struct Mapped {
+    data: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Mapped {
+    fn open(path: &str) -> Option<Mapped> {
+        use std::os::fd::AsRawFd;
+
+        let file = std::fs::File::open(path).ok()?;
+        let len = usize::try_from(file.metadata().ok()?.len()).ok()?;
+        // mmap refuses a length of 0
+        if len == 0 {
+            return None;
+        }
+        // safe, a new private read only mapping, it stays valid after the file is closed
+        // (the file changing on disk while it is decoded can still give garbage, like any read would)
+        let data = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if data == libc::MAP_FAILED {
+            return None;
+        }
+        Some(Mapped { data, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // safe, data points at len readable bytes until drop unmaps them
+        unsafe { std::slice::from_raw_parts(self.data as *const u8, self.len) }
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        // safe, unmaps exactly the range open mapped
+        unsafe {
+            libc::munmap(self.data, self.len);
+        }
+    }
+}
@@ -0,0 +1,20 @@
+// best effort peak memory probe, None where the platform doesn't offer one
+
+// most resident memory the whole process has used so far, in bytes
+#[cfg(unix)]
+pub fn peak_resident_bytes() -> Option<u64> {
+    // safe, getrusage only writes into the rusage struct we pass
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    if result != 0 || usage.ru_maxrss <= 0 {
+        return None;
+    }
+    // macos reports bytes, linux and the bsds kilobytes
+    let scale = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    Some(usage.ru_maxrss as u64 * scale)
+}
+
+#[cfg(not(unix))]
+pub fn peak_resident_bytes() -> Option<u64> {
+    None
+}
@@ -0,0 +1,131 @@
+use std::{fs::{self, File}, io::BufReader, path::Path};
+
+use tiff::{decoder::{ifd::Value, Decoder}, tags::Tag};
+
+const METERS_PER_INCH: f32 = 0.0254;
+// png keyword xmp is stored under, also used for xmp read from jpegs
+pub const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+// jpeg comments are carried as this keyword
+pub const COMMENT_KEYWORD: &str = "Comment";
+// what a jpeg APP1 segment starts with when it holds xmp
+pub const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+// reads the resolution stored in an image file, in dots per inch
+// supports png (pHYs chunk), jpeg (JFIF header) and tiff (XResolution tag)
+pub fn read_dpi(path: &str) -> Option<f32> {
+    let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => read_png_dpi(path),
+        "jpg" | "jpeg" => read_jpeg_dpi(path),
+        "tif" | "tiff" => read_tiff_dpi(path),
+        _ => None,
+    }
+}
+
+fn read_png_dpi(path: &str) -> Option<f32> {
+    let decoder = png::Decoder::new(BufReader::new(File::open(path).ok()?));
+    let reader = decoder.read_info().ok()?;
+    let dims = reader.info().pixel_dims?;
+    match dims.unit {
+        png::Unit::Meter => Some(dims.xppu as f32 * METERS_PER_INCH),
+        png::Unit::Unspecified => None,
+    }
+}
+
+fn read_jpeg_dpi(path: &str) -> Option<f32> {
+    let bytes = fs::read(path).ok()?;
+    // SOI marker followed directly by a JFIF APP0 segment
+    if bytes.len() < 18 || bytes[0..4] != [0xFF, 0xD8, 0xFF, 0xE0] || &bytes[6..11] != b"JFIF\0" {
+        return None;
+    }
+    let units = bytes[13];
+    let x_density = u16::from_be_bytes([bytes[14], bytes[15]]) as f32;
+    match units {
+        1 => Some(x_density),
+        2 => Some(x_density * 2.54),
+        _ => None,
+    }
+}
+
+fn read_tiff_dpi(path: &str) -> Option<f32> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path).ok()?)).ok()?;
+    let resolution = match decoder.find_tag(Tag::XResolution).ok()?? {
+        Value::Rational(n, d) if d != 0 => n as f32 / d as f32,
+        _ => return None,
+    };
+    // resolution unit defaults to inches when the tag is absent
+    let unit = match decoder.find_tag(Tag::ResolutionUnit).ok()? {
+        Some(value) => value.into_u16().ok()?,
+        None => 2,
+    };
+    match unit {
+        2 => Some(resolution),
+        3 => Some(resolution * 2.54),
+        _ => None,
+    }
+}
+
+// keyword and text of every text chunk (png) or comment and xmp segment (jpeg)
+// exif isn't read, so camera details and gps positions never make it to the output
+pub fn read_text(path: &str) -> Vec<(String, String)> {
+    let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    let text = match extension.as_str() {
+        "png" => read_png_text(path),
+        "jpg" | "jpeg" => read_jpeg_text(path),
+        _ => None,
+    };
+    text.unwrap_or_default()
+}
+
+fn read_png_text(path: &str) -> Option<Vec<(String, String)>> {
+    let decoder = png::Decoder::new(BufReader::new(File::open(path).ok()?));
+    let mut reader = decoder.read_info().ok()?;
+    // chunks after the image data are only read once it has been decoded
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut buffer).ok()?;
+    reader.finish().ok()?;
+    let info = reader.info();
+
+    let mut text = vec![];
+    for chunk in &info.uncompressed_latin1_text {
+        text.push((chunk.keyword.clone(), chunk.text.clone()));
+    }
+    for chunk in &info.compressed_latin1_text {
+        text.push((chunk.keyword.clone(), chunk.get_text().ok()?));
+    }
+    for chunk in &info.utf8_text {
+        text.push((chunk.keyword.clone(), chunk.get_text().ok()?));
+    }
+    Some(text)
+}
+
+fn read_jpeg_text(path: &str) -> Option<Vec<(String, String)>> {
+    let bytes = fs::read(path).ok()?;
+    if bytes.get(0..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut text = vec![];
+    let mut position = 2;
+    // segments follow each other up to the start of the scan data
+    while let [0xFF, marker, high, low, ..] = bytes[position..] {
+        if marker == 0xDA {
+            break;
+        }
+        let length = u16::from_be_bytes([high, low]) as usize;
+        let data = bytes.get(position + 4..position + 2 + length)?;
+        match marker {
+            0xFE => text.push((COMMENT_KEYWORD.to_string(), String::from_utf8_lossy(data).into_owned())),
+            0xE1 if data.starts_with(JPEG_XMP_HEADER) => {
+                let xmp = &data[JPEG_XMP_HEADER.len()..];
+                text.push((XMP_KEYWORD.to_string(), String::from_utf8_lossy(xmp).into_owned()));
+            }
+            _ => {}
+        }
+        position += 2 + length;
+    }
+    Some(text)
+}
+
+pub fn dpi_to_pixels_per_meter(dpi: f32) -> u32 {
+    (dpi / METERS_PER_INCH).round() as u32
+}
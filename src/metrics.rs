@@ -0,0 +1,151 @@
+use image::{GrayImage, Luma, RgbaImage};
+
+const WINDOW: u32 = 8;
+const STRIDE: u32 = 4;
+// stabilizing constants from the ssim paper, for 8-bit values
+const C1: f64 = (0.01 * 255.) * (0.01 * 255.);
+const C2: f64 = (0.03 * 255.) * (0.03 * 255.);
+
+// the similarity measures --metric can pick
+#[derive(Clone, Copy, PartialEq)]
+pub enum Metric {
+    Psnr,
+    Ssim,
+}
+
+impl Metric {
+    pub fn parse(name: &str) -> Option<Metric> {
+        match name {
+            "psnr" => Some(Metric::Psnr),
+            "ssim" => Some(Metric::Ssim),
+            _ => None,
+        }
+    }
+
+    // the metric's name and value, like PSNR: 31.20 dB
+    pub fn describe(&self, a: &RgbaImage, b: &RgbaImage) -> String {
+        match self {
+            Metric::Psnr => format!("PSNR: {:.2} dB", psnr(a, b)),
+            Metric::Ssim => format!("SSIM: {:.4}", ssim(a, b)),
+        }
+    }
+}
+
+// peak signal to noise ratio over the rgb channels in dB, infinite for identical images
+pub fn psnr(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let mut squared_error = 0.;
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        for channel in 0..3 {
+            let difference = pa[channel] as f64 - pb[channel] as f64;
+            squared_error += difference * difference;
+        }
+    }
+    let mse = squared_error / (a.width() as f64 * a.height() as f64 * 3.);
+    if mse == 0. {
+        return f64::INFINITY;
+    }
+    10. * (255. * 255. / mse).log10()
+}
+
+// average absolute difference of the rgb channels, 0 for identical images and 255 at most
+pub fn mean_difference(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let mut total = 0.;
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        for channel in 0..3 {
+            total += (pa[channel] as f64 - pb[channel] as f64).abs();
+        }
+    }
+    total / (a.width() as f64 * a.height() as f64 * 3.)
+}
+
+// structural similarity of the luma channels, averaged over 8x8 windows
+// 1 means identical
+pub fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let (luma_a, luma_b) = (luma(a), luma(b));
+    let (width, height) = (a.width(), a.height());
+    // images smaller than a window are compared as one window
+    let window_width = WINDOW.min(width);
+    let window_height = WINDOW.min(height);
+
+    let mut total = 0.;
+    let mut windows = 0;
+    for top in (0..=height - window_height).step_by(STRIDE as usize) {
+        for left in (0..=width - window_width).step_by(STRIDE as usize) {
+            let index = |x: u32, y: u32| ((top + y) * width + left + x) as usize;
+            let count = (window_width * window_height) as f64;
+            let (mut sum_a, mut sum_b) = (0., 0.);
+            for y in 0..window_height {
+                for x in 0..window_width {
+                    sum_a += luma_a[index(x, y)];
+                    sum_b += luma_b[index(x, y)];
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+            let (mut var_a, mut var_b, mut covariance) = (0., 0., 0.);
+            for y in 0..window_height {
+                for x in 0..window_width {
+                    let da = luma_a[index(x, y)] - mean_a;
+                    let db = luma_b[index(x, y)] - mean_b;
+                    var_a += da * da;
+                    var_b += db * db;
+                    covariance += da * db;
+                }
+            }
+            // sample variance, as in the reference implementation
+            let divisor = (count - 1.).max(1.);
+            let (var_a, var_b, covariance) = (var_a / divisor, var_b / divisor, covariance / divisor);
+
+            total += ((2. * mean_a * mean_b + C1) * (2. * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+fn luma(img: &RgbaImage) -> Vec<f64> {
+    img.pixels()
+        .map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64)
+        .collect()
+}
+
+// white where the rgb of two images of the same size differs and black where it is the same,
+// alpha isn't compared
+pub fn change_mask(a: &RgbaImage, b: &RgbaImage) -> GrayImage {
+    GrayImage::from_fn(a.width(), a.height(), |x, y| {
+        let changed = a.get_pixel(x, y).0[..3] != b.get_pixel(x, y).0[..3];
+        Luma([if changed { 255 } else { 0 }])
+    })
+}
+
+// the first pixel, in reading order, where two images of the same size differ
+pub fn first_difference(a: &RgbaImage, b: &RgbaImage) -> Option<(u32, u32)> {
+    let index = a.pixels().zip(b.pixels()).position(|(pa, pb)| pa != pb)?;
+    Some((index as u32 % a.width(), index as u32 / a.width()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn pattern(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba([(x * 29) as u8, (y * 17) as u8, ((x ^ y) * 13) as u8, 255]))
+    }
+
+    #[test]
+    fn identical_images_score_perfectly() {
+        // smaller than a window as well as several windows
+        for (width, height) in [(3, 2), (20, 13)] {
+            let img = pattern(width, height);
+            assert_eq!(psnr(&img, &img), f64::INFINITY, "{}x{}", width, height);
+            assert!((ssim(&img, &img) - 1.).abs() < 1e-12, "{}x{}", width, height);
+            assert_eq!(mean_difference(&img, &img), 0.);
+        }
+        let img = pattern(20, 13);
+        let mut changed = img.clone();
+        changed.put_pixel(5, 5, Rgba([0, 0, 0, 255]));
+        assert!(psnr(&img, &changed).is_finite());
+        assert!(ssim(&img, &changed) < 1.);
+    }
+}
@@ -0,0 +1,463 @@
+use std::{error::Error, fs::{self, File}, io::{BufWriter, Cursor, Seek, Write}, path::Path};
+
+use fdeflate::{Compressor, StoredOnlyCompressor};
+use flate2::{write::ZlibEncoder, Compression};
+use image::{
+    codecs::jpeg::{JpegEncoder, PixelDensity},
+    DynamicImage, ImageFormat, RgbaImage,
+};
+use tiff::encoder::{colortype, Rational, TiffEncoder, TiffValue};
+use tiff::tags::ResolutionUnit;
+
+use crate::apng::Animation;
+use crate::metadata::{dpi_to_pixels_per_meter, COMMENT_KEYWORD, JPEG_XMP_HEADER, XMP_KEYWORD};
+use crate::palette;
+
+// saves the image in the given format, or the one matching the extension
+// if a dpi is given it is written into the png, jpeg or tiff resolution metadata,
+// text (from metadata::read_text) is written into png text chunks or jpeg comments and xmp,
+// encoding sets the bits per channel, interlacing and compression
+pub fn save(img: &RgbaImage, path: &str, format: Option<ImageFormat>, dpi: Option<f32>, text: &[(String, String)], encoding: Encoding, colors: Colors) -> Result<(), Box<dyn Error>> {
+    let format = match format {
+        Some(format) => format,
+        None => ImageFormat::from_path(path)
+            .map_err(|_| format!("Can't tell the output format from {}, pick one with --output-format", path))?,
+    };
+    let name = format.extensions_str()[0];
+    if !supports_bit_depth(format, encoding.bit_depth) {
+        return Err(format!("{} files can't be saved with {} bits per channel, use png or tiff", name, encoding.bit_depth).into());
+    }
+    if encoding.interlaced && format != ImageFormat::Png {
+        return Err(format!("{} files can't be saved interlaced, use png", name).into());
+    }
+    if encoding.compression.is_some() && format != ImageFormat::Png {
+        return Err(format!("{} files have no compression level to pick, use png", name).into());
+    }
+    if !text.is_empty() && !matches!(format, ImageFormat::Png | ImageFormat::Jpeg) {
+        warn(encoding.strict, format!("metadata can't be kept in {} files", name), ", leaving it out")?;
+    }
+    if let Colors::Indexed(_) = colors && format != ImageFormat::Png {
+        return Err(format!("{} files can't be saved with a palette, use png", name).into());
+    }
+    let grayscale = colors == Colors::Grayscale;
+    let channels = channels(img, grayscale);
+    match format {
+        ImageFormat::Png => match colors {
+            Colors::Indexed(max_colors) => save_indexed_png(img, path, dpi, text, max_colors, encoding),
+            _ => save_png(img, path, dpi, text, encoding, channels),
+        },
+        ImageFormat::Jpeg => save_jpeg(img, path, dpi, text, grayscale, encoding.strict),
+        ImageFormat::Tiff if dpi.is_some() || encoding.bit_depth == 16 || grayscale => save_tiff(img, path, dpi, encoding.bit_depth, channels),
+        _ => {
+            if dpi.is_some() {
+                warn(encoding.strict, format!("DPI can't be stored in {} files", name), ", ignoring")?;
+            }
+            img.save_with_format(path, format)?;
+            Ok(())
+        }
+    }
+}
+
+// how the colors of the result are stored
+#[derive(Clone, Copy, PartialEq)]
+pub enum Colors {
+    Rgba,
+    // for results where r, g and b are always equal, png, jpeg and tiff then get a single gray channel
+    Grayscale,
+    // quantized to a palette of at most this many colors, png only
+    Indexed(usize),
+}
+
+// how the samples are written
+#[derive(Clone, Copy)]
+pub struct Encoding {
+    // 8 or 16 bits per channel, 16 only for formats where supports_bit_depth says so
+    pub bit_depth: u8,
+    // adam7 interlaced, png only
+    pub interlaced: bool,
+    // how hard png data is compressed, the png crate's fast setting when None
+    pub compression: Option<png::Compression>,
+    // --strict, what would only be warned about is an error instead
+    pub strict: bool,
+}
+
+// prints a warning about what can't be done and what happens instead, with --strict it is an error
+// so a script notices
+pub fn warn(strict: bool, problem: String, fallback: &str) -> Result<(), String> {
+    if strict {
+        return Err(format!("Stopping for --strict: {}", problem));
+    }
+    println!("Warning: {}{}", problem, fallback);
+    Ok(())
+}
+
+// jpeg and hdr files have no alpha channel, transparent results are flattened before saving to them
+pub fn supports_alpha(format: ImageFormat) -> bool {
+    !matches!(format, ImageFormat::Jpeg | ImageFormat::Hdr)
+}
+
+// every format takes 8 bits per channel, only png and tiff are written with 16
+pub fn supports_bit_depth(format: ImageFormat, bit_depth: u8) -> bool {
+    match bit_depth {
+        8 => true,
+        16 => matches!(format, ImageFormat::Png | ImageFormat::Tiff),
+        _ => false,
+    }
+}
+
+// each 8 bit channel spread over the 16 bit range, 255 becomes 65535
+fn widen(samples: &[u8]) -> Vec<u16> {
+    samples.iter().map(|&channel| channel as u16 * 257).collect()
+}
+
+// which channels of the result are written
+#[derive(Clone, Copy, PartialEq)]
+enum Channels {
+    Rgba,
+    Gray,
+    GrayAlpha,
+}
+
+// alpha is only kept for a gray result when some pixel isn't fully opaque
+fn channels(img: &RgbaImage, grayscale: bool) -> Channels {
+    match grayscale {
+        false => Channels::Rgba,
+        true if img.pixels().all(|pixel| pixel[3] == 255) => Channels::Gray,
+        true => Channels::GrayAlpha,
+    }
+}
+
+// the raw samples of img with just the given channels, the gray is read from red
+fn samples(img: &RgbaImage, channels: Channels) -> Vec<u8> {
+    match channels {
+        Channels::Rgba => img.as_raw().clone(),
+        Channels::Gray => img.pixels().map(|pixel| pixel[0]).collect(),
+        Channels::GrayAlpha => img.pixels().flat_map(|pixel| [pixel[0], pixel[3]]).collect(),
+    }
+}
+
+// format named on the command line, by its usual extension
+pub fn parse_format(name: &str) -> Option<ImageFormat> {
+    ImageFormat::from_extension(name)
+}
+
+// png compression named on the command line
+pub fn parse_compression(name: &str) -> Option<png::Compression> {
+    match name {
+        "fast" => Some(png::Compression::Fast),
+        "default" => Some(png::Compression::Default),
+        "best" => Some(png::Compression::Best),
+        _ => None,
+    }
+}
+
+fn save_png(img: &RgbaImage, path: &str, dpi: Option<f32>, text: &[(String, String)], encoding: Encoding, channels: Channels) -> Result<(), Box<dyn Error>> {
+    let bit_depth = encoding.bit_depth;
+    let mut encoder = png_encoder(img.dimensions(), path, dpi, text, encoding)?;
+    encoder.set_color(match channels {
+        Channels::Rgba => png::ColorType::Rgba,
+        Channels::Gray => png::ColorType::Grayscale,
+        Channels::GrayAlpha => png::ColorType::GrayscaleAlpha,
+    });
+    encoder.set_depth(if bit_depth == 16 { png::BitDepth::Sixteen } else { png::BitDepth::Eight });
+    let mut writer = encoder.write_header()?;
+    let samples = samples(img, channels);
+    let data = match bit_depth {
+        // png stores 16 bit samples big endian
+        16 => widen(&samples).iter().flat_map(|channel| channel.to_be_bytes()).collect(),
+        _ => samples,
+    };
+    let count = match channels {
+        Channels::Rgba => 4,
+        Channels::Gray => 1,
+        Channels::GrayAlpha => 2,
+    };
+    write_png_data(&mut writer, &data, img.dimensions(), count * bit_depth as usize, encoding)
+}
+
+fn save_indexed_png(img: &RgbaImage, path: &str, dpi: Option<f32>, text: &[(String, String)], max_colors: usize, encoding: Encoding) -> Result<(), Box<dyn Error>> {
+    if img.pixels().any(|pixel| pixel[3] > 0 && pixel[3] < 255) {
+        warn(encoding.strict, String::from("partly transparent pixels become opaque in indexed png files"), "")?;
+    }
+    let indexed = palette::quantize(img, max_colors);
+    // the fewest bits per pixel that can tell every palette entry apart
+    let bits = [1, 2, 4, 8].into_iter().find(|&bits| indexed.palette.len() <= 1 << bits).unwrap_or(8);
+    let mut encoder = png_encoder(img.dimensions(), path, dpi, text, encoding)?;
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(match bits {
+        1 => png::BitDepth::One,
+        2 => png::BitDepth::Two,
+        4 => png::BitDepth::Four,
+        _ => png::BitDepth::Eight,
+    });
+    encoder.set_palette(indexed.palette.concat());
+    if let Some(transparent) = indexed.transparent {
+        // alpha for each entry up to the transparent one, the ones after it are opaque
+        let mut alphas = vec![255; transparent as usize + 1];
+        alphas[transparent as usize] = 0;
+        encoder.set_trns(alphas);
+    }
+    let mut writer = encoder.write_header()?;
+    // each row starts on a new byte, the first pixel in the highest bits
+    let per_byte = 8 / bits;
+    let data: Vec<u8> = indexed
+        .indices
+        .chunks(img.width() as usize)
+        .flat_map(|row| row.chunks(per_byte).map(|pixels| pixels.iter().enumerate().fold(0, |byte, (i, &index)| byte | index << (8 - bits * (i + 1)))))
+        .collect();
+    write_png_data(&mut writer, &data, img.dimensions(), bits, encoding)
+}
+
+// where each adam7 pass starts and how far apart its pixels are, as column, row, column step and row step
+const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+// data is the rows of the whole image, bits is how many each pixel takes up, the png crate
+// marks the file interlaced but can only write the data in order so the passes are written here
+fn write_png_data<W: Write>(writer: &mut png::Writer<W>, data: &[u8], (width, height): (u32, u32), bits: usize, encoding: Encoding) -> Result<(), Box<dyn Error>> {
+    if !encoding.interlaced {
+        writer.write_image_data(data)?;
+        return Ok(());
+    }
+    let stride = (width as usize * bits).div_ceil(8);
+    // the sub filter each row gets (the png crate's default too) takes away the byte a pixel back,
+    // or just the byte before for pixels smaller than one
+    let distance = (bits / 8).max(1);
+    let mut zlib = ZlibEncoder::new(vec![], zlib_level(encoding.compression));
+    for (first_column, first_row, column_step, row_step) in ADAM7_PASSES {
+        let columns: Vec<usize> = (first_column..width).step_by(column_step as usize).map(|x| x as usize).collect();
+        // passes with no pixels in them have no rows either, not even empty ones
+        if columns.is_empty() {
+            continue;
+        }
+        for y in (first_row..height).step_by(row_step as usize) {
+            let source = &data[y as usize * stride..][..stride];
+            let mut row = vec![0; (columns.len() * bits).div_ceil(8)];
+            for (i, &x) in columns.iter().enumerate() {
+                if bits >= 8 {
+                    let size = bits / 8;
+                    row[i * size..][..size].copy_from_slice(&source[x * size..][..size]);
+                } else {
+                    // packed pixels, the first in the highest bits of each byte
+                    let value = source[x * bits / 8] >> (8 - bits - x * bits % 8) & ((1 << bits) - 1);
+                    row[i * bits / 8] |= value << (8 - bits - i * bits % 8);
+                }
+            }
+            let filtered: Vec<u8> = (0..row.len()).map(|i| row[i].wrapping_sub(if i >= distance { row[i - distance] } else { 0 })).collect();
+            zlib.write_all(&[png::FilterType::Sub as u8])?;
+            zlib.write_all(&filtered)?;
+        }
+    }
+    for chunk in zlib.finish()?.chunks(i32::MAX as usize) {
+        writer.write_chunk(png::chunk::IDAT, chunk)?;
+    }
+    Ok(())
+}
+
+// the zlib levels the png crate uses for the same settings
+fn zlib_level(compression: Option<png::Compression>) -> Compression {
+    match compression {
+        Some(png::Compression::Best) => Compression::best(),
+        Some(png::Compression::Default) => Compression::default(),
+        _ => Compression::fast(),
+    }
+}
+
+// the largest idat chunk the png crate writes, the compressed data is split into as few as fit
+const MOST_IDAT_BYTES: usize = (u32::MAX >> 1) as usize;
+
+// an 8 bit rgba png that isn't interlaced written a row at a time, top to bottom, while the rest
+// of the image is still being made, the file comes out byte for byte the same as save would write
+pub struct PngStream {
+    writer: png::Writer<BufWriter<File>>,
+    deflater: Deflater,
+}
+
+enum Deflater {
+    // the png crate's fast setting goes through fdeflate instead of zlib
+    Fast(Compressor<Vec<u8>>),
+    Zlib(ZlibEncoder<Vec<u8>>),
+}
+
+impl PngStream {
+    pub fn create(path: &str, size: (u32, u32), dpi: Option<f32>, text: &[(String, String)], compression: Option<png::Compression>) -> Result<PngStream, Box<dyn Error>> {
+        let mut encoder = png_encoder(size, path, dpi, text, Encoding { bit_depth: 8, interlaced: false, compression, strict: false })?;
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let writer = encoder.write_header()?;
+        let deflater = match compression {
+            None | Some(png::Compression::Fast) => Deflater::Fast(Compressor::new(vec![])?),
+            _ => Deflater::Zlib(ZlibEncoder::new(vec![], zlib_level(compression))),
+        };
+        Ok(PngStream { writer, deflater })
+    }
+
+    // the next row's samples, with the sub filter the png crate gives every row by default
+    pub fn write_row(&mut self, row: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut filtered = Vec::with_capacity(row.len() + 1);
+        filtered.push(png::FilterType::Sub as u8);
+        filtered.extend((0..row.len()).map(|i| row[i].wrapping_sub(if i >= 4 { row[i - 4] } else { 0 })));
+        match &mut self.deflater {
+            Deflater::Fast(compressor) => compressor.write_data(&filtered)?,
+            Deflater::Zlib(zlib) => zlib.write_all(&filtered)?,
+        }
+        Ok(())
+    }
+
+    // img is the whole result, like the png crate the rows are stored uncompressed
+    // (and unfiltered) instead when fdeflate made them bigger
+    pub fn finish(mut self, img: &RgbaImage) -> Result<(), Box<dyn Error>> {
+        let data = match self.deflater {
+            Deflater::Fast(compressor) => {
+                let compressed = compressor.finish()?;
+                let stride = img.width() as usize * 4;
+                if compressed.len() > StoredOnlyCompressor::<()>::compressed_size((stride + 1) * img.height() as usize) {
+                    let mut stored = StoredOnlyCompressor::new(Cursor::new(vec![]))?;
+                    for row in img.as_raw().chunks(stride) {
+                        stored.write_data(&[png::FilterType::NoFilter as u8])?;
+                        stored.write_data(row)?;
+                    }
+                    stored.finish()?.into_inner()
+                } else {
+                    compressed
+                }
+            }
+            Deflater::Zlib(zlib) => zlib.finish()?,
+        };
+        for chunk in data.chunks(MOST_IDAT_BYTES) {
+            self.writer.write_chunk(png::chunk::IDAT, chunk)?;
+        }
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+// the pixels row by row from the top, 4 bytes each, and "<name>.json" with the size for whoever reads them
+pub fn save_raw(img: &RgbaImage, path: &str) -> Result<(), Box<dyn Error>> {
+    fs::write(path, img.as_raw())?;
+    let sidecar = Path::new(path).with_extension("json");
+    let description = format!("{{\"width\": {}, \"height\": {}, \"format\": \"rgba8\"}}\n", img.width(), img.height());
+    fs::write(sidecar, description)?;
+    Ok(())
+}
+
+// every frame is written whole, replacing the one before, so the frames can say how long they are
+// shown and nothing else, the dpi and text go in once for the file
+pub fn save_apng(animation: &Animation, path: &str, dpi: Option<f32>, text: &[(String, String)], compression: Option<png::Compression>) -> Result<(), Box<dyn Error>> {
+    let Some((first, _)) = animation.frames.first() else {
+        return Err("The animation has no frames".into());
+    };
+    let mut encoder = png_encoder(first.dimensions(), path, dpi, text, Encoding { bit_depth: 8, interlaced: false, compression, strict: false })?;
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(animation.frames.len() as u32, animation.plays)?;
+    encoder.set_sep_def_img(animation.default_image.is_some())?;
+    encoder.set_blend_op(png::BlendOp::Source)?;
+    encoder.set_dispose_op(png::DisposeOp::None)?;
+    let mut writer = encoder.write_header()?;
+    if let Some(default_image) = &animation.default_image {
+        writer.write_image_data(default_image)?;
+    }
+    for (frame, (numerator, denominator)) in &animation.frames {
+        writer.set_frame_delay(*numerator, *denominator)?;
+        writer.write_image_data(frame)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+// an encoder with the resolution, text, interlacing and compression already set
+fn png_encoder<'a>((width, height): (u32, u32), path: &str, dpi: Option<f32>, text: &[(String, String)], encoding: Encoding) -> Result<png::Encoder<'a, BufWriter<File>>, Box<dyn Error>> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut info = png::Info::with_size(width, height);
+    info.interlaced = encoding.interlaced;
+    if let Some(compression) = encoding.compression {
+        info.compression = compression;
+    }
+    let mut encoder = png::Encoder::with_info(file, info)?;
+    if let Some(dpi) = dpi {
+        let ppm = dpi_to_pixels_per_meter(dpi);
+        encoder.set_pixel_dims(Some(png::PixelDimensions {
+            xppu: ppm,
+            yppu: ppm,
+            unit: png::Unit::Meter,
+        }));
+    }
+    for (keyword, value) in text {
+        // tEXt only holds latin-1, and xmp always goes in an iTXt chunk
+        if keyword != XMP_KEYWORD && value.chars().all(|c| (c as u32) < 256) {
+            encoder.add_text_chunk(keyword.clone(), value.clone())?;
+        } else {
+            encoder.add_itxt_chunk(keyword.clone(), value.clone())?;
+        }
+    }
+    Ok(encoder)
+}
+
+fn save_jpeg(img: &RgbaImage, path: &str, dpi: Option<f32>, text: &[(String, String)], grayscale: bool, strict: bool) -> Result<(), Box<dyn Error>> {
+    // jpeg has no alpha channel
+    let img = DynamicImage::ImageRgba8(img.clone());
+    let mut bytes = vec![];
+    let mut encoder = JpegEncoder::new(&mut bytes);
+    if let Some(dpi) = dpi {
+        encoder.set_pixel_density(PixelDensity::dpi(dpi.round() as u16));
+    }
+    if grayscale {
+        encoder.encode_image(&img.into_luma8())?;
+    } else {
+        encoder.encode_image(&img.into_rgb8())?;
+    }
+
+    let mut segments = vec![];
+    for (keyword, value) in text {
+        let (marker, data) = match keyword.as_str() {
+            XMP_KEYWORD => (0xE1, [JPEG_XMP_HEADER, value.as_bytes()].concat()),
+            COMMENT_KEYWORD => (0xFE, value.as_bytes().to_vec()),
+            _ => (0xFE, format!("{}: {}", keyword, value).into_bytes()),
+        };
+        // the length field counts itself and can't go past 16 bits
+        let Ok(length) = u16::try_from(data.len() + 2) else {
+            warn(strict, format!("{} is too long for a jpeg segment", keyword), ", leaving it out")?;
+            continue;
+        };
+        segments.extend_from_slice(&[0xFF, marker]);
+        segments.extend_from_slice(&length.to_be_bytes());
+        segments.extend_from_slice(&data);
+    }
+    // goes right after the start of image marker and the JFIF header, where readers expect it
+    let mut position = 2;
+    if bytes.get(2..4) == Some(&[0xFF, 0xE0]) {
+        position += 2 + u16::from_be_bytes([bytes[4], bytes[5]]) as usize;
+    }
+    bytes.splice(position..position, segments);
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+fn save_tiff(img: &RgbaImage, path: &str, dpi: Option<f32>, bit_depth: u8, channels: Channels) -> Result<(), Box<dyn Error>> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = TiffEncoder::new(file)?;
+    // store with two decimal places of precision
+    let resolution = dpi.map(|dpi| Rational { n: (dpi * 100.).round() as u32, d: 100 });
+    let size = img.dimensions();
+    // tiff has no gray with alpha, those keep all four channels
+    let channels = if channels == Channels::GrayAlpha { Channels::Rgba } else { channels };
+    let samples = samples(img, channels);
+    match (channels, bit_depth) {
+        (Channels::Gray, 16) => write_tiff::<colortype::Gray16, _>(&mut encoder, size, &widen(&samples), resolution),
+        (Channels::Gray, _) => write_tiff::<colortype::Gray8, _>(&mut encoder, size, &samples, resolution),
+        (_, 16) => write_tiff::<colortype::RGBA16, _>(&mut encoder, size, &widen(&samples), resolution),
+        _ => write_tiff::<colortype::RGBA8, _>(&mut encoder, size, &samples, resolution),
+    }
+}
+
+fn write_tiff<C: colortype::ColorType, W: Write + Seek>(encoder: &mut TiffEncoder<W>, (width, height): (u32, u32), data: &[C::Inner], resolution: Option<Rational>) -> Result<(), Box<dyn Error>>
+where
+    [C::Inner]: TiffValue,
+{
+    let mut image = encoder.new_image::<C>(width, height)?;
+    if let Some(resolution) = resolution {
+        image.resolution(ResolutionUnit::Inch, resolution);
+    }
+    image.write_data(data)?;
+    Ok(())
+}
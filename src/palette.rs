@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use image::{ImageBuffer, Rgb, Rgba, RgbaImage};
+
+use crate::color::parse_hex;
+
+const SWATCH_SIZE: u32 = 32;
+
+// finds up to count representative colors with median cut, most common first
+// fully transparent pixels are left out
+pub fn dominant_colors(img: &RgbaImage, count: usize) -> Vec<[u8; 3]> {
+    let pixels: Vec<[u8; 3]> = img
+        .pixels()
+        .filter(|pixel| pixel[3] > 0)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    if pixels.is_empty() || count == 0 {
+        return vec![];
+    }
+
+    let mut boxes = vec![pixels];
+    while boxes.len() < count {
+        // split the box with the widest spread in any channel
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, colors)| {
+                let (channel, range) = widest_channel(colors);
+                (i, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range);
+        let Some((i, channel, range)) = widest else { break };
+        if range == 0 {
+            break; // every box is a single color
+        }
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|color| color[channel]);
+        // split at the median, moved to the nearest change in value so both halves are distinct
+        let median = colors[colors.len() / 2][channel];
+        let mut split = colors.partition_point(|color| color[channel] < median);
+        if split == 0 {
+            split = colors.partition_point(|color| color[channel] <= median);
+        }
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes.sort_by_key(|colors| std::cmp::Reverse(colors.len()));
+    boxes.iter().map(|colors| average(colors)).collect()
+}
+
+// an image stored as one palette index per pixel
+pub struct Indexed {
+    pub palette: Vec<[u8; 3]>,
+    // the entry used for fully transparent pixels, if there are any
+    pub transparent: Option<u8>,
+    pub indices: Vec<u8>,
+}
+
+// reduces the image to at most max_colors (2 to 256) colors from dominant_colors, each pixel taking
+// the closest one, an image with fewer colors than that keeps them exactly,
+// fully transparent pixels share one transparent entry (counted in max_colors), partly
+// transparent ones are matched on their rgb alone
+pub fn quantize(img: &RgbaImage, max_colors: usize) -> Indexed {
+    let has_transparent = img.pixels().any(|pixel| pixel[3] == 0);
+    let mut palette = dominant_colors(img, max_colors - has_transparent as usize);
+    let transparent = has_transparent.then(|| {
+        palette.push([0, 0, 0]);
+        (palette.len() - 1) as u8
+    });
+    let opaque = &palette[..palette.len() - has_transparent as usize];
+    // images tend to repeat the same colors a lot, each one is matched once
+    let mut matched: HashMap<[u8; 3], u8> = HashMap::new();
+    let indices = img
+        .pixels()
+        .map(|pixel| match transparent {
+            Some(index) if pixel[3] == 0 => index,
+            _ => *matched.entry([pixel[0], pixel[1], pixel[2]]).or_insert_with_key(|&color| closest(opaque, color)),
+        })
+        .collect();
+    Indexed { palette, transparent, indices }
+}
+
+fn closest(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    let distance = |entry: &[u8; 3]| (0..3).map(|channel| (entry[channel] as i32 - color[channel] as i32).pow(2)).sum::<i32>();
+    (0..palette.len()).min_by_key(|&i| distance(&palette[i])).unwrap_or(0) as u8
+}
+
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = colors.iter().map(|color| color[channel]).min().unwrap();
+            let max = colors.iter().map(|color| color[channel]).max().unwrap();
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+fn average(colors: &[[u8; 3]]) -> [u8; 3] {
+    let mut sums = [0u64; 3];
+    for color in colors {
+        for channel in 0..3 {
+            sums[channel] += color[channel] as u64;
+        }
+    }
+    let len = colors.len() as u64;
+    sums.map(|sum| ((sum + len / 2) / len) as u8)
+}
+
+// reads one hex color per line, anything after the color (like a name) is ignored
+pub fn parse_palette(contents: &str) -> Result<Vec<Rgb<u8>>, String> {
+    let mut colors = vec![];
+    for (number, line) in contents.lines().enumerate() {
+        let Some(code) = line.split_whitespace().next() else { continue };
+        let color = parse_hex(code).ok_or(format!("line {}: {} is not a hex color", number + 1, code))?;
+        colors.push(color);
+    }
+    Ok(colors)
+}
+
+pub fn to_hex(color: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+// a row of square swatches, one per color
+pub fn swatches(colors: &[[u8; 3]]) -> RgbaImage {
+    ImageBuffer::from_fn(SWATCH_SIZE * colors.len() as u32, SWATCH_SIZE, |x, _| {
+        let [r, g, b] = colors[(x / SWATCH_SIZE) as usize];
+        Rgba([r, g, b, 255])
+    })
+}
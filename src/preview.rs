@@ -0,0 +1,51 @@
+use std::{env, fmt::Write};
+
+use image::{imageops::{self, FilterType}, Rgba, RgbaImage};
+
+const DEFAULT_COLUMNS: u32 = 80;
+
+// draws the image with 24-bit ansi colors, each character cell holds two pixels
+// stacked with the upper half block, so the preview keeps the image's aspect ratio
+pub fn render(img: &RgbaImage, columns: u32) -> String {
+    let width = columns.min(img.width()).max(1);
+    let height = ((img.height() as f32 * width as f32 / img.width() as f32).round() as u32).max(1);
+    let small = imageops::resize(img, width, height, FilterType::Triangle);
+
+    let mut out = String::new();
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let [tr, tg, tb] = over_black(*small.get_pixel(x, y));
+            // an odd final row gets a black lower half
+            let [br, bg, bb] = if y + 1 < height { over_black(*small.get_pixel(x, y + 1)) } else { [0, 0, 0] };
+            write!(out, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}", tr, tg, tb, br, bg, bb).unwrap();
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+fn over_black(pixel: Rgba<u8>) -> [u8; 3] {
+    let alpha = pixel[3] as u16;
+    [0, 1, 2].map(|i| (pixel[i] as u16 * alpha / 255) as u8)
+}
+
+// width of the terminal, falling back to $COLUMNS and then 80
+pub fn terminal_columns() -> u32 {
+    ioctl_columns()
+        .or_else(|| env::var("COLUMNS").ok()?.parse().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(DEFAULT_COLUMNS)
+}
+
+#[cfg(unix)]
+fn ioctl_columns() -> Option<u32> {
+    let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+    // safe, TIOCGWINSZ only writes into the winsize struct we pass
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result == 0 && size.ws_col > 0 { Some(size.ws_col as u32) } else { None }
+}
+
+#[cfg(not(unix))]
+fn ioctl_columns() -> Option<u32> {
+    None
+}
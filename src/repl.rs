@@ -0,0 +1,60 @@
+use std::{io::{self, BufRead, Write}, time::Instant};
+
+use color_reflect::output;
+use color_reflect::transform::{self, Axis, Operation};
+use image::RgbaImage;
+
+use crate::{build_transform, cli::CommonArgs, describe_error, encoding, keeps_grayscale, log_checksum, output_colors, save_over_input, write_result, LoadedImage};
+
+const HELP: &str = "\
+Commands:
+  <angle>       reflect the image along angle and save it to the output path
+  save <path>   save the last result to path
+  help          show this list
+  quit          stop, reaching the end of the input does too";
+
+// reads angles from stdin, reusing the already decoded image for each one
+pub fn run(loaded: &LoadedImage, common: &CommonArgs) {
+    println!("{}", HELP);
+    let mut last_result: Option<RgbaImage> = None;
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let Some(Ok(line)) = lines.next() else { break };
+        let line = line.trim();
+
+        match line.split_once(' ').map(|(command, rest)| (command, rest.trim())) {
+            _ if line.is_empty() => {}
+            _ if line == "quit" || line == "exit" => break,
+            _ if line == "help" => println!("{}", HELP),
+            Some(("save", path)) => match &last_result {
+                Some(new_img) => match save_over_input(&loaded.path, path, |path| {
+                    output::save(new_img, path, None, loaded.dpi, &loaded.text, encoding(common), output_colors(common.indexed, keeps_grayscale(loaded, new_img)))
+                }) {
+                    Ok(()) => match log_checksum(path, common) {
+                        Ok(()) => println!("Saved {}", path),
+                        Err(message) => println!("{}", message),
+                    },
+                    Err(e) => println!("Failed to save image: {}", describe_error(&*e, common.verbose_errors)),
+                },
+                None => println!("Nothing to save yet, enter an angle first"),
+            },
+            _ => match line.parse::<f32>() {
+                Ok(angle) => {
+                    let timer = Instant::now();
+                    let operation = Operation::Reflect(Axis::Fixed(angle));
+                    let transform = build_transform(operation, loaded, common);
+                    let new_img = transform::reflect_image(&loaded.img, transform);
+                    println!("Done in {}ms", timer.elapsed().as_millis());
+                    if let Err(message) = write_result(&new_img, None, &common.output, None, loaded, common) {
+                        println!("{}", message);
+                    }
+                    last_result = Some(new_img);
+                }
+                Err(_) => println!("Unknown command {}, type help for the list", line),
+            },
+        }
+    }
+}
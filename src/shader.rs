@@ -0,0 +1,136 @@
+// the reflection written out as gpu shader code, following the same steps as color::reflect_color
+
+#[derive(Clone, Copy)]
+pub enum ShaderLanguage {
+    Glsl,
+    Wgsl,
+}
+
+impl ShaderLanguage {
+    pub fn parse(name: &str) -> Option<ShaderLanguage> {
+        match name.to_ascii_lowercase().as_str() {
+            "glsl" => Some(ShaderLanguage::Glsl),
+            "wgsl" => Some(ShaderLanguage::Wgsl),
+            _ => None,
+        }
+    }
+}
+
+// where the gpu result can differ from the cpu one, printed above the code
+const HEADER: &str = "\
+// hue reflection generated by color_reflect
+// hue_reflect takes and returns rgb from 0.0 to 1.0, in the same space the cpu version reads (srgb by default)
+// precision: color_reflect works on 8-bit channels and truncates its results, so the output here can be
+// up to 1/255 above the cpu result per channel, and gpus running at reduced (mediump) precision can drift
+// further near the edges of the hue sectors, use highp where it matters
+";
+
+const GLSL: &str = "
+vec3 rgb_to_hsv(vec3 c) {
+    float big_m = max(max(c.r, c.g), c.b);
+    float little_m = min(min(c.r, c.g), c.b);
+    float chroma = big_m - little_m;
+    float s = big_m == 0.0 ? 0.0 : chroma / big_m;
+    float h = 0.0;
+    if (chroma > 0.0) {
+        vec3 d = (big_m - c) / chroma;
+        if (big_m == c.r) {
+            h = d.b - d.g;
+        } else if (big_m == c.g) {
+            h = 2.0 + d.r - d.b;
+        } else {
+            h = 4.0 + d.g - d.r;
+        }
+    }
+    return vec3(h * 60.0, s, big_m);
+}
+
+vec3 hsv_to_rgb(vec3 hsv) {
+    float c = hsv.y * hsv.z;
+    float big_m = hsv.z;
+    float little_m = big_m - c;
+    float h = (hsv.x >= 300.0 ? hsv.x - 360.0 : hsv.x) / 60.0;
+    if (h < 0.0) return vec3(big_m, little_m, little_m - h * c);
+    if (h < 1.0) return vec3(big_m, little_m + h * c, little_m);
+    if (h < 2.0) return vec3(little_m - (h - 2.0) * c, big_m, little_m);
+    if (h < 3.0) return vec3(little_m, big_m, little_m + (h - 2.0) * c);
+    if (h < 4.0) return vec3(little_m, little_m - (h - 4.0) * c, big_m);
+    return vec3(little_m + (h - 4.0) * c, little_m, big_m);
+}
+
+vec3 hue_reflect(vec3 rgb) {
+    vec3 hsv = rgb_to_hsv(rgb);
+    // glsl mod floors like rem_euclid, so the hue stays in 0..360
+    hsv.x = mod(360.0 - hsv.x + 2.0 * REFLECT_ANGLE, 360.0);
+    return clamp(hsv_to_rgb(hsv), 0.0, 1.0);
+}
+";
+
+const WGSL: &str = "
+fn rgb_to_hsv(c: vec3<f32>) -> vec3<f32> {
+    let big_m = max(max(c.r, c.g), c.b);
+    let little_m = min(min(c.r, c.g), c.b);
+    let chroma = big_m - little_m;
+    let s = select(chroma / big_m, 0.0, big_m == 0.0);
+    var h = 0.0;
+    if (chroma > 0.0) {
+        let d = (vec3<f32>(big_m) - c) / chroma;
+        if (big_m == c.r) {
+            h = d.b - d.g;
+        } else if (big_m == c.g) {
+            h = 2.0 + d.r - d.b;
+        } else {
+            h = 4.0 + d.g - d.r;
+        }
+    }
+    return vec3<f32>(h * 60.0, s, big_m);
+}
+
+fn hsv_to_rgb(hsv: vec3<f32>) -> vec3<f32> {
+    let c = hsv.y * hsv.z;
+    let big_m = hsv.z;
+    let little_m = big_m - c;
+    let h = select(hsv.x, hsv.x - 360.0, hsv.x >= 300.0) / 60.0;
+    if (h < 0.0) { return vec3<f32>(big_m, little_m, little_m - h * c); }
+    if (h < 1.0) { return vec3<f32>(big_m, little_m + h * c, little_m); }
+    if (h < 2.0) { return vec3<f32>(little_m - (h - 2.0) * c, big_m, little_m); }
+    if (h < 3.0) { return vec3<f32>(little_m, big_m, little_m + (h - 2.0) * c); }
+    if (h < 4.0) { return vec3<f32>(little_m, little_m - (h - 4.0) * c, big_m); }
+    return vec3<f32>(little_m + (h - 4.0) * c, little_m, big_m);
+}
+
+fn hue_reflect(rgb: vec3<f32>) -> vec3<f32> {
+    var hsv = rgb_to_hsv(rgb);
+    // wgsl % truncates like rust %, so floor by hand to keep the hue in 0..360
+    let hue = 360.0 - hsv.x + 2.0 * REFLECT_ANGLE;
+    hsv.x = hue - 360.0 * floor(hue / 360.0);
+    return clamp(hsv_to_rgb(hsv), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+";
+
+// shader source with a hue_reflect function for reflect_angle
+pub fn emit(language: ShaderLanguage, reflect_angle: f32) -> String {
+    // {:?} always writes a decimal point or exponent, which both languages need for a float constant
+    let (constant, body) = match language {
+        ShaderLanguage::Glsl => (format!("const float REFLECT_ANGLE = {:?};\n", reflect_angle), GLSL),
+        ShaderLanguage::Wgsl => (format!("const REFLECT_ANGLE: f32 = {:?};\n", reflect_angle), WGSL),
+    };
+    format!("{}\n{}{}", HEADER, constant, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_angle_constant_is_in_the_emitted_code() {
+        let glsl = emit(ShaderLanguage::Glsl, 90.);
+        assert!(glsl.contains("const float REFLECT_ANGLE = 90.0;\n"), "{}", glsl);
+        let wgsl = emit(ShaderLanguage::Wgsl, 37.5);
+        assert!(wgsl.contains("const REFLECT_ANGLE: f32 = 37.5;\n"), "{}", wgsl);
+        // declared once
+        for code in [&glsl, &wgsl] {
+            assert_eq!(code.matches("REFLECT_ANGLE =").count() + code.matches("REFLECT_ANGLE: f32 =").count(), 1);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use image::{imageops, GenericImage, ImageBuffer, Rgb, Rgba, RgbaImage};
+
+// 3x5 pixel glyphs for writing angles, each row is 3 bits with the leftmost pixel highest
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+fn glyph(character: char) -> Option<[u8; 5]> {
+    Some(match character {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => return None,
+    })
+}
+
+// 0, step, 2 * step, ... up to and including 180
+pub fn angles(step: f32) -> Vec<f32> {
+    // counting steps instead of adding up step keeps 180 from being missed to rounding
+    let count = (180. / step + 1e-4).floor() as u32;
+    (0..=count).map(|i| i as f32 * step).collect()
+}
+
+// lays the cells out left to right, top to bottom, in rows of columns cells
+// every cell is expected to be the size of the first, unused spots stay transparent
+pub fn grid(cells: &[RgbaImage], columns: u32) -> RgbaImage {
+    let Some(first) = cells.first() else { return ImageBuffer::new(0, 0) };
+    let (cell_width, cell_height) = first.dimensions();
+    let columns = columns.min(cells.len() as u32);
+    let rows = (cells.len() as u32).div_ceil(columns);
+
+    let mut sheet = ImageBuffer::new(cell_width * columns, cell_height * rows);
+    for (i, cell) in cells.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        sheet.copy_from(cell, column * cell_width, row * cell_height).expect("Contact sheet cells must all be the same size");
+    }
+    sheet
+}
+
+// centers img on a transparent width by height cell, cropping it if it is bigger
+pub fn fit_cell(img: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let mut cell = ImageBuffer::new(width, height);
+    let left = width.saturating_sub(img.width()) / 2;
+    let top = height.saturating_sub(img.height()) / 2;
+    for (x, y, pixel) in img.enumerate_pixels() {
+        if left + x < width && top + y < height {
+            cell.put_pixel(left + x, top + y, *pixel);
+        }
+    }
+    cell
+}
+
+// img mirrored left to right and/or top to bottom, then turned clockwise by degrees (0, 90, 180 or 270)
+pub fn orient(img: &RgbaImage, flip_horizontal: bool, flip_vertical: bool, degrees: u32) -> RgbaImage {
+    let mut oriented = img.clone();
+    if flip_horizontal {
+        imageops::flip_horizontal_in_place(&mut oriented);
+    }
+    if flip_vertical {
+        imageops::flip_vertical_in_place(&mut oriented);
+    }
+    match degrees {
+        90 => imageops::rotate90(&oriented),
+        180 => {
+            imageops::rotate180_in_place(&mut oriented);
+            oriented
+        }
+        270 => imageops::rotate270(&oriented),
+        _ => oriented,
+    }
+}
+
+// img repeated columns times across and rows times down, touching edge to edge
+pub fn tile(img: &RgbaImage, rows: u32, columns: u32) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let mut tiled = ImageBuffer::new(width * columns, height * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            tiled.copy_from(img, column * width, row * height).unwrap();
+        }
+    }
+    tiled
+}
+
+// surrounds img with width pixels of color on every side, or with inset paints over its outer width pixels instead
+pub fn border(img: &RgbaImage, width: u32, color: Rgba<u8>, inset: bool) -> RgbaImage {
+    if inset {
+        let (w, h) = img.dimensions();
+        let mut framed = img.clone();
+        for (x, y, pixel) in framed.enumerate_pixels_mut() {
+            if x < width || y < width || x + width >= w || y + width >= h {
+                *pixel = color;
+            }
+        }
+        return framed;
+    }
+    let mut framed = ImageBuffer::from_pixel(img.width() + 2 * width, img.height() + 2 * width, color);
+    framed.copy_from(img, width, width).unwrap();
+    framed
+}
+
+// img drawn over a solid color, every pixel comes out opaque
+pub fn flatten(img: &RgbaImage, background: Rgb<u8>) -> RgbaImage {
+    let mut flat = img.clone();
+    for pixel in flat.pixels_mut() {
+        let alpha = pixel[3] as f32 / 255.;
+        for channel in 0..3 {
+            pixel[channel] = (pixel[channel] as f32 * alpha + background[channel] as f32 * (1. - alpha)).round() as u8;
+        }
+        pixel[3] = 255;
+    }
+    flat
+}
+
+// writes text in white on a black box in the top left corner, characters without a glyph are left out
+pub fn label(cell: &mut RgbaImage, text: &str) {
+    // grow the text with the cell so it stays readable on big images
+    let scale = (cell.width() / 64).max(1);
+    let glyphs: Vec<[u8; 5]> = text.chars().filter_map(glyph).collect();
+    if glyphs.is_empty() {
+        return;
+    }
+    let text_width = (glyphs.len() as u32 * (GLYPH_WIDTH + 1) - 1) * scale;
+    // one pixel (times scale) of padding around the text
+    let box_width = (text_width + 2 * scale).min(cell.width());
+    let box_height = ((GLYPH_HEIGHT + 2) * scale).min(cell.height());
+    for y in 0..box_height {
+        for x in 0..box_width {
+            cell.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+        }
+    }
+
+    for (i, rows) in glyphs.iter().enumerate() {
+        let left = scale + i as u32 * (GLYPH_WIDTH + 1) * scale;
+        for y in 0..GLYPH_HEIGHT * scale {
+            for x in 0..GLYPH_WIDTH * scale {
+                let lit = rows[(y / scale) as usize] >> (GLYPH_WIDTH - 1 - x / scale) & 1 == 1;
+                let (px, py) = (left + x, scale + y);
+                if lit && px < box_width && py < box_height {
+                    cell.put_pixel(px, py, Rgba([255, 255, 255, 255]));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_transparent_pixel_over_black_and_white() {
+        // 128 of 255 is a little over half
+        let img = RgbaImage::from_pixel(1, 1, Rgba([200, 100, 50, 128]));
+        assert_eq!(flatten(&img, Rgb([0, 0, 0])).get_pixel(0, 0), &Rgba([100, 50, 25, 255]));
+        assert_eq!(flatten(&img, Rgb([255, 255, 255])).get_pixel(0, 0), &Rgba([227, 177, 152, 255]));
+        // opaque and fully transparent pixels are the color or the background
+        let ends = RgbaImage::from_fn(2, 1, |x, _| Rgba([10, 20, 30, if x == 0 { 255 } else { 0 }]));
+        let flat = flatten(&ends, Rgb([90, 90, 90]));
+        assert_eq!(flat.get_pixel(0, 0), &Rgba([10, 20, 30, 255]));
+        assert_eq!(flat.get_pixel(1, 0), &Rgba([90, 90, 90, 255]));
+    }
+}
@@ -0,0 +1,103 @@
+use image::Rgb;
+
+// what the input's rgb values mean, hsv work is always done on srgb values
+// results are converted back so the output is in the same space as the input
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    // srgb transfer curve with the wider display p3 primaries
+    DisplayP3,
+    // srgb primaries without the transfer curve
+    Linear,
+}
+
+// linear display p3 to linear srgb, both with a d65 white point
+const P3_TO_SRGB: [[f32; 3]; 3] = [
+    [1.2249, -0.2247, 0.],
+    [-0.0420, 1.0419, 0.],
+    [-0.0197, -0.0786, 1.0979],
+];
+
+const SRGB_TO_P3: [[f32; 3]; 3] = [
+    [0.8225, 0.1774, 0.],
+    [0.0332, 0.9669, 0.],
+    [0.0171, 0.0724, 0.9108],
+];
+
+// which model hues are measured and moved in
+#[derive(Clone, Copy, PartialEq)]
+pub enum HueSpace {
+    Hsv,
+    // the hue of oklch, evener to the eye than hsv, blues especially
+    Oklab,
+}
+
+impl HueSpace {
+    pub fn parse(name: &str) -> Option<HueSpace> {
+        match name {
+            "hsv" => Some(HueSpace::Hsv),
+            "oklab" => Some(HueSpace::Oklab),
+            _ => None,
+        }
+    }
+}
+
+impl ColorSpace {
+    pub fn parse(name: &str) -> Option<ColorSpace> {
+        match name {
+            "srgb" => Some(ColorSpace::Srgb),
+            "p3" => Some(ColorSpace::DisplayP3),
+            "linear" => Some(ColorSpace::Linear),
+            _ => None,
+        }
+    }
+
+    // input pixel to srgb, colors outside the srgb gamut are clipped
+    pub fn to_srgb(self, pixel: Rgb<u8>) -> Rgb<u8> {
+        match self {
+            ColorSpace::Srgb => pixel,
+            ColorSpace::Linear => map(pixel, |c| c, encode),
+            ColorSpace::DisplayP3 => {
+                let linear = multiply(&P3_TO_SRGB, to_floats(pixel).map(decode));
+                to_bytes(linear.map(encode))
+            }
+        }
+    }
+
+    // srgb pixel back to the input's space
+    pub fn from_srgb(self, pixel: Rgb<u8>) -> Rgb<u8> {
+        match self {
+            ColorSpace::Srgb => pixel,
+            ColorSpace::Linear => map(pixel, decode, |c| c),
+            ColorSpace::DisplayP3 => {
+                let linear = multiply(&SRGB_TO_P3, to_floats(pixel).map(decode));
+                to_bytes(linear.map(encode))
+            }
+        }
+    }
+}
+
+// srgb transfer curve, encoded 0-1 to linear 0-1
+pub(crate) fn decode(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+pub(crate) fn encode(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1. / 2.4) - 0.055 }
+}
+
+fn map(pixel: Rgb<u8>, first: fn(f32) -> f32, second: fn(f32) -> f32) -> Rgb<u8> {
+    to_bytes(to_floats(pixel).map(|c| second(first(c))))
+}
+
+pub(crate) fn multiply(matrix: &[[f32; 3]; 3], c: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * c[0] + row[1] * c[1] + row[2] * c[2])
+}
+
+fn to_floats(pixel: Rgb<u8>) -> [f32; 3] {
+    pixel.0.map(|c| c as f32 / 255.)
+}
+
+fn to_bytes(c: [f32; 3]) -> Rgb<u8> {
+    Rgb(c.map(|c| (c.clamp(0., 1.) * 255.).round() as u8))
+}
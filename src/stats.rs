@@ -0,0 +1,202 @@
+use std::thread;
+
+use image::{Rgb, RgbaImage};
+
+use crate::color::{is_gray, rgb_to_hsv};
+
+pub const CHANNEL_NAMES: [&str; 3] = ["red", "green", "blue"];
+
+#[derive(Clone, Copy)]
+pub struct ChannelStats {
+    pub min: f32,
+    pub max: f32,
+    sum: f64,
+    count: u64,
+}
+
+impl ChannelStats {
+    fn new() -> ChannelStats {
+        ChannelStats { min: f32::INFINITY, max: f32::NEG_INFINITY, sum: 0., count: 0 }
+    }
+
+    fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value as f64;
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: &ChannelStats) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    // None when no pixel had a value, like hue in a grayscale image
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 { None } else { Some(self.sum / self.count as f64) }
+    }
+}
+
+// hues as a direction around the wheel, the sum of one arrow per pixel as long as its weight
+// (hsv saturation or oklch chroma) so the faint hues of near grays count for little, and a
+// mean of 350 and 10 is 0 rather than 180, there is no min or max on a circle
+#[derive(Clone, Copy, Default)]
+pub struct HueStats {
+    x: f64,
+    y: f64,
+    weight: f64,
+}
+
+impl HueStats {
+    pub fn add(&mut self, hue: f32, weight: f32) {
+        let radians = (hue as f64).to_radians();
+        self.x += weight as f64 * radians.cos();
+        self.y += weight as f64 * radians.sin();
+        self.weight += weight as f64;
+    }
+
+    fn merge(&mut self, other: &HueStats) {
+        self.x += other.x;
+        self.y += other.y;
+        self.weight += other.weight;
+    }
+
+    // from 0 to 360, None when there are no hues or they cancel out
+    pub fn mean(&self) -> Option<f64> {
+        // less than a millionth of the weight left over is rounding, not a direction
+        if self.weight == 0. || self.x.hypot(self.y) < self.weight * 1e-6 {
+            return None;
+        }
+        Some(self.y.atan2(self.x).to_degrees().rem_euclid(360.))
+    }
+}
+
+pub struct ImageStats {
+    // red, green and blue from 0 to 255
+    pub channels: [ChannelStats; 3],
+    // skips gray pixels, which have none
+    pub hue: HueStats,
+}
+
+impl ImageStats {
+    fn new() -> ImageStats {
+        ImageStats { channels: [ChannelStats::new(); 3], hue: HueStats::default() }
+    }
+
+    fn merge(&mut self, other: &ImageStats) {
+        for (total, part) in self.channels.iter_mut().zip(&other.channels) {
+            total.merge(part);
+        }
+        self.hue.merge(&other.hue);
+    }
+}
+
+// grays within tolerance (see is_gray) have no hue and are left out of the mean hue
+pub fn image_stats(img: &RgbaImage, tolerance: f32) -> ImageStats {
+    let core_count = num_cpus::get();
+    let row_bytes = img.width() as usize * 4;
+    let rows_per_chunk = (img.height() as usize).div_ceil(core_count).max(1);
+
+    let mut stats = ImageStats::new();
+    if row_bytes == 0 {
+        return stats;
+    }
+    // each core sums its own band of rows, merged at the end
+    let partials: Vec<ImageStats> = thread::scope(|scope| {
+        let handles: Vec<_> = img
+            .as_raw()
+            .chunks(row_bytes * rows_per_chunk)
+            .map(|band| scope.spawn(move || band_stats(band, tolerance)))
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+    for partial in &partials {
+        stats.merge(partial);
+    }
+    stats
+}
+
+fn band_stats(band: &[u8], tolerance: f32) -> ImageStats {
+    let mut stats = ImageStats::new();
+    for pixel in band.chunks_exact(4) {
+        for (channel, &value) in stats.channels.iter_mut().zip(pixel) {
+            channel.add(value as f32);
+        }
+        let color = Rgb([pixel[0], pixel[1], pixel[2]]);
+        if !is_gray(color, tolerance) {
+            let [hue, saturation, _] = rgb_to_hsv(&color).0;
+            stats.hue.add(hue, saturation);
+        }
+    }
+    stats
+}
+
+// before and after side by side, one row per channel and the mean hue last
+pub fn table(before: &ImageStats, after: &ImageStats) -> String {
+    let mut out = format!("{:<8}{:>26}  {:>26}\n", "", "before", "after");
+    out += &format!("{:<8}{:>10}{:>8}{:>8}  {:>10}{:>8}{:>8}\n", "", "mean", "min", "max", "mean", "min", "max");
+    for (i, name) in CHANNEL_NAMES.iter().enumerate() {
+        out += &format!("{:<8}{}  {}\n", name, row(&before.channels[i]), row(&after.channels[i]));
+    }
+    out += &format!("{:<8}{}  {}\n", "hue", hue_row(&before.hue), hue_row(&after.hue));
+    out
+}
+
+fn row(stats: &ChannelStats) -> String {
+    match stats.mean() {
+        Some(mean) => format!("{:>10.2}{:>8.1}{:>8.1}", mean, stats.min, stats.max),
+        None => format!("{:>10}{:>8}{:>8}", "-", "-", "-"),
+    }
+}
+
+fn hue_row(stats: &HueStats) -> String {
+    let mean = stats.mean().map_or(String::from("-"), |mean| format!("{:.2}", mean));
+    format!("{:>10}{:>8}{:>8}", mean, "-", "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::DEFAULT_TOLERANCE;
+
+    fn image_of(colors: &[[u8; 4]]) -> RgbaImage {
+        RgbaImage::from_fn(colors.len() as u32, 1, |x, _| image::Rgba(colors[x as usize]))
+    }
+
+    #[test]
+    fn known_image_stats() {
+        let stats = image_stats(&image_of(&[[255, 0, 0, 255], [0, 60, 0, 255], [100, 100, 100, 255], [20, 20, 20, 255]]), DEFAULT_TOLERANCE);
+        let rgb: Vec<_> = stats.channels.iter().map(|channel| (channel.mean().unwrap(), channel.min, channel.max)).collect();
+        assert_eq!(rgb, [(93.75, 0., 255.), (45., 0., 100.), (30., 0., 100.)]);
+        // the grays have no hue, red and green are both fully saturated
+        let mean = stats.hue.mean().unwrap();
+        assert!((mean - 60.).abs() < 1e-3, "{}", mean);
+    }
+
+    #[test]
+    fn hues_either_side_of_red_average_to_red() {
+        // 350 and 10 degree reds, the plain average of the two would be 180
+        let reds = [[255, 0, 43, 255], [255, 43, 0, 255]];
+        let mean = image_stats(&image_of(&reds), DEFAULT_TOLERANCE).hue.mean().unwrap();
+        assert!(mean.min(360. - mean) < 0.5, "{}", mean);
+        // a faint hue counts for its saturation only
+        let mean = image_stats(&image_of(&[[255, 0, 0, 255], [100, 100, 104, 255]]), DEFAULT_TOLERANCE).hue.mean().unwrap();
+        assert!(mean.min(360. - mean) < 10., "{}", mean);
+        assert_eq!(image_stats(&image_of(&[[90, 90, 90, 255]]), DEFAULT_TOLERANCE).hue.mean(), None);
+        assert_eq!(image_stats(&image_of(&[[255, 0, 0, 255], [0, 255, 255, 255]]), DEFAULT_TOLERANCE).hue.mean(), None);
+    }
+
+    #[test]
+    fn bands_merge_into_the_whole_image() {
+        let img = RgbaImage::from_fn(13, 57, |x, y| image::Rgba([(x * 19) as u8, (y * 4) as u8, ((x + y) * 7) as u8, 255]));
+        let whole = band_stats(img.as_raw(), DEFAULT_TOLERANCE);
+        let merged = image_stats(&img, DEFAULT_TOLERANCE);
+        assert!((whole.hue.mean().unwrap() - merged.hue.mean().unwrap()).abs() < 1e-9);
+        for (whole, merged) in whole.channels.iter().zip(&merged.channels) {
+            assert_eq!((whole.min, whole.max, whole.count), (merged.min, merged.max, merged.count));
+            assert!((whole.mean().unwrap() - merged.mean().unwrap()).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::{path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+// a file name pattern like {stem}_{angle}.{ext}, filled in once per output
+pub struct Template {
+    parts: Vec<Part>,
+    // folder the expanded names are inside of, from --output-dir
+    directory: Option<PathBuf>,
+}
+
+enum Part {
+    Text(String),
+    Stem,
+    Ext,
+    Angle,
+    Mode,
+    Index,
+    Date,
+}
+
+// what the placeholders stand for when one output is named
+pub struct Values<'a> {
+    // the input's file name without its extension, and the extension
+    pub stem: &'a str,
+    pub ext: &'a str,
+    // reflection axis or rotation, None for outputs that don't have a single one
+    pub angle: Option<f32>,
+    pub mode: &'a str,
+    // counts every output of the run from 0
+    pub index: usize,
+    pub date: &'a str,
+}
+
+const PLACEHOLDERS: &str = "{stem}, {ext}, {angle}, {mode}, {index} and {date}";
+
+impl Template {
+    // {{ and }} stand for literal braces
+    pub fn parse(pattern: &str) -> Result<Template, String> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or(format!("Unclosed {{ in output template {}", pattern))?;
+                    let part = match &rest[..end] {
+                        "stem" => Part::Stem,
+                        "ext" => Part::Ext,
+                        "angle" => Part::Angle,
+                        "mode" => Part::Mode,
+                        "index" => Part::Index,
+                        "date" => Part::Date,
+                        name => return Err(format!("Unknown placeholder {{{}}} in output template, use {}", name, PLACEHOLDERS)),
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(part);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("Unmatched }} in output template {}", pattern)),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts, directory: None })
+    }
+
+    // {stem}.<extension>, or {stem}_{angle}.<extension> when an input has several outputs
+    pub fn after_input(with_angle: bool, extension: &str) -> Template {
+        let mut parts = vec![Part::Stem];
+        if with_angle {
+            parts.extend([Part::Text(String::from("_")), Part::Angle]);
+        }
+        parts.push(Part::Text(format!(".{}", extension)));
+        Template { parts, directory: None }
+    }
+
+    pub fn in_directory(self, directory: &Path) -> Template {
+        Template { directory: Some(directory.to_path_buf()), ..self }
+    }
+
+    pub fn uses_angle(&self) -> bool {
+        self.parts.iter().any(|part| matches!(part, Part::Angle))
+    }
+
+    pub fn expand(&self, values: &Values) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Stem => name.push_str(values.stem),
+                Part::Ext => name.push_str(values.ext),
+                Part::Angle => name.push_str(&values.angle.map(|angle| angle.to_string()).unwrap_or_default()),
+                Part::Mode => name.push_str(values.mode),
+                Part::Index => name.push_str(&values.index.to_string()),
+                Part::Date => name.push_str(values.date),
+            }
+        }
+        match &self.directory {
+            Some(directory) => directory.join(name).to_string_lossy().into_owned(),
+            None => name,
+        }
+    }
+}
+
+// today as yyyy-mm-dd, in utc
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs() / 86400).unwrap_or(0) as i64;
+    let (year, month, day) = calendar_date(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// days since 1970-01-01 to year, month and day, from http://howardhinnant.github.io/date_algorithms.html
+pub fn calendar_date(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
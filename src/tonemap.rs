@@ -0,0 +1,73 @@
+// bringing float (high dynamic range) inputs down to 8 bits with a curve that rolls highlights off
+// instead of cutting every value over 1 to full, values are kept as they are otherwise, so the
+// result means the same as the clamped one apart from the highlights
+
+use image::{DynamicImage, Rgba, Rgba32FImage, RgbaImage};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Tonemap {
+    // extended reinhard, c * (1 + c / white²) / (1 + c), white the brightest value in the image,
+    // which comes out at exactly full so nothing clips
+    Reinhard,
+    // Krzysztof Narkowicz's fit of the aces filmic curve, a little contrast added in the midtones,
+    // it reaches full at about 7.2 and cuts off above that
+    Aces,
+}
+
+impl Tonemap {
+    pub fn parse(name: &str) -> Option<Tonemap> {
+        match name {
+            "reinhard" => Some(Tonemap::Reinhard),
+            "aces" => Some(Tonemap::Aces),
+            _ => None,
+        }
+    }
+}
+
+// None when img isn't a float image, those have nothing above 1 to roll off
+pub fn apply(img: &DynamicImage, tonemap: Tonemap) -> Option<DynamicImage> {
+    let floats: Rgba32FImage = match img {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => img.to_rgba32f(),
+        _ => return None,
+    };
+    // white below 1 would brighten an image that has nothing to roll off
+    let white = floats.pixels().flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).filter(|c| c.is_finite()).fold(1f32, f32::max);
+    let curve = |c: f32| match tonemap {
+        Tonemap::Reinhard => c * (1. + c / (white * white)) / (1. + c),
+        Tonemap::Aces => c * (2.51 * c + 0.03) / (c * (2.43 * c + 0.59) + 0.14),
+    };
+    let byte = |c: f32| (c.clamp(0., 1.) * 255.).round() as u8;
+    let mapped = RgbaImage::from_fn(floats.width(), floats.height(), |x, y| {
+        let [r, g, b, a] = floats.get_pixel(x, y).0;
+        // negative values can't be shown either way, infinities are as bright as it gets
+        let channel = |c: f32| byte(if c.is_nan() { 0. } else { curve(c.clamp(0., white)) });
+        Rgba([channel(r), channel(g), channel(b), byte(a)])
+    });
+    Some(DynamicImage::ImageRgba8(mapped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reinhard_rolls_off_a_bright_gradient_without_clipping() {
+        // 0 to 16 times full brightness from left to right
+        let floats = Rgba32FImage::from_fn(256, 1, |x, _| {
+            let c = x as f32 / 255. * 16.;
+            Rgba([c, c, c, 1.])
+        });
+        let mapped = apply(&DynamicImage::ImageRgba32F(floats), Tonemap::Reinhard).unwrap().to_rgba8();
+        let values: Vec<u8> = mapped.pixels().map(|pixel| pixel[0]).collect();
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]), "the gradient isn't increasing");
+        // clamping would leave everything past the 16th pixel at full, the curve only the last few
+        // that round up to it
+        let full = values.iter().filter(|&&value| value == 255).count();
+        assert!(full <= 8, "{} pixels clipped to full", full);
+        assert_eq!(values[255], 255, "the brightest value should reach full");
+        let mut top: Vec<u8> = values[128..].to_vec();
+        top.dedup();
+        assert!(top.len() > 16, "the brightest half is flattened to {} levels", top.len());
+        assert!(apply(&DynamicImage::ImageRgba8(RgbaImage::new(2, 2)), Tonemap::Reinhard).is_none());
+    }
+}
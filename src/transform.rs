@@ -0,0 +1,1418 @@
+use std::{sync::{atomic::{AtomicBool, AtomicU32, Ordering}, Arc, Mutex}, thread};
+
+use image::{DynamicImage, GenericImageView, GrayImage, ImageBuffer, Rgb, Rgba, RgbaImage};
+
+use crate::affinity;
+use crate::curve::Tables;
+use crate::color::{Hsv, Oklab};
+use crate::color::{hsv_invert_value, is_fixed_point_within, is_gray, kaleidoscope_axis, hsv_reflect_within, hsv_rotate, hsv_stretch_value, hsv_to_rgb, rgb_to_hsv, DEFAULT_TOLERANCE};
+use crate::color::{oklab_chroma_hue, oklab_chroma_invert, oklab_reflect, oklab_rotate, oklab_to_rgb, rgb_to_oklab};
+use crate::space::{ColorSpace, HueSpace};
+use crate::stats::HueStats;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Horizontal,
+    Vertical,
+}
+
+// where the reflection axis of each pixel comes from
+#[derive(Clone, Copy)]
+pub enum Axis {
+    Fixed(f32),
+    // changes linearly from start at the left (or top) edge to end at the right (or bottom) edge
+    Gradient { start: f32, end: f32, direction: Direction },
+    // changes with distance from the center, reaching edge at the farthest corner
+    Radial { center_angle: f32, edge_angle: f32, center: Option<(f32, f32)> },
+    // low for pixels with an hsv saturation (0-100) under threshold, high for the rest
+    BySaturation { low: f32, high: f32, threshold: f32 },
+    // from the gray value (0-255 for 0-180 degrees) of the same pixel of an image the size of the input,
+    // borrowed for the whole run so the axis stays cheap to copy into every worker
+    Map(&'static GrayImage),
+}
+
+impl Axis {
+    pub fn angle_at(&self, x: u32, y: u32, width: u32, height: u32) -> f32 {
+        match *self {
+            Axis::Fixed(angle) => angle,
+            Axis::Gradient { start, end, direction } => {
+                let (position, length) = match direction {
+                    Direction::Horizontal => (x, width),
+                    Direction::Vertical => (y, height),
+                };
+                let t = if length > 1 { position as f32 / (length - 1) as f32 } else { 0. };
+                start + (end - start) * t
+            }
+            Axis::Radial { center_angle, edge_angle, center } => {
+                let (right, bottom) = (width as f32 - 1., height as f32 - 1.);
+                let (cx, cy) = center.unwrap_or((right / 2., bottom / 2.));
+                // distance to the farthest corner
+                let max_distance = (cx.max(right - cx)).hypot(cy.max(bottom - cy));
+                let distance = (x as f32 - cx).hypot(y as f32 - cy);
+                let t = if max_distance > 0. { distance / max_distance } else { 0. };
+                center_angle + (edge_angle - center_angle) * t
+            }
+            // the position alone can't tell, transform_pixel picks the band with for_saturation first
+            Axis::BySaturation { high, .. } => high,
+            Axis::Map(map) => map.get_pixel(x, y)[0] as f32 * 180. / 255.,
+        }
+    }
+
+    // the axis to use along row y, worked out once if the angle doesn't change within the row
+    fn for_row(&self, y: u32, width: u32, height: u32) -> Axis {
+        match *self {
+            Axis::Gradient { direction: Direction::Vertical, .. } => Axis::Fixed(self.angle_at(0, y, width, height)),
+            axis => axis,
+        }
+    }
+
+    // the axis for a pixel with this saturation
+    fn for_saturation(&self, saturation: f32) -> Axis {
+        match *self {
+            Axis::BySaturation { low, high, threshold } => Axis::Fixed(if saturation < threshold { low } else { high }),
+            axis => axis,
+        }
+    }
+}
+
+// the color operation picked by the subcommand
+#[derive(Clone, Copy)]
+pub enum Operation {
+    Reflect(Axis),
+    // shift every hue by this many degrees
+    Rotate(f32),
+    // rgb negative
+    Invert,
+    // negate oklab a and b, the complement at the same lightness
+    ChromaInvert,
+    // reflect across the closest of folds mirror lines spread evenly from the axis
+    Kaleidoscope { axis: Axis, folds: u32 },
+    // several steps one after the other, without going back to rgb in between
+    Chain(Chain),
+    // the hue of the same pixel of a second image the size of the input, keeping saturation and value,
+    // borrowed for the whole run like Axis::Map
+    HueFrom(&'static RgbaImage),
+}
+
+// one step of a chain
+#[derive(Clone, Copy)]
+pub enum Stage {
+    Reflect(f32),
+    Rotate(f32),
+    // saturation (or oklch chroma) is multiplied by this
+    Saturation(f32),
+    // hsv value (or oklab lightness) is multiplied by this, stopping at full brightness
+    Brightness(f32),
+}
+
+pub const MAX_STAGES: usize = 8;
+
+// what a saturation stage does with a result over 100
+#[derive(Clone, Copy, PartialEq)]
+pub enum Overflow {
+    // stop at 100
+    Clamp,
+    // start again from 0, 120 becomes 20
+    Wrap,
+}
+
+impl Overflow {
+    pub fn parse(name: &str) -> Option<Overflow> {
+        match name {
+            "clamp" => Some(Overflow::Clamp),
+            "wrap" => Some(Overflow::Wrap),
+            _ => None,
+        }
+    }
+}
+
+// the stages are kept in a fixed size array so that Operation, and with it Transform, can stay Copy
+#[derive(Clone, Copy)]
+pub struct Chain {
+    stages: [Stage; MAX_STAGES],
+    len: usize,
+    overflow: Overflow,
+}
+
+impl Chain {
+    // None without any stages or with more than MAX_STAGES
+    pub fn new(stages: &[Stage]) -> Option<Chain> {
+        if stages.is_empty() || stages.len() > MAX_STAGES {
+            return None;
+        }
+        let mut chain = Chain { stages: [Stage::Rotate(0.); MAX_STAGES], len: stages.len(), overflow: Overflow::Clamp };
+        chain.stages[..stages.len()].copy_from_slice(stages);
+        Some(chain)
+    }
+
+    pub fn with_overflow(self, overflow: Overflow) -> Chain {
+        Chain { overflow, ..self }
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages[..self.len]
+    }
+
+    // grays have no hue for the other stages but still get brighter or darker
+    fn changes_grays(&self) -> bool {
+        self.stages().iter().any(|stage| matches!(stage, Stage::Brightness(_)))
+    }
+
+    // an hsv saturation scaled by a stage brought back into 0-100, factors are never negative
+    fn fit_saturation(&self, saturation: f32) -> f32 {
+        match self.overflow {
+            Overflow::Clamp => saturation.min(100.),
+            Overflow::Wrap if saturation > 100. => saturation.rem_euclid(100.),
+            Overflow::Wrap => saturation,
+        }
+    }
+}
+
+// settings applied to every pixel, copied into each worker thread
+#[derive(Clone, Copy)]
+pub struct Transform {
+    pub operation: Operation,
+    // how many times the operation is applied, kept in hsv between passes
+    // reflecting twice along the same axis gives back the input (up to rounding)
+    pub repeat: u32,
+    // how to read the input's rgb values
+    pub space: ColorSpace,
+    // what reflect and rotate measure hues in, levels and invert_value always work in hsv
+    pub hue_space: HueSpace,
+    // value range to stretch out to 0-100 before the operation
+    pub levels: Option<(f32, f32)>,
+    // v becomes 100 - v before the operation
+    pub invert_value: bool,
+    // --equalize-hue, the share of the colored pixels with an hsv hue under each whole degree (0 to 360),
+    // every hue is moved to its place in it after the value steps so the hues spread evenly round the wheel
+    pub hue_equalization: Option<[f32; 361]>,
+    // --highlight-only, pixels with an oklab lightness (0-100) at or under this skip the operation
+    // and what comes before it, the steps after it still apply
+    pub highlight_only: Option<f32>,
+    // which input channel each of r, g and b of the result is taken from, after the operation
+    pub swap: Option<[usize; 3]>,
+    // --curve lookup tables for r, g and b, applied after swap
+    pub curve: Option<Tables>,
+    // color blended over the result, with its opacity
+    pub tint: Option<(Rgb<u8>, f32)>,
+    // up to how many degrees each hue is moved at random after the operation, and the seed for it
+    pub jitter: Option<(f32, u64)>,
+    // --glitch, up to how many degrees the reflect axis is turned at random along each row, and the seed for it
+    pub glitch: Option<(f32, u64)>,
+    // most degrees any hue may end up from where it started, after the operation and jitter
+    pub max_shift: Option<f32>,
+    // --tolerance, pixels with an rgb spread (or oklch chroma) up to this count as gray and keep their
+    // color, and hues up to this fraction of a whole turn off the reflect axis count as on it and stay
+    // put, see DEFAULT_TOLERANCE
+    pub tolerance: f32,
+    // how much the corners are darkened after everything else, 0 leaves them alone and 1 makes them black
+    pub vignette: f32,
+    // alpha is multiplied by this, capped at 255, rgb is left alone
+    pub alpha_scale: f32,
+    // bind each worker thread to its own core (best effort, linux only)
+    pub pin_workers: bool,
+    // how many threads share the rows, None picks from the size of the image, up to one per core,
+    // batches that already run one image per core use 1
+    pub threads: Option<u32>,
+}
+
+impl Transform {
+    // just the operation, applied once to srgb input with no extra steps
+    pub fn new(operation: Operation) -> Transform {
+        Transform { operation, repeat: 1, space: ColorSpace::Srgb, hue_space: HueSpace::Hsv, levels: None, invert_value: false, hue_equalization: None, highlight_only: None, swap: None, curve: None, tint: None, jitter: None, glitch: None, max_shift: None, tolerance: DEFAULT_TOLERANCE, vignette: 0., alpha_scale: 1., pin_workers: false, threads: None }
+    }
+}
+
+// the same transform with the axis of reflect or kaleidoscope replaced
+fn with_axis(transform: &Transform, change: impl Fn(&Axis) -> Axis) -> Transform {
+    let operation = match transform.operation {
+        Operation::Reflect(axis) => Operation::Reflect(change(&axis)),
+        Operation::Kaleidoscope { axis, folds } => Operation::Kaleidoscope { axis: change(&axis), folds },
+        operation => operation,
+    };
+    Transform { operation, ..*transform }
+}
+
+fn transform_pixel(pixel: Rgba<u8>, x: u32, y: u32, (width, height): (u32, u32), transform: &Transform) -> Rgba<u8> {
+    let pxl: Rgb<u8> = transform.space.to_srgb(Rgb([pixel[0], pixel[1], pixel[2]]));
+    if is_shadow(pxl, transform) {
+        return finish_pixel(pxl, pixel[3], (x, y), (width, height), transform);
+    }
+    // an even number of inversions cancels out
+    let invert = |rgb: Rgb<u8>| if transform.repeat % 2 == 1 { negative(rgb) } else { rgb };
+    // inverting doesn't need hsv, skip the lossy round trip when there's nothing to do there
+    if let (Operation::Invert, None, false, None) = (transform.operation, transform.levels, transform.invert_value, transform.hue_equalization) {
+        return finish_pixel(invert(pxl), pixel[3], (x, y), (width, height), transform);
+    }
+    // grays (every pixel of a grayscale input) have no hue for reflect or rotate to move,
+    // so they are copied through, or only get the value steps, instead of going round hsv
+    let gray = is_gray(pxl, transform.tolerance) && !changes_grays(&transform.operation);
+    let adjust_value = transform.levels.is_some() || transform.invert_value;
+    if gray && !adjust_value {
+        return finish_pixel(pxl, pixel[3], (x, y), (width, height), transform);
+    }
+    let mut hsv = rgb_to_hsv(&pxl);
+
+    if let Some((min, max)) = transform.levels {
+        hsv = hsv_stretch_value(&hsv, min, max);
+    }
+    if transform.invert_value {
+        hsv = hsv_invert_value(&hsv);
+    }
+    if gray {
+        return finish_pixel(hsv_to_rgb(&hsv), pixel[3], (x, y), (width, height), transform);
+    }
+    if let Some(shares) = &transform.hue_equalization {
+        hsv = equalize_hue(&hsv, shares);
+    }
+    let adjust_hsv = adjust_value || transform.hue_equalization.is_some();
+    // the band is picked on hsv saturation after the value steps, in either hue space
+    let transform = &with_axis(transform, |axis| axis.for_saturation(hsv.0[1]));
+    // chroma-invert always works in oklab, there is no hsv version of it
+    let in_oklab = transform.hue_space == HueSpace::Oklab || matches!(transform.operation, Operation::ChromaInvert);
+    if in_oklab && !matches!(transform.operation, Operation::Invert) {
+        let adjusted = if adjust_hsv { hsv_to_rgb(&hsv) } else { pxl };
+        return finish_pixel(oklab_operation(adjusted, x, y, (width, height), transform), pixel[3], (x, y), (width, height), transform);
+    }
+    let new_rgb = match transform.operation {
+        Operation::Invert => invert(hsv_to_rgb(&hsv)),
+        Operation::ChromaInvert => unreachable!(),
+        _ => {
+            // a hue on the axis stays put however many times it is reflected, so when hsv
+            // is untouched too the input is kept as is, skipping the lossy round trip
+            let on_axis = reflect_angle(&hsv, x, y, (width, height), transform).is_some_and(|angle| is_fixed_point_within(hsv.0[0], angle, transform.tolerance));
+            let no_hue_to_take = matches!(transform.operation, Operation::HueFrom(companion) if companion_color(companion, x, y, transform).is_none());
+            // likewise a whole number of turns, which --reference uses when the hues already match
+            let no_turn = matches!(transform.operation, Operation::Rotate(degrees) if degrees.rem_euclid(360.) == 0.);
+            if !adjust_hsv && transform.jitter.is_none() && (on_axis || no_hue_to_take || no_turn) {
+                return finish_pixel(pxl, pixel[3], (x, y), (width, height), transform);
+            }
+            hsv_to_rgb(&hsv_operation(hsv, x, y, (width, height), transform))
+        }
+    };
+    finish_pixel(new_rgb, pixel[3], (x, y), (width, height), transform)
+}
+
+// the color of the companion image of --hue-from at x, y, read the same way as the input,
+// None where it has no hue to give (gray or fully transparent) and the pixel keeps its own
+fn companion_color(companion: &RgbaImage, x: u32, y: u32, transform: &Transform) -> Option<Rgb<u8>> {
+    let pixel = companion.get_pixel(x, y);
+    let color = transform.space.to_srgb(Rgb([pixel[0], pixel[1], pixel[2]]));
+    (pixel[3] > 0 && !is_gray(color, transform.tolerance)).then_some(color)
+}
+
+// whether grays have to go through the operation instead of being copied, invert flips them
+// and a chain can change their brightness
+fn changes_grays(operation: &Operation) -> bool {
+    match operation {
+        Operation::Invert => true,
+        Operation::Chain(chain) => chain.changes_grays(),
+        _ => false,
+    }
+}
+
+// pixels --highlight-only leaves out, judged by oklab lightness as it follows how bright colors look,
+// where hsv value puts pure blue level with white
+fn is_shadow(pixel: Rgb<u8>, transform: &Transform) -> bool {
+    transform.highlight_only.is_some_and(|threshold| rgb_to_oklab(&pixel).0[0] * 100. <= threshold)
+}
+
+// the axis a reflect or kaleidoscope turns this pixel around, None for the other operations
+fn reflect_angle(hsv: &Hsv, x: u32, y: u32, (width, height): (u32, u32), transform: &Transform) -> Option<f32> {
+    match transform.operation {
+        Operation::Reflect(axis) => Some(axis_angle(&axis, x, y, (width, height), transform)),
+        Operation::Kaleidoscope { axis, folds } => Some(kaleidoscope_axis(hsv.0[0], axis_angle(&axis, x, y, (width, height), transform), folds)),
+        _ => None,
+    }
+}
+
+// the hue moving part of reflect, rotate and chains in hsv, repeated and jittered
+fn hsv_operation(mut hsv: Hsv, x: u32, y: u32, size: (u32, u32), transform: &Transform) -> Hsv {
+    let original_hue = hsv.0[0];
+    match transform.operation {
+        Operation::Reflect(_) | Operation::Kaleidoscope { .. } => {
+            let angle = reflect_angle(&hsv, x, y, size, transform).unwrap();
+            for _ in 0..transform.repeat {
+                hsv = hsv_reflect_within(&hsv, angle, transform.tolerance);
+            }
+        }
+        Operation::Rotate(degrees) => {
+            for _ in 0..transform.repeat {
+                hsv = hsv_rotate(&hsv, degrees);
+            }
+        }
+        Operation::Chain(chain) => {
+            for _ in 0..transform.repeat {
+                for stage in chain.stages() {
+                    hsv = match *stage {
+                        Stage::Reflect(angle) => hsv_reflect_within(&hsv, angle, transform.tolerance),
+                        Stage::Rotate(degrees) => hsv_rotate(&hsv, degrees),
+                        Stage::Saturation(factor) => Hsv([hsv.0[0], chain.fit_saturation(hsv.0[1] * factor), hsv.0[2]]),
+                        Stage::Brightness(factor) => Hsv([hsv.0[0], hsv.0[1], (hsv.0[2] * factor).min(100.)]),
+                    };
+                }
+            }
+        }
+        Operation::HueFrom(companion) => {
+            if let Some(color) = companion_color(companion, x, y, transform) {
+                hsv = Hsv([rgb_to_hsv(&color).0[0], hsv.0[1], hsv.0[2]]);
+            }
+        }
+        Operation::Invert | Operation::ChromaInvert => unreachable!(),
+    }
+    if let Some(degrees) = jitter_degrees(x, y, transform) {
+        hsv = hsv_rotate(&hsv, degrees);
+    }
+    // --max-shift pulls the hue back towards where it started, after everything that moved it
+    match transform.max_shift {
+        Some(max) => hsv_rotate(&hsv, limit_shift(original_hue, hsv.0[0], max)),
+        None => hsv,
+    }
+}
+
+// reflect or rotate with hues taken from oklch instead of hsv, and chroma-invert
+fn oklab_operation(pixel: Rgb<u8>, x: u32, y: u32, (width, height): (u32, u32), transform: &Transform) -> Rgb<u8> {
+    let mut lab = rgb_to_oklab(&pixel);
+    let (chroma, hue) = oklab_chroma_hue(&lab);
+    let original_hue = hue;
+    // grays have no real hue, moving it would only add rounding noise, the least colorful 8 bit
+    // color has a chroma just over 1e-3 and grays come out at most around 3e-7, the default
+    // tolerance sits between the two
+    if chroma <= transform.tolerance && !changes_grays(&transform.operation) {
+        return pixel;
+    }
+    match transform.operation {
+        Operation::Reflect(axis) | Operation::Kaleidoscope { axis, .. } => {
+            let mut angle = axis_angle(&axis, x, y, (width, height), transform);
+            if let Operation::Kaleidoscope { folds, .. } = transform.operation {
+                angle = kaleidoscope_axis(hue, angle, folds);
+            }
+            if transform.jitter.is_none() && is_fixed_point_within(hue, angle, transform.tolerance) {
+                return pixel;
+            }
+            for _ in 0..transform.repeat {
+                lab = oklab_reflect(&lab, angle);
+            }
+        }
+        Operation::Rotate(degrees) => {
+            for _ in 0..transform.repeat {
+                lab = oklab_rotate(&lab, degrees);
+            }
+        }
+        Operation::Chain(chain) => {
+            for _ in 0..transform.repeat {
+                for stage in chain.stages() {
+                    lab = match *stage {
+                        Stage::Reflect(angle) => oklab_reflect(&lab, angle),
+                        Stage::Rotate(degrees) => oklab_rotate(&lab, degrees),
+                        // scaling a and b scales chroma and keeps the hue
+                        Stage::Saturation(factor) => Oklab([lab.0[0], lab.0[1] * factor, lab.0[2] * factor]),
+                        Stage::Brightness(factor) => Oklab([(lab.0[0] * factor).min(1.), lab.0[1], lab.0[2]]),
+                    };
+                }
+            }
+        }
+        Operation::HueFrom(companion) => {
+            let taken = companion_color(companion, x, y, transform).map(|color| oklab_chroma_hue(&rgb_to_oklab(&color)));
+            match taken {
+                Some((chroma, taken_hue)) if chroma > transform.tolerance => lab = oklab_rotate(&lab, taken_hue - hue),
+                _ if transform.jitter.is_none() => return pixel,
+                _ => {}
+            }
+        }
+        // an even number of them cancels out
+        Operation::ChromaInvert => {
+            if transform.repeat % 2 == 1 {
+                lab = oklab_chroma_invert(&lab);
+            }
+        }
+        Operation::Invert => unreachable!(),
+    }
+    if let Some(degrees) = jitter_degrees(x, y, transform) {
+        lab = oklab_rotate(&lab, degrees);
+    }
+    // bringing the color into srgb afterwards keeps the hue, so the cap still holds
+    if let Some(max) = transform.max_shift {
+        lab = oklab_rotate(&lab, limit_shift(original_hue, oklab_chroma_hue(&lab).1, max));
+    }
+    oklab_to_rgb(&lab)
+}
+
+// the angle of the axis at x, y, turned by --glitch along the row when it is on
+fn axis_angle(axis: &Axis, x: u32, y: u32, (width, height): (u32, u32), transform: &Transform) -> f32 {
+    let angle = axis.angle_at(x, y, width, height);
+    match transform.glitch {
+        Some((degrees, seed)) => angle + degrees * noise(seed, y as u64),
+        None => angle,
+    }
+}
+
+// the degrees to turn a hue that went from original to new by so it ends up at most max away,
+// measured the short way round the wheel, a move of exactly 180 is taken as positive
+fn limit_shift(original: f32, new: f32, max: f32) -> f32 {
+    let shift = (new - original + 180.).rem_euclid(360.) - 180.;
+    let shift = if shift == -180. { 180. } else { shift };
+    shift.clamp(-max, max) - shift
+}
+
+// how far the hue at x, y is moved by --jitter, if it is on
+// the noise comes from hashing the seed with the position instead of a shared generator,
+// so rows can be done in any order on any thread and still give the same result
+fn jitter_degrees(x: u32, y: u32, transform: &Transform) -> Option<f32> {
+    let (degrees, seed) = transform.jitter?;
+    Some(degrees * noise(seed, (y as u64) << 32 | x as u64))
+}
+
+// a number from -1 to 1 that only depends on the seed and the key, splitmix64
+fn noise(seed: u64, key: u64) -> f32 {
+    let mut z = seed.wrapping_add(key.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    // the top 24 bits as a fraction
+    let fraction = (z >> 40) as f32 / (1 << 24) as f32;
+    fraction * 2. - 1.
+}
+
+// steps applied after the operation, alpha is carried over untouched
+fn finish_pixel(pixel: Rgb<u8>, alpha: u8, position: (u32, u32), size: (u32, u32), transform: &Transform) -> Rgba<u8> {
+    let pixel = transform.space.from_srgb(pixel);
+    let pixel = match transform.swap {
+        Some(order) => Rgb(order.map(|channel| pixel[channel])),
+        None => pixel,
+    };
+    let pixel = match &transform.curve {
+        Some(tables) => Rgb([tables[0][pixel[0] as usize], tables[1][pixel[1] as usize], tables[2][pixel[2] as usize]]),
+        None => pixel,
+    };
+    let pixel = match transform.tint {
+        Some((color, opacity)) => blend(pixel, color, opacity),
+        None => pixel,
+    };
+    let pixel = if transform.vignette > 0. { vignette(pixel, position, size, transform.vignette) } else { pixel };
+    let alpha = if transform.alpha_scale == 1. { alpha } else { (alpha as f32 * transform.alpha_scale).round().min(255.) as u8 };
+    Rgba([pixel[0], pixel[1], pixel[2], alpha])
+}
+
+fn negative(pixel: Rgb<u8>) -> Rgb<u8> {
+    Rgb([255 - pixel[0], 255 - pixel[1], 255 - pixel[2]])
+}
+
+// mixes color over pixel, opacity 0 keeps pixel and 1 replaces it
+fn blend(pixel: Rgb<u8>, color: Rgb<u8>, opacity: f32) -> Rgb<u8> {
+    let mix = |below: u8, above: u8| (below as f32 + (above as f32 - below as f32) * opacity).round() as u8;
+    Rgb([mix(pixel[0], color[0]), mix(pixel[1], color[1]), mix(pixel[2], color[2])])
+}
+
+// darkens pixel more the farther it is from the center, easing in so the middle stays bright
+// the corners are scaled by 1 - strength
+fn vignette(pixel: Rgb<u8>, (x, y): (u32, u32), (width, height): (u32, u32), strength: f32) -> Rgb<u8> {
+    let (cx, cy) = ((width as f32 - 1.) / 2., (height as f32 - 1.) / 2.);
+    let max_distance = cx.hypot(cy);
+    let t = if max_distance > 0. { (x as f32 - cx).hypot(y as f32 - cy) / max_distance } else { 0. };
+    // smoothstep
+    let falloff = t * t * (3. - 2. * t);
+    let scale = (1. - strength * falloff).max(0.);
+    Rgb(pixel.0.map(|channel| (channel as f32 * scale).round() as u8))
+}
+
+// smallest and largest hsv value in the image, None if every pixel has the same value
+pub fn value_range(img: &DynamicImage, space: ColorSpace) -> Option<(f32, f32)> {
+    let (mut min, mut max) = (u8::MAX, u8::MIN);
+    for (_, _, pixel) in img.pixels() {
+        let pixel = space.to_srgb(Rgb([pixel[0], pixel[1], pixel[2]]));
+        let value = pixel[0].max(pixel[1]).max(pixel[2]);
+        min = min.min(value);
+        max = max.max(value);
+    }
+    if min >= max {
+        return None;
+    }
+    Some((min as f32 / 255. * 100., max as f32 / 255. * 100.))
+}
+
+// for --equalize-hue, the share of the pixels with a hue under each whole degree, counting only
+// pixels that have a hue (not gray within tolerance, not fully transparent), None when there are none
+pub fn hue_distribution(img: &DynamicImage, space: ColorSpace, tolerance: f32) -> Option<[f32; 361]> {
+    let counts = hue_histogram(img, space, HueSpace::Hsv, tolerance);
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let mut shares = [0.; 361];
+    let mut below = 0;
+    for (degree, count) in counts.iter().enumerate() {
+        below += count;
+        shares[degree + 1] = below as f32 / total as f32;
+    }
+    Some(shares)
+}
+
+// how many pixels have a hue in each whole degree, measured in hue_space, fully transparent pixels
+// and grays within tolerance (see is_gray) have none and aren't counted
+pub fn hue_histogram(img: &DynamicImage, space: ColorSpace, hue_space: HueSpace, tolerance: f32) -> [u64; 360] {
+    let mut counts = [0; 360];
+    for (_, _, pixel) in img.pixels() {
+        let rgb = space.to_srgb(Rgb([pixel[0], pixel[1], pixel[2]]));
+        if pixel[3] == 0 || is_gray(rgb, tolerance) {
+            continue;
+        }
+        // reds just under 360 come out of rgb_to_hsv as small negative hues
+        let hue = match hue_space {
+            HueSpace::Hsv => rgb_to_hsv(&rgb).0[0],
+            HueSpace::Oklab => oklab_chroma_hue(&rgb_to_oklab(&rgb)).1,
+        }
+        .rem_euclid(360.);
+        counts[(hue as usize).min(359)] += 1;
+    }
+    counts
+}
+
+// for --reference, the average direction of the hues round the wheel in hue_space, each pixel
+// counted by how colorful it is (hsv saturation, or oklch chroma), so near grays with a noisy hue
+// barely pull it, grays within tolerance are left out, None when no pixel has a hue or the hues
+// cancel out
+pub fn mean_hue(img: &DynamicImage, space: ColorSpace, hue_space: HueSpace, tolerance: f32) -> Option<f32> {
+    let mut hues = HueStats::default();
+    for (_, _, pixel) in img.pixels() {
+        let rgb = space.to_srgb(Rgb([pixel[0], pixel[1], pixel[2]]));
+        if pixel[3] == 0 || is_gray(rgb, tolerance) {
+            continue;
+        }
+        let (weight, hue) = match hue_space {
+            HueSpace::Hsv => {
+                let [hue, saturation, _] = rgb_to_hsv(&rgb).0;
+                (saturation, hue)
+            }
+            HueSpace::Oklab => oklab_chroma_hue(&rgb_to_oklab(&rgb)),
+        };
+        hues.add(hue, weight);
+    }
+    hues.mean().map(|mean| (mean as f32).min(359.99))
+}
+
+// for --reference, the reflect axis that takes mean hue hue to target, halfway between them as
+// reflecting along angle takes a hue to 2 * angle - hue, None when the two already match to within
+// tolerance of a whole turn, as
+// reflecting along the mean hue itself would mirror every other hue around it for no change
+pub fn matching_axis(hue: f32, target: f32, tolerance: f32) -> Option<f32> {
+    let apart = (hue - target).rem_euclid(360.);
+    if apart.min(360. - apart) <= tolerance * 360. {
+        return None;
+    }
+    // angles 180 apart are the same line
+    Some(((hue + target) / 2.).rem_euclid(180.))
+}
+
+// for --suggest-angle, from a hue_histogram, the whole degree reflect angle that moves the hues
+// furthest on average and the one that moves them closest to 90 degrees on average, each with
+// that average, None when no pixel has a hue
+// reflecting along angle takes a hue to 2 * angle - hue, how far that is around the wheel
+// is what's averaged, each degree of the histogram counted at its start so exact hues like pure red stay exact
+pub fn suggest_angles(histogram: &[u64; 360]) -> Option<((f32, f32), (f32, f32))> {
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let mean_shift = |angle: f32| {
+        let sum: f64 = histogram
+            .iter()
+            .enumerate()
+            .map(|(degree, &count)| {
+                let moved = (2. * (angle - degree as f32)).rem_euclid(360.);
+                count as f64 * moved.min(360. - moved) as f64
+            })
+            .sum();
+        (sum / total as f64) as f32
+    };
+    // angles 180 apart are the same line, so 0 to 179 covers every reflection
+    let shifts: Vec<(f32, f32)> = (0..180).map(|angle| (angle as f32, mean_shift(angle as f32))).collect();
+    // the first of equally good angles, so the same image always gets the same suggestion
+    let pick = |miss: &dyn Fn(f32) -> f32| *shifts.iter().min_by(|a, b| miss(a.1).total_cmp(&miss(b.1))).unwrap();
+    Some((pick(&|shift| -shift), pick(&|shift| (shift - 90.).abs())))
+}
+
+// the hue moved to its share of the distribution times 360, so a hue that half the pixels are under
+// lands at 180, linear within each degree so nearby hues stay apart
+fn equalize_hue(pixel: &Hsv, shares: &[f32; 361]) -> Hsv {
+    let [hue, saturation, value] = pixel.0;
+    let hue = hue.rem_euclid(360.);
+    let degree = (hue as usize).min(359);
+    let share = shares[degree] + (hue - degree as f32) * (shares[degree + 1] - shares[degree]);
+    Hsv([share * 360., saturation, value])
+}
+
+// called with the partly filled output and how many percent of rows are done
+pub type Snapshot = Arc<dyn Fn(&RgbaImage, f32) + Send + Sync>;
+
+// called with a finished row and its y
+pub type RowHandler<'a> = dyn Fn(u32, &[Rgba<u8>]) + Sync + 'a;
+
+// optional ways for the caller to follow or stop a run
+#[derive(Default)]
+struct Hooks<'a> {
+    snapshots: Option<(f32, Snapshot)>,
+    // checked before every row, the run gives up once it is set
+    cancel: Option<&'a AtomicBool>,
+    // called with the fraction of rows done after each row
+    progress: Option<&'a (dyn Fn(f32) + Sync)>,
+    // called with each row once it is done, in the order the workers finish them
+    rows: Option<&'a RowHandler<'a>>,
+}
+
+// runs the transform over every pixel of the image, split across the cores (fewer for small images)
+pub fn reflect_image(img: &DynamicImage, transform: Transform) -> RgbaImage {
+    run(img, transform, Hooks::default()).unwrap()
+}
+
+// the one pixel at x, y as reflect_image would give it, along with its hsv before and after the hue
+// is moved (after --levels, --invert-value and --equalize-hue), which is None for pixels that don't go round hsv:
+// transparent and gray ones, those under --highlight-only, invert, chroma-invert and anything in --space oklab
+pub fn trace_pixel(img: &DynamicImage, x: u32, y: u32, transform: Transform) -> (Rgba<u8>, Option<(Hsv, Hsv)>) {
+    let size = img.dimensions();
+    let transform = with_axis(&transform, |axis| axis.for_row(y, size.0, size.1));
+    let pixel = img.get_pixel(x, y);
+    let result = transform_pixel(pixel, x, y, size, &transform);
+    let rgb = transform.space.to_srgb(Rgb([pixel[0], pixel[1], pixel[2]]));
+    let gray = is_gray(rgb, transform.tolerance) && !changes_grays(&transform.operation);
+    let moves_hue = !matches!(transform.operation, Operation::Invert | Operation::ChromaInvert);
+    if pixel[3] == 0 || gray || is_shadow(rgb, &transform) || transform.hue_space == HueSpace::Oklab || !moves_hue {
+        return (result, None);
+    }
+    let mut hsv = rgb_to_hsv(&rgb);
+    if let Some((min, max)) = transform.levels {
+        hsv = hsv_stretch_value(&hsv, min, max);
+    }
+    if transform.invert_value {
+        hsv = hsv_invert_value(&hsv);
+    }
+    if let Some(shares) = &transform.hue_equalization {
+        hsv = equalize_hue(&hsv, shares);
+    }
+    let transform = with_axis(&transform, |axis| axis.for_saturation(hsv.0[1]));
+    let moved = hsv_operation(Hsv(hsv.0), x, y, size, &transform);
+    (result, Some((hsv, moved)))
+}
+
+// like reflect_image, calling snapshot each time another `every` percent of rows is finished
+pub fn reflect_image_with_snapshots(img: &DynamicImage, transform: Transform, snapshots: Option<(f32, Snapshot)>) -> RgbaImage {
+    run(img, transform, Hooks { snapshots, ..Hooks::default() }).unwrap()
+}
+
+// like reflect_image_with_snapshots, also handing each finished row to row as it is done
+pub fn reflect_image_with_rows(img: &DynamicImage, transform: Transform, snapshots: Option<(f32, Snapshot)>, row: &RowHandler<'_>) -> RgbaImage {
+    run(img, transform, Hooks { snapshots, rows: Some(row), ..Hooks::default() }).unwrap()
+}
+
+// reflects along reflect_angle, returning None if cancel was set before every row was done
+// workers check the flag between rows so they stop soon after it is set
+pub fn reflect_image_cancellable(img: &DynamicImage, reflect_angle: f32, cancel: &AtomicBool) -> Option<RgbaImage> {
+    let transform = Transform::new(Operation::Reflect(Axis::Fixed(reflect_angle)));
+    run(img, transform, Hooks { cancel: Some(cancel), ..Hooks::default() })
+}
+
+// reflects along reflect_angle, calling progress from the worker threads with the fraction of rows done
+// as they finish, calls from different threads may arrive slightly out of order, then 1.0 comes
+// exactly once from the calling thread after every row is done, last
+pub fn reflect_image_with_progress(img: &DynamicImage, reflect_angle: f32, progress: impl Fn(f32) + Sync) -> RgbaImage {
+    let transform = Transform::new(Operation::Reflect(Axis::Fixed(reflect_angle)));
+    run(img, transform, Hooks { progress: Some(&progress), ..Hooks::default() }).unwrap()
+}
+
+// reflects along reflect_angle writing over img instead of into a second buffer,
+// each pixel only depends on itself so it can be read and replaced in one go
+pub fn reflect_in_place(img: &mut RgbaImage, reflect_angle: f32) {
+    transform_in_place(img, Transform::new(Operation::Reflect(Axis::Fixed(reflect_angle))));
+}
+
+fn transform_in_place(img: &mut RgbaImage, transform: Transform) {
+    let (width, height) = img.dimensions();
+    let core_count = thread_count(&transform, (width, height));
+    if width == 0 || height == 0 {
+        return;
+    }
+    // one band of whole rows per core
+    let band_rows = height.div_ceil(core_count);
+    let band_length = band_rows as usize * width as usize * 4;
+
+    thread::scope(|scope| {
+        for (band, pixels) in img.chunks_mut(band_length).enumerate() {
+            spawn(scope, move || {
+                if pins_workers(&transform, core_count) {
+                    affinity::pin_to_core(band);
+                }
+                let top = band as u32 * band_rows;
+                for (i, channels) in pixels.chunks_exact_mut(4).enumerate() {
+                    let (x, y) = (i as u32 % width, top + i as u32 / width);
+                    let pixel = Rgba([channels[0], channels[1], channels[2], channels[3]]);
+                    channels.copy_from_slice(&transform_pixel(pixel, x, y, (width, height), &transform).0);
+                }
+            });
+        }
+    });
+}
+
+fn run(img: &DynamicImage, transform: Transform, hooks: Hooks) -> Option<RgbaImage> {
+    let (width, height) = img.dimensions();
+    let core_count = thread_count(&transform, (width, height));
+
+    let new_img = Mutex::new(ImageBuffer::new(width, height));
+    let rows_done = AtomicU32::new(0);
+    let cancelled = || hooks.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+
+    let process_row = |y: u32| {
+        let row_transform = with_axis(&transform, |axis| axis.for_row(y, width, height));
+        let row: Vec<Rgba<u8>> = (0..width)
+            .map(|x| transform_pixel(img.get_pixel(x, y), x, y, (width, height), &row_transform))
+            .collect();
+        if let Some(rows) = hooks.rows {
+            rows(y, &row);
+        }
+        let mut new_img_lock = new_img.lock().unwrap();
+        for (x, new_pixel) in row.into_iter().enumerate() {
+            new_img_lock.put_pixel(x as u32, y, new_pixel);
+        }
+        drop(new_img_lock);
+        let done = row_finished(&new_img, &rows_done, height, &hooks.snapshots);
+        // the last row is left to the report after the workers are done
+        if let Some(progress) = hooks.progress
+            && done < height
+        {
+            progress(done as f32 / height as f32);
+        }
+    };
+
+    thread::scope(|scope| {
+        // worker n takes rows n, n + core_count, n + 2 * core_count and so on to the bottom,
+        // so the rows left over when height isn't a multiple of core_count go through the same loop
+        for y_inner in 0..core_count.min(height) {
+            let process_row = &process_row;
+            let cancelled = &cancelled;
+            spawn(scope, move || {
+                // the main thread is left floating, pinning it would outlast the run
+                if pins_workers(&transform, core_count) {
+                    affinity::pin_to_core(y_inner as usize);
+                }
+                for y in (y_inner..height).step_by(core_count as usize) {
+                    if cancelled() {
+                        return;
+                    }
+                    process_row(y);
+                }
+            });
+        }
+    });
+
+    if cancelled() && rows_done.load(Ordering::SeqCst) < height {
+        return None;
+    }
+    // once every row is in, even for an image with no rows
+    if let Some(progress) = hooks.progress {
+        progress(1.);
+    }
+    Some(new_img.into_inner().unwrap())
+}
+
+// pixels each worker gets at least when the count is left to thread_count, the 100k the request
+// suggested, measured with benches/threads.rs (release build): starting and joining a
+// thread takes about 20µs and a reflected pixel about 0.2µs, so 100k pixels are some 20ms of work,
+// a thousand times the start, which errs well on the side of fewer threads, the machine it was
+// measured on had one core, so where more threads start to win wasn't seen
+const PIXELS_PER_THREAD: u64 = 100_000;
+
+fn thread_count(transform: &Transform, (width, height): (u32, u32)) -> u32 {
+    match transform.threads {
+        Some(threads) => threads.max(1),
+        None => threads_for(width as u64 * height as u64, num_cpus::get().max(1) as u32),
+    }
+}
+
+// one worker per PIXELS_PER_THREAD pixels up to one per core, so small images stay on one thread
+fn threads_for(pixels: u64, cores: u32) -> u32 {
+    (pixels / PIXELS_PER_THREAD).clamp(1, cores as u64) as u32
+}
+
+// the one thread of every image of a batch would all end up on the first core
+fn pins_workers(transform: &Transform, thread_count: u32) -> bool {
+    transform.pin_workers && thread_count > 1
+}
+
+// starts work on its own thread, wasm32 has no threads so there it runs right away on the caller's,
+// a worker then simply finishes before the next one starts
+fn spawn<'scope>(scope: &'scope thread::Scope<'scope, '_>, work: impl FnOnce() + Send + 'scope) {
+    #[cfg(not(target_arch = "wasm32"))]
+    scope.spawn(work);
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = scope;
+        work();
+    }
+}
+
+// counts a finished row and takes a snapshot if it crossed the next step, returns the rows done so far
+fn row_finished(new_img: &Mutex<RgbaImage>, rows_done: &AtomicU32, height: u32, snapshots: &Option<(f32, Snapshot)>) -> u32 {
+    let done = rows_done.fetch_add(1, Ordering::SeqCst) + 1;
+    let Some((every, snapshot)) = snapshots else { return done };
+    let step = |rows: u32| (rows as f32 * 100. / height as f32 / every).floor();
+    if step(done) > step(done - 1) {
+        // holding the lock keeps other rows from being written mid snapshot
+        let partial = new_img.lock().unwrap();
+        snapshot(&partial, step(done) * every);
+    }
+    done
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::hsv_reflect;
+    use crate::metrics;
+
+    // every color a little different, with some transparent and half transparent pixels
+    fn test_image(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            let alpha = match (x + y) % 11 {
+                0 => 0,
+                1 => 128,
+                _ => 255,
+            };
+            Rgba([(x * 7 + y * 3) as u8, ((x * 13) ^ (y * 5)) as u8, (y * 11 + x) as u8, alpha])
+        }))
+    }
+
+    fn with_threads(transform: Transform, threads: Option<u32>) -> Transform {
+        Transform { threads, ..transform }
+    }
+
+    #[test]
+    fn same_result_with_1_2_and_7_threads() {
+        let img = test_image(157, 61);
+        let transform = Transform { jitter: Some((15., 7)), ..Transform::new(Operation::Reflect(Axis::Fixed(40.))) };
+        let expected = run(&img, with_threads(transform, Some(1)), Hooks::default()).unwrap();
+        for threads in [2, 7] {
+            let result = run(&img, with_threads(transform, Some(threads)), Hooks::default()).unwrap();
+            if let Some((x, y)) = metrics::first_difference(&expected, &result) {
+                panic!("{} threads differ first at {},{}: {:?} instead of {:?}", threads, x, y, result.get_pixel(x, y).0, expected.get_pixel(x, y).0);
+            }
+        }
+    }
+
+    #[test]
+    fn leftover_rows_when_height_isnt_a_multiple_of_the_workers() {
+        // opaque, so a row that was never written would show up as transparent
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(9, 13, |x, y| Rgba([(x * 29) as u8, (y * 19) as u8, 200, 255])));
+        let transform = Transform::new(Operation::Reflect(Axis::Fixed(75.)));
+        let rows = Mutex::new(vec![]);
+        let record = |y: u32, _: &[Rgba<u8>]| rows.lock().unwrap().push(y);
+        let result = run(&img, with_threads(transform, Some(4)), Hooks { rows: Some(&record), ..Hooks::default() }).unwrap();
+        let mut rows = rows.into_inner().unwrap();
+        rows.sort();
+        assert_eq!(rows, (0..13).collect::<Vec<_>>());
+        let one_worker = run(&img, with_threads(transform, Some(1)), Hooks::default()).unwrap();
+        assert_eq!(result, one_worker);
+        for (x, y, pixel) in result.enumerate_pixels() {
+            assert_eq!(*pixel, transform_pixel(img.get_pixel(x, y), x, y, (9, 13), &transform), "pixel {},{}", x, y);
+        }
+    }
+
+    #[test]
+    fn small_images_use_one_thread_and_large_ones_several() {
+        assert_eq!(threads_for(32 * 32, 8), 1);
+        assert_eq!(threads_for(PIXELS_PER_THREAD - 1, 8), 1);
+        assert_eq!(threads_for(PIXELS_PER_THREAD * 2, 8), 2);
+        assert_eq!(threads_for(1920 * 1080, 8), 8);
+        assert_eq!(threads_for(1920 * 1080, 1), 1);
+        let transform = Transform::new(Operation::Reflect(Axis::Fixed(40.)));
+        assert_eq!(thread_count(&transform, (32, 32)), 1);
+        assert_eq!(thread_count(&with_threads(transform, Some(3)), (32, 32)), 3);
+    }
+
+    #[test]
+    fn one_forced_worker_matches_the_default() {
+        // big enough for the default to split it where there are several cores
+        let img = test_image(700, 300);
+        let transform = Transform { glitch: Some((20., 3)), ..Transform::new(Operation::Reflect(Axis::Fixed(200.))) };
+        assert_eq!(thread_count(&with_threads(transform, Some(1)), (700, 300)), 1);
+        let single = run(&img, with_threads(transform, Some(1)), Hooks::default()).unwrap();
+        assert_eq!(single, run(&img, transform, Hooks::default()).unwrap());
+    }
+
+    #[test]
+    fn progress_ends_at_one_exactly_once() {
+        for (width, height) in [(20, 13), (20, 0), (0, 5)] {
+            let img = test_image(width, height);
+            let reports = Mutex::new(vec![]);
+            let transform = with_threads(Transform::new(Operation::Reflect(Axis::Fixed(30.))), Some(3));
+            run(&img, transform, Hooks { progress: Some(&|done| reports.lock().unwrap().push(done)), ..Hooks::default() }).unwrap();
+            let reports = reports.into_inner().unwrap();
+            assert_eq!(reports.last(), Some(&1.), "{}x{}", width, height);
+            assert_eq!(reports.iter().filter(|&&done| done == 1.).count(), 1, "{}x{}", width, height);
+            assert!(reports.iter().all(|done| (0. ..=1.).contains(done)), "{}x{}: {:?}", width, height, reports);
+        }
+    }
+
+    #[test]
+    fn transparent_gif_index_stays_transparent() {
+        use image::codecs::gif::GifEncoder;
+        use image::{Frame, ImageFormat};
+        // the encoder gives alpha 0 pixels the gif's transparent index
+        let visible = Rgba([200, 40, 40, 255]);
+        let img = RgbaImage::from_fn(8, 6, |x, y| if (x + y) % 3 == 0 { Rgba([0, 0, 0, 0]) } else { visible });
+        let mut gif = vec![];
+        GifEncoder::new(&mut gif).encode_frame(Frame::new(img.clone())).unwrap();
+        let decoded = image::load_from_memory_with_format(&gif, ImageFormat::Gif).unwrap();
+        let result = run(&decoded, Transform::new(Operation::Reflect(Axis::Fixed(90.))), Hooks::default()).unwrap();
+        for (x, y, pixel) in result.enumerate_pixels() {
+            let expected = if img.get_pixel(x, y)[3] == 0 { 0 } else { 255 };
+            assert_eq!(pixel[3], expected, "alpha at {},{}", x, y);
+        }
+    }
+
+    #[test]
+    fn own_mean_hue_as_reference_leaves_the_image_unchanged() {
+        let img = test_image(40, 30);
+        let hue = mean_hue(&img, ColorSpace::Srgb, HueSpace::Hsv, DEFAULT_TOLERANCE).unwrap();
+        let target = mean_hue(&img, ColorSpace::Srgb, HueSpace::Hsv, DEFAULT_TOLERANCE).unwrap();
+        assert_eq!(matching_axis(hue, target, DEFAULT_TOLERANCE), None);
+        assert_eq!(matching_axis(359.9995, 0., DEFAULT_TOLERANCE), None);
+        // what --reference runs instead of a reflection
+        let result = run(&img, Transform::new(Operation::Rotate(0.)), Hooks::default()).unwrap();
+        if let Some((x, y)) = metrics::first_difference(&img.to_rgba8(), &result) {
+            panic!("changed at {},{}: {:?} instead of {:?}", x, y, result.get_pixel(x, y).0, img.get_pixel(x, y).0);
+        }
+        // any other target is reached exactly
+        let target = (hue + 60.).rem_euclid(360.);
+        let angle = matching_axis(hue, target, DEFAULT_TOLERANCE).unwrap();
+        assert!(((2. * angle - hue).rem_euclid(360.) - target).abs() < 1e-3);
+    }
+
+    #[test]
+    fn gradient_ends_use_start_and_end() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(9, 7, |x, y| Rgba([200, (x * 20) as u8, (y * 30) as u8, 255])));
+        let fixed = |angle| run(&img, Transform::new(Operation::Reflect(Axis::Fixed(angle))), Hooks::default()).unwrap();
+        let (start, end) = (fixed(30.), fixed(120.));
+        for (direction, name) in [(Direction::Horizontal, "horizontal"), (Direction::Vertical, "vertical")] {
+            let axis = Axis::Gradient { start: 30., end: 120., direction };
+            let result = run(&img, Transform::new(Operation::Reflect(axis)), Hooks::default()).unwrap();
+            let (last, along): (u32, fn(u32, u32) -> u32) = match direction {
+                Direction::Horizontal => (8, |x: u32, _: u32| x),
+                Direction::Vertical => (6, |_: u32, y: u32| y),
+            };
+            for (x, y, pixel) in result.enumerate_pixels() {
+                if along(x, y) == 0 {
+                    assert_eq!(pixel, start.get_pixel(x, y), "{} at {},{}", name, x, y);
+                } else if along(x, y) == last {
+                    assert_eq!(pixel, end.get_pixel(x, y), "{} at {},{}", name, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn radial_center_uses_the_center_angle_and_the_corners_the_edge_angle() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(9, 7, |x, y| Rgba([200, (x * 20) as u8, (y * 30) as u8, 255])));
+        let fixed = |angle| run(&img, Transform::new(Operation::Reflect(Axis::Fixed(angle))), Hooks::default()).unwrap();
+        let (center, edge) = (fixed(10.), fixed(80.));
+        let axis = Axis::Radial { center_angle: 10., edge_angle: 80., center: None };
+        assert_eq!(axis.angle_at(4, 3, 9, 7), 10.);
+        let result = run(&img, Transform::new(Operation::Reflect(axis)), Hooks::default()).unwrap();
+        assert_eq!(result.get_pixel(4, 3), center.get_pixel(4, 3));
+        for (x, y) in [(0, 0), (8, 0), (0, 6), (8, 6)] {
+            assert!((axis.angle_at(x, y, 9, 7) - 80.).abs() < 1e-4, "{},{}", x, y);
+            assert_eq!(result.get_pixel(x, y), edge.get_pixel(x, y), "{},{}", x, y);
+        }
+        // --center moves it, the farthest corner from there is the edge
+        let moved = Axis::Radial { center_angle: 10., edge_angle: 80., center: Some((1., 1.)) };
+        assert_eq!(moved.angle_at(1, 1, 9, 7), 10.);
+        assert!((moved.angle_at(8, 6, 9, 7) - 80.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn invert_value_keeps_hue_and_saturation() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([40, 100, 70, 255])));
+        let transform = Transform { invert_value: true, ..Transform::new(Operation::Rotate(0.)) };
+        let result = run(&img, transform, Hooks::default()).unwrap();
+        let before = rgb_to_hsv(&Rgb([40, 100, 70])).0;
+        let pixel = result.get_pixel(0, 0);
+        let after = rgb_to_hsv(&Rgb([pixel[0], pixel[1], pixel[2]])).0;
+        assert!((after[2] - (100. - before[2])).abs() < 0.5, "value {} from {}", after[2], before[2]);
+        // a byte each way moves hue and saturation a little
+        assert!((after[0] - before[0]).abs() < 1., "hue {} from {}", after[0], before[0]);
+        assert!((after[1] - before[1]).abs() < 1., "saturation {} from {}", after[1], before[1]);
+        assert_eq!(pixel[3], 255);
+        // not the rgb negative
+        assert_ne!(pixel.0, [215, 155, 185, 255]);
+    }
+
+    #[test]
+    fn srgb_and_linear_inputs_reflect_differently() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 4, |x, y| Rgba([200, (x * 12) as u8, (y * 40) as u8, 255])));
+        let transform = Transform::new(Operation::Reflect(Axis::Fixed(100.)));
+        let srgb = run(&img, transform, Hooks::default()).unwrap();
+        let linear = run(&img, Transform { space: ColorSpace::Linear, ..transform }, Hooks::default()).unwrap();
+        let differing = srgb.pixels().zip(linear.pixels()).filter(|(a, b)| a != b).count();
+        assert!(differing > 16 * 4 / 2, "only {} pixels differ", differing);
+        // either way the grays stay gray and alpha is kept
+        let gray = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([90, 90, 90, 77])));
+        for space in [ColorSpace::Srgb, ColorSpace::Linear] {
+            let result = run(&gray, Transform { space, ..transform }, Hooks::default()).unwrap();
+            assert_eq!(result.get_pixel(0, 0).0, [90, 90, 90, 77]);
+        }
+    }
+
+    #[test]
+    fn rotating_10_degrees_three_times_is_rotating_30() {
+        let img = test_image(31, 17);
+        let thrice = run(&img, Transform { repeat: 3, ..Transform::new(Operation::Rotate(10.)) }, Hooks::default()).unwrap();
+        let once = run(&img, Transform::new(Operation::Rotate(30.)), Hooks::default()).unwrap();
+        // the hues agree to float rounding, which can tip a channel over to the next byte
+        for (x, y, pixel) in thrice.enumerate_pixels() {
+            let expected = once.get_pixel(x, y);
+            assert!(pixel.0.iter().zip(expected.0).all(|(&a, b)| a.abs_diff(b) <= 1), "{},{}: {:?} instead of {:?}", x, y, pixel.0, expected.0);
+        }
+        for hue in (0..3600).map(|tenth| tenth as f32 / 10.) {
+            let stepped = (0..3).fold(Hsv([hue, 80., 60.]), |hsv, _| hsv_rotate(&hsv, 10.));
+            let apart = (stepped.0[0] - hsv_rotate(&Hsv([hue, 80., 60.]), 30.).0[0]).rem_euclid(360.);
+            assert!(apart.min(360. - apart) < 1e-3, "hue {}", hue);
+        }
+    }
+
+    #[test]
+    fn cancelling_stops_the_workers_and_gives_none() {
+        let img = test_image(40, 60);
+        let cancel = AtomicBool::new(true);
+        assert!(reflect_image_cancellable(&img, 30., &cancel).is_none());
+        cancel.store(false, Ordering::Relaxed);
+        assert!(reflect_image_cancellable(&img, 30., &cancel).is_some());
+        // set from the first finished row, the workers stop at their next one
+        let rows = AtomicU32::new(0);
+        let stop = |_: u32, _: &[Rgba<u8>]| {
+            rows.fetch_add(1, Ordering::Relaxed);
+            cancel.store(true, Ordering::Relaxed);
+        };
+        let transform = with_threads(Transform::new(Operation::Reflect(Axis::Fixed(30.))), Some(3));
+        assert!(run(&img, transform, Hooks { cancel: Some(&cancel), rows: Some(&stop), ..Hooks::default() }).is_none());
+        assert!(rows.into_inner() <= 3);
+    }
+
+    #[test]
+    fn gradient_angle_top_and_bottom_rows_use_the_endpoints() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(6, 11, |x, y| Rgba([(x * 40) as u8, 220, (y * 20) as u8, 255])));
+        // what --gradient-angle 20 150 runs
+        let axis = Axis::Gradient { start: 20., end: 150., direction: Direction::Vertical };
+        // worked out once for each row, down to a fixed angle
+        for (y, expected) in [(0, 20.), (10, 150.)] {
+            let Axis::Fixed(angle) = axis.for_row(y, 6, 11) else { panic!("row {} isn't a fixed angle", y) };
+            assert!((angle - expected).abs() < 1e-4, "row {}: {}", y, angle);
+        }
+        let result = run(&img, Transform::new(Operation::Reflect(axis)), Hooks::default()).unwrap();
+        for (y, angle) in [(0, 20.), (10, 150.)] {
+            let fixed = run(&img, Transform::new(Operation::Reflect(Axis::Fixed(angle))), Hooks::default()).unwrap();
+            for x in 0..6 {
+                assert_eq!(result.get_pixel(x, y), fixed.get_pixel(x, y), "{},{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn vignette_keeps_the_center_and_darkens_the_corners_by_strength() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(9, 9, Rgba([200, 120, 40, 128])));
+        let plain = run(&img, Transform::new(Operation::Rotate(0.)), Hooks::default()).unwrap();
+        for strength in [0.25, 0.5, 1.] {
+            let result = run(&img, Transform { vignette: strength, ..Transform::new(Operation::Rotate(0.)) }, Hooks::default()).unwrap();
+            assert_eq!(result.get_pixel(4, 4), plain.get_pixel(4, 4), "strength {}", strength);
+            let expected = [200., 120., 40.].map(|channel: f32| (channel * (1. - strength)).round() as u8);
+            for (x, y) in [(0, 0), (8, 0), (0, 8), (8, 8)] {
+                assert_eq!(result.get_pixel(x, y).0, [expected[0], expected[1], expected[2], 128], "strength {} at {},{}", strength, x, y);
+            }
+            // in between, darker the farther out
+            let (near, far) = (result.get_pixel(5, 4)[0], result.get_pixel(7, 4)[0]);
+            assert!(200 >= near && near > far && far > expected[0], "strength {}: {} and {}", strength, near, far);
+        }
+    }
+
+    #[test]
+    fn alpha_scale_leaves_the_rgb_alone() {
+        let img = test_image(23, 9);
+        let plain = run(&img, Transform::new(Operation::Reflect(Axis::Fixed(70.))), Hooks::default()).unwrap();
+        for factor in [0.5, 2.] {
+            let scaled = run(&img, Transform { alpha_scale: factor, ..Transform::new(Operation::Reflect(Axis::Fixed(70.))) }, Hooks::default()).unwrap();
+            for (x, y, pixel) in scaled.enumerate_pixels() {
+                let expected = plain.get_pixel(x, y);
+                assert_eq!(pixel.0[..3], expected.0[..3], "rgb at {},{}", x, y);
+                let alpha = (expected[3] as f32 * factor).round().min(255.) as u8;
+                assert_eq!(pixel[3], alpha, "alpha at {},{} with {}", x, y, factor);
+            }
+        }
+        // the test image has alpha 0, 128 and 255
+        let halved = run(&img, Transform { alpha_scale: 0.5, ..Transform::new(Operation::Rotate(0.)) }, Hooks::default()).unwrap();
+        let mut alphas: Vec<u8> = halved.pixels().map(|pixel| pixel[3]).collect();
+        alphas.sort();
+        alphas.dedup();
+        assert_eq!(alphas, [0, 64, 128]);
+    }
+
+    #[test]
+    fn in_place_matches_the_second_buffer() {
+        for (width, height, threads) in [(157, 61, Some(1)), (157, 61, Some(4)), (700, 300, None), (1, 1, None)] {
+            let img = test_image(width, height);
+            for angle in [0., 40., 200.] {
+                let mut in_place = img.to_rgba8();
+                let transform = with_threads(Transform::new(Operation::Reflect(Axis::Fixed(angle))), threads);
+                transform_in_place(&mut in_place, transform);
+                assert_eq!(in_place, reflect_image(&img, Transform::new(Operation::Reflect(Axis::Fixed(angle)))), "{}x{} at {}", width, height, angle);
+            }
+            let mut in_place = img.to_rgba8();
+            reflect_in_place(&mut in_place, 40.);
+            assert_eq!(in_place, reflect_image(&img, Transform::new(Operation::Reflect(Axis::Fixed(40.)))));
+        }
+    }
+
+    #[test]
+    fn two_fold_kaleidoscope_is_a_plain_reflect() {
+        let img = test_image(90, 40);
+        for angle in [0., 35., 90., 271.5] {
+            for hue_space in [HueSpace::Hsv, HueSpace::Oklab] {
+                let plain = Transform { hue_space, ..Transform::new(Operation::Reflect(Axis::Fixed(angle))) };
+                let kaleidoscope = Transform { hue_space, ..Transform::new(Operation::Kaleidoscope { axis: Axis::Fixed(angle), folds: 2 }) };
+                assert_eq!(run(&img, kaleidoscope, Hooks::default()).unwrap(), run(&img, plain, Hooks::default()).unwrap(), "angle {}", angle);
+            }
+        }
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_jitter() {
+        let img = test_image(64, 33);
+        let transform = |seed| Transform { jitter: Some((20., seed)), ..Transform::new(Operation::Reflect(Axis::Fixed(60.))) };
+        let jittered = |seed| run(&img, transform(seed), Hooks::default()).unwrap();
+        let first = jittered(7);
+        assert_eq!(jittered(7), first);
+        // the noise comes from each pixel's position, not from the order the workers get to it
+        assert_eq!(run(&img, with_threads(transform(7), Some(3)), Hooks::default()).unwrap(), first);
+        // another seed moves the hues some other way, and either way it is noise on top of the reflect
+        let other = jittered(8);
+        assert!(first.pixels().zip(other.pixels()).filter(|(a, b)| a != b).count() > 64 * 33 / 4);
+        let plain = run(&img, Transform::new(Operation::Reflect(Axis::Fixed(60.))), Hooks::default()).unwrap();
+        assert_ne!(first, plain);
+    }
+
+    #[test]
+    fn saturation_bands_use_their_own_axis() {
+        // saturations 100, 60, 25 and 0 against a threshold of 50
+        let colors = [[255, 0, 0, 255], [200, 80, 80, 255], [200, 150, 150, 255], [120, 120, 120, 255]];
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 1, |x, _| Rgba(colors[x as usize])));
+        let banded = run(&img, Transform::new(Operation::Reflect(Axis::BySaturation { low: 30., high: 90., threshold: 50. })), Hooks::default()).unwrap();
+        let low = run(&img, Transform::new(Operation::Reflect(Axis::Fixed(30.))), Hooks::default()).unwrap();
+        let high = run(&img, Transform::new(Operation::Reflect(Axis::Fixed(90.))), Hooks::default()).unwrap();
+        assert_eq!(banded.get_pixel(0, 0), high.get_pixel(0, 0));
+        assert_eq!(banded.get_pixel(1, 0), high.get_pixel(1, 0));
+        assert_eq!(banded.get_pixel(2, 0), low.get_pixel(2, 0));
+        assert_ne!(low.get_pixel(2, 0), high.get_pixel(2, 0), "the two axes agree, the test can't tell the bands apart");
+        assert_eq!(banded.get_pixel(3, 0), &Rgba(colors[3]), "a gray has no hue to reflect");
+    }
+
+    #[test]
+    fn reflect_then_rotate_chain_matches_two_runs() {
+        let img = test_image(24, 16);
+        let chain = Chain::new(&[Stage::Reflect(90.), Stage::Rotate(30.)]).unwrap();
+        let chained = run(&img, Transform::new(Operation::Chain(chain)), Hooks::default()).unwrap();
+        let reflected = run(&img, Transform::new(Operation::Reflect(Axis::Fixed(90.))), Hooks::default()).unwrap();
+        let sequential = run(&DynamicImage::ImageRgba8(reflected), Transform::new(Operation::Rotate(30.)), Hooks::default()).unwrap();
+        // the chain stays in hsv between the stages, the two runs round to 8 bits in between
+        for (x, y, pixel) in chained.enumerate_pixels() {
+            let other = sequential.get_pixel(x, y);
+            assert!(pixel.0.iter().zip(other.0).all(|(&a, b)| a.abs_diff(b) <= 1), "{},{}: {:?} {:?}", x, y, pixel, other);
+        }
+    }
+
+    #[test]
+    fn chroma_invert_keeps_neutrals_and_cools_warm_colors() {
+        let colors = [[0, 0, 0], [128, 128, 128], [255, 255, 255], [230, 120, 40], [200, 60, 30]];
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(colors.len() as u32, 1, |x, _| {
+            let [r, g, b] = colors[x as usize];
+            Rgba([r, g, b, 255])
+        }));
+        let result = run(&img, Transform::new(Operation::ChromaInvert), Hooks::default()).unwrap();
+        for x in 0..3 {
+            assert_eq!(*result.get_pixel(x, 0), img.get_pixel(x, 0), "a neutral moved");
+        }
+        for x in 3..colors.len() as u32 {
+            let (before, after) = (img.get_pixel(x, 0), result.get_pixel(x, 0));
+            assert!(after[2] > after[0], "{:?} became {:?}, not a cool color", before, after);
+            let (warm, cool) = (rgb_to_oklab(&Rgb([before[0], before[1], before[2]])), rgb_to_oklab(&Rgb([after[0], after[1], after[2]])));
+            assert!(warm.0[2] > 0. && cool.0[2] < 0., "oklab b went from {} to {}", warm.0[2], cool.0[2]);
+            assert!((warm.0[0] - cool.0[0]).abs() < 0.02, "the lightness changed");
+        }
+    }
+
+    #[test]
+    fn uniform_angle_map_matches_a_fixed_angle() {
+        let img = test_image(12, 9);
+        // 255 is 180 degrees, so these are 0, 60, 120 and 180
+        for (gray, angle) in [(0, 0.), (85, 60.), (170, 120.), (255, 180.)] {
+            let map: &'static GrayImage = Box::leak(Box::new(GrayImage::from_pixel(12, 9, image::Luma([gray]))));
+            let mapped = run(&img, Transform::new(Operation::Reflect(Axis::Map(map))), Hooks::default()).unwrap();
+            let fixed = run(&img, Transform::new(Operation::Reflect(Axis::Fixed(angle))), Hooks::default()).unwrap();
+            assert!(mapped == fixed, "gray {} isn't {} degrees", gray, angle);
+        }
+    }
+
+    #[test]
+    fn tripled_saturation_wraps_or_clamps() {
+        // saturations 50 and 20, tripled to 150 and 60
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 1, |x, _| if x == 0 { Rgba([200, 100, 100, 255]) } else { Rgba([200, 160, 160, 255]) }));
+        let boosted = |overflow| {
+            let chain = Chain::new(&[Stage::Saturation(3.)]).unwrap().with_overflow(overflow);
+            run(&img, Transform::new(Operation::Chain(chain)), Hooks::default()).unwrap()
+        };
+        let (clamped, wrapped) = (boosted(Overflow::Clamp), boosted(Overflow::Wrap));
+        assert_eq!(clamped.get_pixel(0, 0), &Rgba([200, 0, 0, 255]), "150 stops at 100");
+        // 150 starts again from 0 and gives 50, back where it was
+        assert_eq!(wrapped.get_pixel(0, 0), &Rgba([200, 100, 100, 255]));
+        assert_eq!(clamped.get_pixel(1, 0), wrapped.get_pixel(1, 0), "60 is in range either way");
+        assert!(clamped != wrapped);
+    }
+
+    #[test]
+    fn max_shift_caps_every_hue_change() {
+        let wheel = RgbaImage::from_fn(72, 1, |x, _| {
+            let [r, g, b] = hsv_to_rgb(&Hsv([x as f32 * 5., 100., 100.])).0;
+            Rgba([r, g, b, 255])
+        });
+        let img = DynamicImage::ImageRgba8(wheel);
+        let transform = Transform { max_shift: Some(20.), ..Transform::new(Operation::Reflect(Axis::Fixed(90.))) };
+        let result = run(&img, transform, Hooks::default()).unwrap();
+        let hue = |pixel: &Rgba<u8>| rgb_to_hsv(&Rgb([pixel[0], pixel[1], pixel[2]])).0[0];
+        let mut largest = 0f32;
+        for (x, y, pixel) in result.enumerate_pixels() {
+            let apart = (hue(pixel) - hue(&img.get_pixel(x, y))).rem_euclid(360.);
+            largest = largest.max(apart.min(360. - apart));
+        }
+        // a little over for the rounding to 8 bits
+        assert!(largest <= 20.5, "a hue moved {} degrees", largest);
+        assert!(largest >= 19., "nothing reached the cap");
+    }
+
+    #[test]
+    fn identity_curve_leaves_the_image_alone() {
+        let img = test_image(16, 12);
+        let transform = Transform::new(Operation::Reflect(Axis::Fixed(70.)));
+        let plain = run(&img, transform, Hooks::default()).unwrap();
+        // the straight line through the ends, and through a point in the middle too
+        for points in ["0 0\n255 255", "0 0\n128 128\n255 255", "r 0 0\nr 255 255"] {
+            let tables = crate::curve::parse(points).unwrap();
+            assert!(tables.iter().all(|table| table.iter().enumerate().all(|(i, &value)| value == i as u8)), "{:?}", points);
+            let curved = run(&img, Transform { curve: Some(tables), ..transform }, Hooks::default()).unwrap();
+            assert!(curved == plain, "{:?} changed the image", points);
+        }
+    }
+
+    #[test]
+    fn equalized_hues_spread_more_evenly() {
+        // three quarters of the pixels between hue 0 and 40, the rest spread over the wheel
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 20, |x, y| {
+            let hue = if y < 15 { x as f32 } else { x as f32 * 9. };
+            let [r, g, b] = hsv_to_rgb(&Hsv([hue, 90., 90.])).0;
+            Rgba([r, g, b, 255])
+        }));
+        // the largest share of the pixels any 30 degrees hold
+        let fullest = |img: &DynamicImage| {
+            let histogram = hue_histogram(img, ColorSpace::Srgb, HueSpace::Hsv, DEFAULT_TOLERANCE);
+            let bins: Vec<u64> = histogram.chunks(30).map(|bin| bin.iter().sum()).collect();
+            *bins.iter().max().unwrap() as f32 / bins.iter().sum::<u64>() as f32
+        };
+        let shares = hue_distribution(&img, ColorSpace::Srgb, DEFAULT_TOLERANCE).unwrap();
+        let transform = Transform { hue_equalization: Some(shares), ..Transform::new(Operation::Rotate(0.)) };
+        let result = DynamicImage::ImageRgba8(run(&img, transform, Hooks::default()).unwrap());
+        let (before, after) = (fullest(&img), fullest(&result));
+        assert!(before > 0.5, "the test image isn't skewed: {}", before);
+        assert!(after < before / 2., "the fullest 30 degrees went from {} to {}", before, after);
+    }
+
+    #[test]
+    fn highlight_only_leaves_the_shadows() {
+        // the left half bright orange, the right half dark red, with oklab lightness about 80 and 35
+        let (bright, dark) = (Rgba([250, 170, 60, 255]), Rgba([110, 20, 20, 255]));
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 4, |x, _| if x < 4 { bright } else { dark }));
+        let transform = Transform { highlight_only: Some(50.), ..Transform::new(Operation::Reflect(Axis::Fixed(120.))) };
+        let result = run(&img, transform, Hooks::default()).unwrap();
+        let everything = run(&img, Transform::new(Operation::Reflect(Axis::Fixed(120.))), Hooks::default()).unwrap();
+        for (x, y, pixel) in result.enumerate_pixels() {
+            if x < 4 {
+                assert_eq!(pixel, everything.get_pixel(x, y), "a highlight wasn't reflected");
+                assert_ne!(*pixel, bright);
+            } else {
+                assert_eq!(*pixel, dark, "a shadow changed");
+            }
+        }
+    }
+
+    #[test]
+    fn hue_from_takes_the_companion_hue_and_keeps_the_value() {
+        let primary = [[200, 60, 60], [40, 90, 160], [250, 240, 100], [90, 30, 120], [180, 90, 40]];
+        let companion = [[30, 200, 40], [255, 128, 0], [60, 60, 220], [0, 200, 200], [128, 128, 128]];
+        let image = |colors: &[[u8; 3]; 5]| RgbaImage::from_fn(5, 1, |x, _| {
+            let [r, g, b] = colors[x as usize];
+            Rgba([r, g, b, 255])
+        });
+        let img = DynamicImage::ImageRgba8(image(&primary));
+        let companion: &'static RgbaImage = Box::leak(Box::new(image(&companion)));
+        let result = run(&img, Transform::new(Operation::HueFrom(companion)), Hooks::default()).unwrap();
+        let hsv = |pixel: &Rgba<u8>| rgb_to_hsv(&Rgb([pixel[0], pixel[1], pixel[2]])).0;
+        for x in 0..4 {
+            let before = hsv(&img.get_pixel(x, 0));
+            let (after, source) = (hsv(result.get_pixel(x, 0)), hsv(companion.get_pixel(x, 0)));
+            let apart = (after[0] - source[0]).rem_euclid(360.);
+            // up to the rounding to 8 bits
+            assert!(apart.min(360. - apart) < 1.5, "{}: hue {} isn't the companion's {}", x, after[0], source[0]);
+            assert!((after[2] - before[2]).abs() < 0.5, "{}: value {} isn't the primary's {}", x, after[2], before[2]);
+            assert!((after[1] - before[1]).abs() < 1., "{}: saturation {} isn't the primary's {}", x, after[1], before[1]);
+        }
+        assert_eq!(result.get_pixel(4, 0), &img.get_pixel(4, 0), "a gray companion pixel has no hue to give");
+    }
+
+    #[test]
+    fn mostly_red_suggests_taking_red_far_away() {
+        // nine in ten pixels red, the rest green
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(10, 10, |x, _| if x < 9 { Rgba([220, 30, 30, 255]) } else { Rgba([40, 200, 40, 255]) }));
+        let histogram = hue_histogram(&img, ColorSpace::Srgb, HueSpace::Hsv, DEFAULT_TOLERANCE);
+        let ((dramatic, shift), (balanced, balanced_shift)) = suggest_angles(&histogram).unwrap();
+        let red = hsv_reflect(&Hsv([0., 80., 80.]), dramatic).0[0].rem_euclid(360.);
+        assert!(red.min(360. - red) >= 150., "angle {} only takes red to hue {}", dramatic, red);
+        assert!(shift >= 150., "{}", shift);
+        assert!((balanced_shift - 90.).abs() < 10., "angle {} moves hues {} on average", balanced, balanced_shift);
+    }
+
+    #[test]
+    fn glitch_repeats_with_its_seed_and_does_nothing_at_amount_0() {
+        let img = test_image(48, 40);
+        let glitched = |amount, seed| run(&img, Transform { glitch: Some((amount, seed)), ..Transform::new(Operation::Reflect(Axis::Fixed(75.))) }, Hooks::default()).unwrap();
+        let plain = run(&img, Transform::new(Operation::Reflect(Axis::Fixed(75.))), Hooks::default()).unwrap();
+        let first = glitched(15., 42);
+        assert!(glitched(15., 42) == first, "the same seed gave another image");
+        assert!(first != plain && glitched(15., 43) != first);
+        // each row gets its own offset, only a row that happens to draw about 0 stays as it was
+        let row_changed = |img: &RgbaImage, y| (0..img.width()).any(|x| img.get_pixel(x, y) != plain.get_pixel(x, y));
+        assert!((0..40).filter(|&y| row_changed(&first, y)).count() > 20, "most rows should move");
+        assert!(glitched(0., 42) == plain, "amount 0 isn't a plain reflect");
+    }
+
+    #[test]
+    fn tolerance_decides_near_grays_and_hues_near_the_axis() {
+        // 2 steps apart, and a yellow just under a degree from the axis
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 1, |x, _| if x == 0 { Rgba([130, 128, 128, 255]) } else { Rgba([255, 251, 0, 255]) }));
+        let reflected = |tolerance| run(&img, Transform { tolerance, ..Transform::new(Operation::Reflect(Axis::Fixed(60.))) }, Hooks::default()).unwrap();
+        let tight = reflected(0.);
+        assert_eq!(tight.get_pixel(0, 0), &Rgba([128, 130, 128, 255]));
+        assert_eq!(tight.get_pixel(1, 0), &Rgba([251, 255, 0, 255]));
+        let loose = reflected(0.01);
+        assert!(loose == img.to_rgba8(), "both pixels are within 0.01 and should be kept");
+        // the measuring side skips the same grays
+        let gray = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([130, 128, 128, 255])));
+        assert!(mean_hue(&gray, ColorSpace::Srgb, HueSpace::Hsv, 0.).is_some());
+        assert_eq!(mean_hue(&gray, ColorSpace::Srgb, HueSpace::Hsv, 0.01), None);
+        assert_eq!(hue_histogram(&gray, ColorSpace::Srgb, HueSpace::Hsv, 0.01).iter().sum::<u64>(), 0);
+        assert_eq!(matching_axis(10., 12., 0.01), None);
+        assert!(matching_axis(10., 12., 0.).is_some());
+    }
+}
@@ -0,0 +1,88 @@
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::space::{decode, encode, multiply};
+
+// color blindness simulation after Viénot, Brettel and Mollon (1999): linear rgb is turned into lms cone
+// responses, the response of the missing cone is rebuilt from the other two, and the result goes back to rgb
+#[derive(Clone, Copy, PartialEq)]
+pub enum Deficiency {
+    // no long (red) cones
+    Protanopia,
+    // no medium (green) cones
+    Deuteranopia,
+    // no short (blue) cones
+    Tritanopia,
+}
+
+const RGB_TO_LMS: [[f32; 3]; 3] = [
+    [17.8824, 43.5161, 4.11935],
+    [3.45565, 27.1554, 3.86714],
+    [0.0299566, 0.184309, 1.46709],
+];
+
+const LMS_TO_RGB: [[f32; 3]; 3] = [
+    [0.080_944_45, -0.130_504_4, 0.116_721_07],
+    [-0.010_248_534, 0.054_019_33, -0.113_614_71],
+    [-0.000_365_296_93, -0.004_121_614_7, 0.693_511_4],
+];
+
+impl Deficiency {
+    pub fn parse(name: &str) -> Option<Deficiency> {
+        match name {
+            "protanopia" => Some(Deficiency::Protanopia),
+            "deuteranopia" => Some(Deficiency::Deuteranopia),
+            "tritanopia" => Some(Deficiency::Tritanopia),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Deficiency::Protanopia => "protanopia",
+            Deficiency::Deuteranopia => "deuteranopia",
+            Deficiency::Tritanopia => "tritanopia",
+        }
+    }
+
+    // each row replaces one cone response, the missing one is a mix of the other two
+    // picked so that white and one more color (blue, or red for tritanopia) look the same as before
+    fn projection(self) -> [[f32; 3]; 3] {
+        match self {
+            Deficiency::Protanopia => [[0., 2.02344, -2.52581], [0., 1., 0.], [0., 0., 1.]],
+            Deficiency::Deuteranopia => [[1., 0., 0.], [0.494207, 0., 1.24827], [0., 0., 1.]],
+            Deficiency::Tritanopia => [[1., 0., 0.], [0., 1., 0.], [-0.395913, 0.801109, 0.]],
+        }
+    }
+}
+
+pub fn simulate_color(pixel: Rgb<u8>, deficiency: Deficiency) -> Rgb<u8> {
+    let linear = pixel.0.map(|c| decode(c as f32 / 255.));
+    let lms = multiply(&deficiency.projection(), multiply(&RGB_TO_LMS, linear));
+    Rgb(multiply(&LMS_TO_RGB, lms).map(|c| (encode(c.clamp(0., 1.)) * 255.).round() as u8))
+}
+
+// the whole image as it would look with the deficiency, alpha is kept
+pub fn simulate(img: &RgbaImage, deficiency: Deficiency) -> RgbaImage {
+    RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        let pixel = img.get_pixel(x, y);
+        let [r, g, b] = simulate_color(Rgb([pixel[0], pixel[1], pixel[2]]), deficiency).0;
+        Rgba([r, g, b, pixel[3]])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deuteranopia_known_pixels() {
+        // worked out with the paper's linear rgb matrix [0.29275 0.70725 0], [0.29275 0.70725 0],
+        // [-0.02234 0.02234 1], red and green fall onto the same yellow line
+        assert_eq!(simulate_color(Rgb([255, 0, 0]), Deficiency::Deuteranopia), Rgb([147, 147, 0]));
+        assert_eq!(simulate_color(Rgb([0, 255, 0]), Deficiency::Deuteranopia), Rgb([219, 219, 41]));
+        assert_eq!(simulate_color(Rgb([200, 120, 40]), Deficiency::Deuteranopia), Rgb([149, 149, 29]));
+        // white and blue are kept by construction
+        assert_eq!(simulate_color(Rgb([255, 255, 255]), Deficiency::Deuteranopia), Rgb([255, 255, 255]));
+        assert_eq!(simulate_color(Rgb([0, 0, 255]), Deficiency::Deuteranopia), Rgb([0, 0, 255]));
+    }
+}
@@ -0,0 +1,36 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageFormat};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::transform::{self, Axis, Operation, Transform};
+
+// entry point for the browser, takes the bytes of an image file in any format the image crate reads
+// and gives back a png of it reflected along angle, errors turn into javascript exceptions
+#[wasm_bindgen]
+pub fn reflect_bytes(input: &[u8], angle: f32) -> Result<Vec<u8>, String> {
+    let img = image::load_from_memory(input).map_err(|e| format!("Failed to open image: {}", e))?;
+    let new_img = transform::reflect_image(&img, Transform::new(Operation::Reflect(Axis::Fixed(angle))));
+    let mut png = Cursor::new(vec![]);
+    DynamicImage::ImageRgba8(new_img)
+        .write_to(&mut png, ImageFormat::Png)
+        .map_err(|e| format!("Failed to save image: {}", e))?;
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn reflect_bytes_matches_reflect_image() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(3, 2, |x, y| Rgba([(x * 90) as u8, (y * 200) as u8, 40, (255 - x * 60) as u8])));
+        let mut input = Cursor::new(vec![]);
+        img.write_to(&mut input, ImageFormat::Png).unwrap();
+        let output = reflect_bytes(input.get_ref(), 120.).unwrap();
+        let reflected = image::load_from_memory_with_format(&output, ImageFormat::Png).unwrap().to_rgba8();
+        assert_eq!(reflected, transform::reflect_image(&img, Transform::new(Operation::Reflect(Axis::Fixed(120.)))));
+        assert!(reflect_bytes(b"not an image", 120.).is_err());
+    }
+}